@@ -11,6 +11,8 @@ pub struct Model {
     pub media_id: Uuid,
     pub playback_position: i64,
     pub last_played_at: DateTime,
+    pub watched: bool,
+    pub duration: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -11,12 +11,27 @@ pub struct Model {
     pub path: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub include_patterns: Option<Json>,
+    pub exclude_patterns: Option<Json>,
+    pub min_file_size: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::album::Entity")]
+    Album,
     #[sea_orm(has_many = "super::media::Entity")]
     Media,
+    #[sea_orm(has_many = "super::scan_job::Entity")]
+    ScanJob,
+    #[sea_orm(has_many = "super::share_link::Entity")]
+    ShareLink,
+}
+
+impl Related<super::album::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Album.def()
+    }
 }
 
 impl Related<super::media::Entity> for Entity {
@@ -25,4 +40,16 @@ impl Related<super::media::Entity> for Entity {
     }
 }
 
+impl Related<super::scan_job::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ScanJob.def()
+    }
+}
+
+impl Related<super::share_link::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ShareLink.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
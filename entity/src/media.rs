@@ -2,7 +2,7 @@
 
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
 #[sea_orm(table_name = "media")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -13,6 +13,19 @@ pub struct Model {
     pub media_type: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    pub file_size: Option<i64>,
+    pub file_mtime: Option<i64>,
+    pub file_inode: Option<i64>,
+    pub fingerprint: Option<String>,
+    #[sea_orm(column_type = "Double", nullable)]
+    pub duration: Option<f64>,
+    pub bitrate: Option<i32>,
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -29,6 +42,12 @@ pub enum Relation {
     Library,
     #[sea_orm(has_many = "super::media_metadata::Entity")]
     MediaMetadata,
+    #[sea_orm(has_many = "super::peer_media::Entity")]
+    PeerMedia,
+    #[sea_orm(has_many = "super::share_link::Entity")]
+    ShareLink,
+    #[sea_orm(has_many = "super::track::Entity")]
+    Track,
     #[sea_orm(has_many = "super::user_activity::Entity")]
     UserActivity,
 }
@@ -51,6 +70,24 @@ impl Related<super::media_metadata::Entity> for Entity {
     }
 }
 
+impl Related<super::peer_media::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PeerMedia.def()
+    }
+}
+
+impl Related<super::share_link::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ShareLink.def()
+    }
+}
+
+impl Related<super::track::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Track.def()
+    }
+}
+
 impl Related<super::user_activity::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserActivity.def()
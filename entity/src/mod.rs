@@ -2,10 +2,17 @@
 
 pub mod prelude;
 
+pub mod album;
+pub mod artist;
 pub mod history;
 pub mod library;
 pub mod media;
 pub mod media_metadata;
 pub mod peer;
+pub mod peer_media;
 pub mod profile;
+pub mod scan_job;
+pub mod share_link;
+pub mod share_link_access;
+pub mod track;
 pub mod user_activity;
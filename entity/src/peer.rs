@@ -12,9 +12,34 @@ pub struct Model {
     pub ip_address: String,
     pub port: i32,
     pub last_seen: DateTime,
+    pub profile_id: Option<Uuid>,
+    pub is_local: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::peer_media::Entity")]
+    PeerMedia,
+    #[sea_orm(
+        belongs_to = "super::profile::Entity",
+        from = "Column::ProfileId",
+        to = "super::profile::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Profile,
+}
+
+impl Related<super::peer_media::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PeerMedia.def()
+    }
+}
+
+impl Related<super::profile::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Profile.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
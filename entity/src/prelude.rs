@@ -1,9 +1,16 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.10
 
+pub use super::album::Entity as Album;
+pub use super::artist::Entity as Artist;
 pub use super::history::Entity as History;
 pub use super::library::Entity as Library;
 pub use super::media::Entity as Media;
 pub use super::media_metadata::Entity as MediaMetadata;
 pub use super::peer::Entity as Peer;
+pub use super::peer_media::Entity as PeerMedia;
 pub use super::profile::Entity as Profile;
+pub use super::scan_job::Entity as ScanJob;
+pub use super::share_link::Entity as ShareLink;
+pub use super::share_link_access::Entity as ShareLinkAccess;
+pub use super::track::Entity as Track;
 pub use super::user_activity::Entity as UserActivity;
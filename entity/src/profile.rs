@@ -16,6 +16,7 @@ pub struct Model {
     pub avatar: Option<String>,
     pub pin: Option<String>,
     pub use_pin: Option<bool>,
+    pub role: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
 }
@@ -32,6 +33,10 @@ pub enum Relation {
         on_delete = "SetNull"
     )]
     SelfRef,
+    #[sea_orm(has_many = "super::peer::Entity")]
+    Peer,
+    #[sea_orm(has_many = "super::share_link::Entity")]
+    ShareLink,
     #[sea_orm(has_many = "super::user_activity::Entity")]
     UserActivity,
 }
@@ -42,6 +47,18 @@ impl Related<super::history::Entity> for Entity {
     }
 }
 
+impl Related<super::peer::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Peer.def()
+    }
+}
+
+impl Related<super::share_link::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ShareLink.def()
+    }
+}
+
 impl Related<super::user_activity::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserActivity.def()
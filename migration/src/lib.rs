@@ -8,6 +8,20 @@ pub mod m20250426_152510_create_media_metadata;
 pub mod m20250426_152523_create_user_activities;
 mod m20250426_153350_create_foreign_keys_migration;
 mod m20250426_155425_create_peers_table;
+mod m20261019_090000_add_watch_state_to_history;
+mod m20261019_100000_create_share_links_table;
+mod m20261019_110000_add_role_to_profile;
+mod m20261019_120000_create_peer_media_table;
+mod m20261019_130000_add_is_local_to_peer;
+mod m20261019_140000_add_file_state_to_media;
+mod m20261019_150000_add_fingerprint_to_media;
+mod m20261019_160000_make_media_paths_relative;
+mod m20261019_170000_create_scan_job_table;
+mod m20261019_180000_add_scan_rules_to_library;
+mod m20261019_190000_add_technical_attributes_to_media;
+mod m20261019_200000_create_music_tables;
+mod m20261019_210000_add_unique_index_to_history;
+mod m20261019_220000_add_folder_to_album_unique_index;
 
 pub struct Migrator;
 
@@ -23,6 +37,20 @@ impl MigratorTrait for Migrator {
             Box::new(m20250426_152523_create_user_activities::Migration),
             Box::new(m20250426_153350_create_foreign_keys_migration::Migration),
             Box::new(m20250426_155425_create_peers_table::Migration),
+            Box::new(m20261019_090000_add_watch_state_to_history::Migration),
+            Box::new(m20261019_100000_create_share_links_table::Migration),
+            Box::new(m20261019_110000_add_role_to_profile::Migration),
+            Box::new(m20261019_120000_create_peer_media_table::Migration),
+            Box::new(m20261019_130000_add_is_local_to_peer::Migration),
+            Box::new(m20261019_140000_add_file_state_to_media::Migration),
+            Box::new(m20261019_150000_add_fingerprint_to_media::Migration),
+            Box::new(m20261019_160000_make_media_paths_relative::Migration),
+            Box::new(m20261019_170000_create_scan_job_table::Migration),
+            Box::new(m20261019_180000_add_scan_rules_to_library::Migration),
+            Box::new(m20261019_190000_add_technical_attributes_to_media::Migration),
+            Box::new(m20261019_200000_create_music_tables::Migration),
+            Box::new(m20261019_210000_add_unique_index_to_history::Migration),
+            Box::new(m20261019_220000_add_folder_to_album_unique_index::Migration),
         ]
     }
 }
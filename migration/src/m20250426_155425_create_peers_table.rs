@@ -32,6 +32,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
+#[allow(clippy::enum_variant_names)]
 enum Peer {
     // Added for P2P
     Table,
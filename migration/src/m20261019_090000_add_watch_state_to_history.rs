@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(History::Table)
+                    .add_column(
+                        ColumnDef::new(History::Watched)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(History::Duration).big_integer().null()) // in seconds, as last reported by a client
+                    .to_owned(),
+            )
+            .await?;
+
+        // Continue-watching lists are read per profile, most recent first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-history-profile_id-last_played_at")
+                    .table(History::Table)
+                    .col(History::ProfileId)
+                    .col(History::LastPlayedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-history-profile_id-last_played_at")
+                    .table(History::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(History::Table)
+                    .drop_column(History::Watched)
+                    .drop_column(History::Duration)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum History {
+    Table,
+    ProfileId,
+    LastPlayedAt,
+    Watched,
+    Duration,
+}
@@ -0,0 +1,129 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{
+    m20250426_143220_create_profiles_table::Profile,
+    m20250426_151614_create_library_table::Library, m20250426_151715_create_media_table::Media,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ShareLink::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ShareLink::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(string(ShareLink::Token).not_null().unique_key())
+                    .col(ColumnDef::new(ShareLink::OwnerId).uuid().not_null())
+                    .col(ColumnDef::new(ShareLink::MediaId).uuid().null()) // a single media item...
+                    .col(ColumnDef::new(ShareLink::LibraryId).uuid().null()) // ...or a whole library/album
+                    .col(ColumnDef::new(ShareLink::PasswordHash).string().null())
+                    .col(ColumnDef::new(ShareLink::ExpiresAt).timestamp().null())
+                    .col(ColumnDef::new(ShareLink::MaxUses).integer().null()) // plays + downloads
+                    .col(integer(ShareLink::UseCount).not_null().default(0))
+                    .col(ColumnDef::new(ShareLink::RevokedAt).timestamp().null())
+                    .col(timestamp(ShareLink::CreatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-share_link-owner_id")
+                            .from(ShareLink::Table, ShareLink::OwnerId)
+                            .to(Profile::Table, Profile::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-share_link-media_id")
+                            .from(ShareLink::Table, ShareLink::MediaId)
+                            .to(Media::Table, Media::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-share_link-library_id")
+                            .from(ShareLink::Table, ShareLink::LibraryId)
+                            .to(Library::Table, Library::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ShareLinkAccess::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ShareLinkAccess::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ShareLinkAccess::ShareLinkId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ShareLinkAccess::MediaId).uuid().null())
+                    .col(string(ShareLinkAccess::Action).not_null()) // e.g., "view", "play", "download", "denied"
+                    .col(ColumnDef::new(ShareLinkAccess::IpAddress).string().null())
+                    .col(ColumnDef::new(ShareLinkAccess::UserAgent).string().null())
+                    .col(timestamp(ShareLinkAccess::AccessedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-share_link_access-share_link_id")
+                            .from(ShareLinkAccess::Table, ShareLinkAccess::ShareLinkId)
+                            .to(ShareLink::Table, ShareLink::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ShareLinkAccess::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ShareLink::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ShareLink {
+    Table,
+    Id,
+    Token,
+    OwnerId,
+    MediaId,
+    LibraryId,
+    PasswordHash,
+    ExpiresAt,
+    MaxUses,
+    UseCount,
+    RevokedAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ShareLinkAccess {
+    Table,
+    Id,
+    ShareLinkId,
+    MediaId,
+    Action,
+    IpAddress,
+    UserAgent,
+    AccessedAt,
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profile::Table)
+                    .add_column(
+                        ColumnDef::new(Profile::Role)
+                            .string()
+                            .not_null()
+                            .default("user"), // "admin" or "user"
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Registration only promotes the first profile of a fresh install, so
+        // an upgraded one would be left without an admin
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                UPDATE profile SET role = 'admin'
+                WHERE id = (
+                    SELECT id FROM profile
+                    WHERE parent_id IS NULL
+                    ORDER BY created_at, id
+                    LIMIT 1
+                )
+                "#,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profile::Table)
+                    .drop_column(Profile::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Profile {
+    Table,
+    Role,
+}
@@ -0,0 +1,125 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{
+    m20250426_143220_create_profiles_table::Profile, m20250426_151715_create_media_table::Media,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Peer::Table)
+                    .add_column(ColumnDef::new(Peer::ProfileId).uuid().null()) // who the peer signed in as
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("fk-peer-profile_id")
+                            .from_tbl(Peer::Table)
+                            .from_col(Peer::ProfileId)
+                            .to_tbl(Profile::Table)
+                            .to_col(Profile::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(PeerMedia::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PeerMedia::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PeerMedia::PeerId).uuid().not_null())
+                    .col(ColumnDef::new(PeerMedia::MediaId).uuid().not_null())
+                    .col(boolean(PeerMedia::HasFullFile).not_null().default(false))
+                    .col(ColumnDef::new(PeerMedia::ChunkSize).integer().null()) // in bytes
+                    .col(ColumnDef::new(PeerMedia::Chunks).json().null()) // indices held when partial
+                    .col(timestamp(PeerMedia::UpdatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-peer_media-peer_id")
+                            .from(PeerMedia::Table, PeerMedia::PeerId)
+                            .to(Peer::Table, Peer::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-peer_media-media_id")
+                            .from(PeerMedia::Table, PeerMedia::MediaId)
+                            .to(Media::Table, Media::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-peer_media-peer_id-media_id")
+                    .table(PeerMedia::Table)
+                    .col(PeerMedia::PeerId)
+                    .col(PeerMedia::MediaId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Lookups go from a media item to the peers holding it
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-peer_media-media_id")
+                    .table(PeerMedia::Table)
+                    .col(PeerMedia::MediaId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PeerMedia::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Peer::Table)
+                    .drop_foreign_key(Alias::new("fk-peer-profile_id"))
+                    .drop_column(Peer::ProfileId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+#[allow(clippy::enum_variant_names)]
+enum Peer {
+    Table,
+    Id,
+    ProfileId,
+}
+
+#[derive(DeriveIden)]
+enum PeerMedia {
+    Table,
+    Id,
+    PeerId,
+    MediaId,
+    HasFullFile,
+    ChunkSize,
+    Chunks,
+    UpdatedAt,
+}
@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Null until the next scan fills them in, which forces one full re-read
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .add_column(ColumnDef::new(Media::FileSize).big_integer().null()) // in bytes
+                    .add_column(ColumnDef::new(Media::FileMtime).big_integer().null()) // unix nanoseconds
+                    .add_column(ColumnDef::new(Media::FileInode).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Scans load a whole library at once
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-library_id-file_path")
+                    .table(Media::Table)
+                    .col(Media::LibraryId)
+                    .col(Media::FilePath)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-media-library_id-file_path")
+                    .table(Media::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .drop_column(Media::FileSize)
+                    .drop_column(Media::FileMtime)
+                    .drop_column(Media::FileInode)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Media {
+    Table,
+    LibraryId,
+    FilePath,
+    FileSize,
+    FileMtime,
+    FileInode,
+}
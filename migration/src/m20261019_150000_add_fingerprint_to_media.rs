@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .add_column(ColumnDef::new(Media::Fingerprint).string().null()) // hash of size, head and tail
+                    .to_owned(),
+            )
+            .await?;
+
+        // Moved files are looked up by either identity
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-library_id-file_inode")
+                    .table(Media::Table)
+                    .col(Media::LibraryId)
+                    .col(Media::FileInode)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-media-library_id-fingerprint")
+                    .table(Media::Table)
+                    .col(Media::LibraryId)
+                    .col(Media::Fingerprint)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-media-library_id-fingerprint")
+                    .table(Media::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-media-library_id-file_inode")
+                    .table(Media::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .drop_column(Media::Fingerprint)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Media {
+    Table,
+    LibraryId,
+    FileInode,
+    Fingerprint,
+}
@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Strips the library root (and the separator after it) from absolute
+        // paths; rows outside their library root are left absolute
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                UPDATE media
+                SET file_path = ltrim(substr(media.file_path, length(library.path) + 1), '/')
+                FROM library
+                WHERE media.library_id = library.id
+                  AND length(library.path) > 0
+                  AND left(media.file_path, length(library.path)) = library.path
+                  AND (
+                    right(library.path, 1) = '/'
+                    OR substr(media.file_path, length(library.path) + 1, 1) = '/'
+                  )
+                "#,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                UPDATE media
+                SET file_path = rtrim(library.path, '/') || '/' || media.file_path
+                FROM library
+                WHERE media.library_id = library.id
+                  AND left(media.file_path, 1) <> '/'
+                "#,
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250426_151614_create_library_table::Library;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScanJob::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ScanJob::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(ScanJob::LibraryId).uuid().not_null())
+                    .col(string(ScanJob::Status).not_null()) // running, completed, failed or cancelled
+                    .col(string(ScanJob::Trigger).not_null()) // manual, watch or periodic
+                    .col(integer(ScanJob::FilesSeen).not_null().default(0))
+                    .col(integer(ScanJob::FilesProcessed).not_null().default(0))
+                    .col(integer(ScanJob::Added).not_null().default(0))
+                    .col(integer(ScanJob::Updated).not_null().default(0))
+                    .col(integer(ScanJob::Moved).not_null().default(0))
+                    .col(integer(ScanJob::Removed).not_null().default(0))
+                    .col(integer(ScanJob::Unchanged).not_null().default(0))
+                    .col(integer(ScanJob::Errors).not_null().default(0))
+                    .col(ColumnDef::new(ScanJob::ErrorDetails).json().null()) // [{path, message}]
+                    .col(ColumnDef::new(ScanJob::Message).string().null())
+                    .col(timestamp(ScanJob::StartedAt).default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(ScanJob::FinishedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-scan_job-library_id")
+                            .from(ScanJob::Table, ScanJob::LibraryId)
+                            .to(Library::Table, Library::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // History is listed newest first, per library
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-scan_job-library_id-started_at")
+                    .table(ScanJob::Table)
+                    .col(ScanJob::LibraryId)
+                    .col(ScanJob::StartedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScanJob::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ScanJob {
+    Table,
+    Id,
+    LibraryId,
+    Status,
+    Trigger,
+    FilesSeen,
+    FilesProcessed,
+    Added,
+    Updated,
+    Moved,
+    Removed,
+    Unchanged,
+    Errors,
+    ErrorDetails,
+    Message,
+    StartedAt,
+    FinishedAt,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Library::Table)
+                    .add_column(ColumnDef::new(Library::IncludePatterns).json().null()) // globs; none means everything
+                    .add_column(ColumnDef::new(Library::ExcludePatterns).json().null()) // globs
+                    .add_column(ColumnDef::new(Library::MinFileSize).big_integer().null()) // in bytes
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Library::Table)
+                    .drop_column(Library::IncludePatterns)
+                    .drop_column(Library::ExcludePatterns)
+                    .drop_column(Library::MinFileSize)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Library {
+    Table,
+    IncludePatterns,
+    ExcludePatterns,
+    MinFileSize,
+}
@@ -0,0 +1,88 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Null until the next scan probes the file; rows without a mime type are re-read once
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .add_column(ColumnDef::new(Media::Duration).double().null()) // in seconds
+                    .add_column(ColumnDef::new(Media::Bitrate).integer().null()) // in kbps
+                    .add_column(ColumnDef::new(Media::Container).string().null())
+                    .add_column(ColumnDef::new(Media::VideoCodec).string().null())
+                    .add_column(ColumnDef::new(Media::AudioCodec).string().null())
+                    .add_column(ColumnDef::new(Media::Width).integer().null())
+                    .add_column(ColumnDef::new(Media::Height).integer().null())
+                    .add_column(ColumnDef::new(Media::MimeType).string().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // The most common sorts and range filters of library listings
+        for (name, column) in [
+            ("idx-media-library_id-duration", Media::Duration),
+            ("idx-media-library_id-file_size", Media::FileSize),
+            ("idx-media-library_id-height", Media::Height),
+        ] {
+            manager
+                .create_index(
+                    Index::create()
+                        .name(name)
+                        .table(Media::Table)
+                        .col(Media::LibraryId)
+                        .col(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for name in [
+            "idx-media-library_id-duration",
+            "idx-media-library_id-file_size",
+            "idx-media-library_id-height",
+        ] {
+            manager
+                .drop_index(Index::drop().name(name).table(Media::Table).to_owned())
+                .await?;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Media::Table)
+                    .drop_column(Media::Duration)
+                    .drop_column(Media::Bitrate)
+                    .drop_column(Media::Container)
+                    .drop_column(Media::VideoCodec)
+                    .drop_column(Media::AudioCodec)
+                    .drop_column(Media::Width)
+                    .drop_column(Media::Height)
+                    .drop_column(Media::MimeType)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Media {
+    Table,
+    LibraryId,
+    FileSize,
+    Duration,
+    Bitrate,
+    Container,
+    VideoCodec,
+    AudioCodec,
+    Width,
+    Height,
+    MimeType,
+}
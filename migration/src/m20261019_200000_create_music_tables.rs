@@ -0,0 +1,214 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::{
+    m20250426_151614_create_library_table::Library, m20250426_151715_create_media_table::Media,
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Artist::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Artist::Id).uuid().not_null().primary_key())
+                    .col(string(Artist::Name).not_null())
+                    .col(string(Artist::NameKey).not_null().unique_key()) // case- and space-folded name
+                    .col(ColumnDef::new(Artist::MusicbrainzId).string().null())
+                    .col(timestamp(Artist::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp(Artist::UpdatedAt).default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Album::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Album::Id).uuid().not_null().primary_key())
+                    .col(ColumnDef::new(Album::LibraryId).uuid().not_null())
+                    .col(ColumnDef::new(Album::ArtistId).uuid().not_null()) // the album artist
+                    .col(string(Album::Title).not_null())
+                    .col(string(Album::TitleKey).not_null())
+                    .col(ColumnDef::new(Album::Year).integer().null())
+                    .col(ColumnDef::new(Album::DiscTotal).integer().null())
+                    .col(boolean(Album::IsCompilation).not_null().default(false))
+                    .col(ColumnDef::new(Album::MusicbrainzId).string().null()) // release ID
+                    .col(ColumnDef::new(Album::Folder).string().null()) // set when grouped by folder
+                    .col(ColumnDef::new(Album::CoverHash).string().null())
+                    .col(timestamp(Album::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp(Album::UpdatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-album-library_id")
+                            .from(Album::Table, Album::LibraryId)
+                            .to(Library::Table, Library::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-album-artist_id")
+                            .from(Album::Table, Album::ArtistId)
+                            .to(Artist::Table, Artist::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // One album per title and album artist, so concurrent scans can't duplicate it
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-album-library_id-artist_id-title_key")
+                    .table(Album::Table)
+                    .col(Album::LibraryId)
+                    .col(Album::ArtistId)
+                    .col(Album::TitleKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-album-library_id-folder")
+                    .table(Album::Table)
+                    .col(Album::LibraryId)
+                    .col(Album::Folder)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(Track::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Track::Id).uuid().not_null().primary_key())
+                    .col(
+                        ColumnDef::new(Track::MediaId)
+                            .uuid()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Track::AlbumId).uuid().not_null())
+                    .col(ColumnDef::new(Track::ArtistId).uuid().not_null()) // the track artist
+                    .col(string(Track::Title).not_null())
+                    .col(integer(Track::DiscNumber).not_null().default(1))
+                    .col(ColumnDef::new(Track::TrackNumber).integer().null())
+                    .col(ColumnDef::new(Track::Duration).double().null()) // in seconds
+                    .col(ColumnDef::new(Track::MusicbrainzId).string().null()) // recording ID
+                    .col(timestamp(Track::CreatedAt).default(Expr::current_timestamp()))
+                    .col(timestamp(Track::UpdatedAt).default(Expr::current_timestamp()))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-track-media_id")
+                            .from(Track::Table, Track::MediaId)
+                            .to(Media::Table, Media::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-track-album_id")
+                            .from(Track::Table, Track::AlbumId)
+                            .to(Album::Table, Album::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-track-artist_id")
+                            .from(Track::Table, Track::ArtistId)
+                            .to(Artist::Table, Artist::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Albums are listed in disc and track order
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-track-album_id-disc_number-track_number")
+                    .table(Track::Table)
+                    .col(Track::AlbumId)
+                    .col(Track::DiscNumber)
+                    .col(Track::TrackNumber)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-track-artist_id")
+                    .table(Track::Table)
+                    .col(Track::ArtistId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Track::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Album::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Artist::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Artist {
+    Table,
+    Id,
+    Name,
+    NameKey,
+    MusicbrainzId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    Id,
+    LibraryId,
+    ArtistId,
+    Title,
+    TitleKey,
+    Year,
+    DiscTotal,
+    IsCompilation,
+    MusicbrainzId,
+    Folder,
+    CoverHash,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(DeriveIden)]
+enum Track {
+    Table,
+    Id,
+    MediaId,
+    AlbumId,
+    ArtistId,
+    Title,
+    DiscNumber,
+    TrackNumber,
+    Duration,
+    MusicbrainzId,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Concurrent reports may have inserted the same item twice; keep the
+        // most recently played row of each
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"
+                DELETE FROM history
+                WHERE id IN (
+                    SELECT id FROM (
+                        SELECT id, row_number() OVER (
+                            PARTITION BY profile_id, media_id
+                            ORDER BY last_played_at DESC, id
+                        ) AS rank
+                        FROM history
+                    ) ranked
+                    WHERE rank > 1
+                )
+                "#,
+            )
+            .await?;
+
+        // One row per profile and item, so progress can be upserted
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-history-profile_id-media_id")
+                    .table(History::Table)
+                    .col(History::ProfileId)
+                    .col(History::MediaId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-history-profile_id-media_id")
+                    .table(History::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum History {
+    Table,
+    ProfileId,
+    MediaId,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-album-library_id-artist_id-title_key")
+                    .table(Album::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Untagged albums are grouped by folder, so two folders of "Unknown
+        // Album" by one artist stay apart; tagged albums have no folder and
+        // NULLs must collide for them to stay one per title and album artist
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-album-library_id-artist_id-title_key-folder")
+                    .table(Album::Table)
+                    .col(Album::LibraryId)
+                    .col(Album::ArtistId)
+                    .col(Album::TitleKey)
+                    .col(Album::Folder)
+                    .unique()
+                    .nulls_not_distinct()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx-album-library_id-artist_id-title_key-folder")
+                    .table(Album::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-album-library_id-artist_id-title_key")
+                    .table(Album::Table)
+                    .col(Album::LibraryId)
+                    .col(Album::ArtistId)
+                    .col(Album::TitleKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Album {
+    Table,
+    LibraryId,
+    ArtistId,
+    TitleKey,
+    Folder,
+}
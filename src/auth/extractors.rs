@@ -0,0 +1,83 @@
+use crate::{
+    auth::{models::Claims, services::verify_jwt},
+    errors::AppError,
+    media::services::url_signer::{SignedQuery, UrlSigner},
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Query, RawPathParams, State},
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use entity::profile;
+use sea_orm::EntityTrait;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+// Extract and verify the Bearer token of protected routes
+impl FromRequestParts<AppState> for Claims {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AppError::AuthenticationError)?;
+
+        verify_jwt(State(state.clone()), token.to_string()).await
+    }
+}
+
+/// Access to a media stream, either through a Bearer token or a signed URL.
+pub struct StreamAccess {
+    pub profile_id: Uuid,
+    pub role: String,
+}
+
+impl FromRequestParts<AppState> for StreamAccess {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if parts.headers.contains_key(AUTHORIZATION) {
+            let claims = Claims::from_request_parts(parts, state).await?;
+            return Ok(StreamAccess {
+                profile_id: claims.sub,
+                role: claims.role,
+            });
+        }
+
+        // Signatures are scoped to the media item in the path
+        let media_id = RawPathParams::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|params| {
+                params
+                    .iter()
+                    .find(|(key, _)| *key == "media_id")
+                    .and_then(|(_, value)| value.parse::<Uuid>().ok())
+            })
+            .ok_or(AppError::AuthenticationError)?;
+        let Query(query) = Query::<SignedQuery>::try_from_uri(&parts.uri)
+            .map_err(|_| AppError::AuthenticationError)?;
+        let client_ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let profile_id = UrlSigner::verify(state, media_id, &query, client_ip)?;
+        // Signed URLs don't carry a role, so it's looked up for bandwidth limits
+        let role = profile::Entity::find_by_id(profile_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::AuthenticationError)?
+            .role;
+        Ok(StreamAccess { profile_id, role })
+    }
+}
@@ -10,6 +10,7 @@ pub struct Claims {
     pub aud: String,
     pub jti: String,
     pub role: String,
+    pub token_type: String, // "access" or "refresh"
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,6 +13,13 @@ use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode}
 use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, Set};
 use uuid::Uuid;
 
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+pub const ROLE_GUEST: &str = "guest"; // visitors of a share link
+
+pub const TOKEN_ACCESS: &str = "access";
+pub const TOKEN_REFRESH: &str = "refresh";
+
 pub async fn register_user(
     State(state): State<AppState>,
     req: RegisterRequest,
@@ -33,6 +40,13 @@ pub async fn register_user(
     let hashed_password = hash(&req.password, bcrypt::DEFAULT_COST)
         .map_err(|_e| AppError::InternalServerError("Password hashing failed".into()))?;
 
+    // The first account on a fresh server administers it
+    let is_first_user = ProfileEntity::find()
+        .one(db)
+        .await
+        .map_err(AppError::DatabaseError)?
+        .is_none();
+
     let new_user = ActiveModel {
         id: Set(Uuid::new_v4()),
         parent_id: Set(None), // For initial user, no parent
@@ -42,7 +56,8 @@ pub async fn register_user(
         name: Set(req.name.clone()),
         avatar: Set(None),
         pin: Set(req.pin.clone()),
-        use_pin: Set(req.use_pin.clone()), //handle the Option
+        use_pin: Set(req.use_pin), //handle the Option
+        role: Set(if is_first_user { ROLE_ADMIN } else { ROLE_USER }.to_string()),
         created_at: Set(Utc::now().naive_utc()),
         updated_at: Set(Utc::now().naive_utc()),
     };
@@ -81,7 +96,8 @@ pub async fn login_user(State(state): State<AppState>, req: LoginRequest) -> Res
         iss: "smartinis_media_server".to_string(), // Replace with your issuer
         aud: "user".to_string(),                   // Define your audience
         jti: Uuid::new_v4().to_string(),
-        role: "user".to_string(), // Get from DB
+        role: user.role.clone(),
+        token_type: TOKEN_ACCESS.to_string(),
     };
 
     let refresh_claims = Claims {
@@ -91,7 +107,8 @@ pub async fn login_user(State(state): State<AppState>, req: LoginRequest) -> Res
         iss: "smartinis_media_server".to_string(),
         aud: "user".to_string(),
         jti: Uuid::new_v4().to_string(),
-        role: "user".to_string(), //  Get from DB
+        role: user.role.clone(),
+        token_type: TOKEN_REFRESH.to_string(),
     };
 
     let encoding_key = EncodingKey::from_secret(state.jwt_secret.as_bytes());
@@ -113,11 +130,37 @@ pub async fn login_user(State(state): State<AppState>, req: LoginRequest) -> Res
 
 pub async fn verify_jwt(State(state): State<AppState>, token: String) -> Result<Claims> {
     let decoding_key = DecodingKey::from_secret(state.jwt_secret.as_bytes());
-    let validation = Validation::default();
+    let mut validation = Validation::default();
+    validation.set_audience(&["user"]);
+    validation.set_issuer(&["smartinis_media_server"]);
 
-    decode::<Claims>(&token, &decoding_key, &validation)
+    let claims = decode::<Claims>(&token, &decoding_key, &validation)
         .map(|decoded| decoded.claims)
-        .map_err(|_| AppError::AuthenticationError)
+        .map_err(|_| AppError::AuthenticationError)?;
+    // Refresh tokens live for a month, so they must not open the API themselves
+    if claims.token_type != TOKEN_ACCESS {
+        return Err(AppError::AuthenticationError);
+    }
+    Ok(claims)
+}
+
+// A profile may act for itself and for the child profiles it owns
+pub async fn authorize_profile(
+    State(state): State<AppState>,
+    claims: &Claims,
+    profile_id: Uuid,
+) -> Result<ProfileModel> {
+    let profile = ProfileEntity::find_by_id(profile_id)
+        .one(&state.conn)
+        .await
+        .map_err(AppError::DatabaseError)?
+        .ok_or(AppError::NotFound)?;
+
+    if profile.id != claims.sub && profile.parent_id != Some(claims.sub) {
+        return Err(AppError::AuthorizationError);
+    }
+
+    Ok(profile)
 }
 
 pub async fn forgot_password(State(state): State<AppState>, email: String) -> Result<()> {
@@ -355,6 +398,7 @@ pub async fn add_child_profile(
         avatar: Set(None),
         pin: Set(None),
         use_pin: Set(Some(false)),
+        role: Set(ROLE_USER.to_string()),
         created_at: Set(Utc::now().naive_utc()),
         updated_at: Set(Utc::now().naive_utc()),
     };
@@ -366,3 +410,37 @@ pub async fn add_child_profile(
 
     Ok(child_profile)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(state: &AppState, token_type: &str) -> String {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: Uuid::new_v4(),
+            exp: (now + Duration::hours(1)).timestamp(),
+            iat: now.timestamp(),
+            iss: "smartinis_media_server".to_string(),
+            aud: "user".to_string(),
+            jti: Uuid::new_v4().to_string(),
+            role: ROLE_USER.to_string(),
+            token_type: token_type.to_string(),
+        };
+        let key = EncodingKey::from_secret(state.jwt_secret.as_bytes());
+        encode(&Header::default(), &claims, &key).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_access_tokens_are_accepted() {
+        let state = AppState::for_tests();
+        let access = token(&state, TOKEN_ACCESS);
+        assert!(verify_jwt(State(state.clone()), access).await.is_ok());
+
+        let refresh = token(&state, TOKEN_REFRESH);
+        assert!(matches!(
+            verify_jwt(State(state), refresh).await,
+            Err(AppError::AuthenticationError)
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use axum::http::StatusCode;
+use axum::http::{StatusCode, header};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -29,6 +29,21 @@ pub enum AppError {
 
     #[error("Bad request: {0}")]
     BadRequest(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Gone: {0}")]
+    Gone(String),
+
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("Range not satisfiable for {0} bytes")]
+    RangeNotSatisfiable(u64),
 }
 
 impl axum::response::IntoResponse for AppError {
@@ -61,6 +76,18 @@ impl axum::response::IntoResponse for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Internal server error: {}", message),
             ),
+            AppError::ServiceUnavailable(message) => (StatusCode::SERVICE_UNAVAILABLE, message),
+            AppError::Conflict(message) => (StatusCode::CONFLICT, message),
+            AppError::Gone(message) => (StatusCode::GONE, message),
+            AppError::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
+            AppError::RangeNotSatisfiable(size) => {
+                return (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                    "Requested range not satisfiable".to_string(),
+                )
+                    .into_response();
+            }
         };
 
         (status, body).into_response()
@@ -1,10 +1,19 @@
 pub mod auth;
 pub mod errors;
 pub mod media;
+pub mod music;
+pub mod parties;
 pub mod routes;
+pub mod shares;
 pub mod state;
 
-use crate::state::AppState;
+use crate::{
+    media::services::{
+        discovery::Discovery, peers::PeerRegistry, scan_jobs::ScanJobService,
+        watcher::LibraryWatcher,
+    },
+    state::AppState,
+};
 use axum::Router;
 use axum::response::Html;
 use axum::routing::get;
@@ -33,41 +42,51 @@ async fn main() {
         .expect("Failed to connect to database");
 
     // Initialize the application state
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let media_root = env::var("MEDIA_ROOT").unwrap_or_else(|_| "/media".to_string());
-    let allow_register =
-        env::var("ALLOW_REGISTER").unwrap_or_else(|_| "false".to_string()) == "true";
-    let allow_anonymous =
-        env::var("ALLOW_ANONYMOUS").unwrap_or_else(|_| "false".to_string()) == "true";
-    let allow_peer_to_peer =
-        env::var("ALLOW_PEER_TO_PEER").unwrap_or_else(|_| "false".to_string()) == "true";
-    let state = AppState {
-        conn: db,
-        jwt_secret,
-        media_root,
-        allow_register,
-        allow_anonymous,
-        allow_peer_to_peer,
-    };
+    env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let state = AppState::new(db);
+    PeerRegistry::spawn_reaper(state.clone());
+    if let Err(e) = ScanJobService::recover(&state).await {
+        tracing::warn!("failed to close interrupted scan jobs: {}", e);
+    }
+    LibraryWatcher::spawn_all(state.clone());
 
     // Initialize the routes
     let auth_routes = routes::auth_routes(state.clone());
+    let media_routes = routes::media_routes(state.clone());
+    let share_routes = routes::share_routes(state.clone());
+    let music_routes = routes::music_routes(state.clone());
+    let party_routes = routes::party_routes(state.clone());
+    let public_share_routes = routes::public_share_routes(state.clone());
+    let admin_routes = routes::admin_routes(state.clone());
 
     // Create the main router with the /v1 prefix for the auth routes
     let app = Router::new()
         .route("/", get(index))
-        .nest("/v1/auth", auth_routes);
+        .nest("/v1/auth", auth_routes)
+        .nest("/v1/media", media_routes)
+        .nest("/v1/shares", share_routes)
+        .nest("/v1/music", music_routes)
+        .nest("/v1/parties", party_routes)
+        .nest("/v1/share", public_share_routes)
+        .nest("/v1/admin", admin_routes);
 
-    // Define the server address
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000)); // Listen on 0.0.0.0:3000
+    // Define the server address; use 0.0.0.0:3000 to be reachable from the LAN
+    let addr = env::var("BIND_ADDR")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(SocketAddr::from(([127, 0, 0, 1], 3000)));
 
     println!("Listening on {}", addr);
+    Discovery::spawn(state, addr);
 
     //Use the axum::serve with a listener
     let listener = TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 async fn index() -> Html<&'static str> {
@@ -10,12 +10,29 @@ pub enum MediaError {
     StreamingError(String),
     #[error("P2P connection failed")]
     P2PConnectionFailed,
+    #[error("Peer-to-peer streaming is disabled on this server")]
+    P2PDisabled,
+    #[error("All transcoder slots are busy")]
+    TranscoderBusy,
+    #[error("Transcoding failed: {0}")]
+    TranscodeFailed(String),
+    #[error("Concurrent stream limit of {0} reached for this account")]
+    StreamLimitReached(usize),
+    #[error("The stream session has ended")]
+    SessionEnded,
 }
 
 impl From<MediaError> for crate::errors::AppError {
     fn from(error: MediaError) -> Self {
         match error {
             MediaError::NotFound => crate::errors::AppError::NotFound,
+            MediaError::TranscoderBusy | MediaError::P2PDisabled => {
+                crate::errors::AppError::ServiceUnavailable(error.to_string())
+            }
+            MediaError::StreamLimitReached(_) => {
+                crate::errors::AppError::TooManyRequests(error.to_string())
+            }
+            MediaError::SessionEnded => crate::errors::AppError::Gone(error.to_string()),
             _ => crate::errors::AppError::InternalServerError(error.to_string()),
         }
     }
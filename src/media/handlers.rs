@@ -0,0 +1,655 @@
+use crate::{
+    auth::{
+        extractors::StreamAccess,
+        models::Claims,
+        services::{ROLE_ADMIN, authorize_profile, verify_jwt},
+    },
+    errors::{AppError, Result},
+    media::{
+        errors::MediaError,
+        models::{
+            LanDiscoveryResponse, LanPeer, LibraryMediaItem, LibraryMediaQuery, ScanJobQuery,
+            ScanJobResponse, StreamRequest,
+        },
+        services::{
+            catalog::MediaCatalog,
+            chunks::ChunkStore,
+            filters::LibraryRules,
+            http_fallback::HttpStreamer,
+            metadata::MetadataStore,
+            p2p::P2PSignaling,
+            paths::MediaPaths,
+            peers::PeerRegistry,
+            progress::ProgressTracker,
+            scan_jobs::{ScanJobService, ScanTrigger},
+            streamer::MediaStreamer,
+            swarm::SwarmStreamer,
+            throttle::StreamContext,
+            transcoder::{TranscodeContainer, TranscodeOptions},
+        },
+    },
+    state::AppState,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode, header},
+    response::{
+        IntoResponse, Json,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::StreamExt;
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+// Handler for choosing how a device should play a media item
+pub async fn stream_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    claims: Claims,
+    Json(req): Json<StreamRequest>,
+) -> Result<impl IntoResponse> {
+    let response = MediaStreamer::prepare_stream(state, claims, req, Some(addr.ip())).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(response)).into_response())
+}
+
+// Handler for direct play of the original file, with range support for seeking
+pub async fn stream_file_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    access: StreamAccess,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let session = state
+        .sessions
+        .live(query.session_id, access.profile_id, media_id)
+        .ok_or(MediaError::SessionEnded)?;
+    let context = StreamContext {
+        session_id: Some(session.id),
+        profile_id: access.profile_id,
+        media_id,
+        role: access.role,
+        client_ip: Some(addr.ip()),
+    };
+    let file = HttpStreamer::stream_file(state, media_id, range, context).await?;
+    Ok::<_, AppError>(file.into_response())
+}
+
+// Handler for direct play assembled from peers and the server, in order
+pub async fn stream_swarm_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    access: StreamAccess,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let session = state
+        .sessions
+        .live(query.session_id, access.profile_id, media_id)
+        .ok_or(MediaError::SessionEnded)?;
+    let context = StreamContext {
+        session_id: Some(session.id),
+        profile_id: access.profile_id,
+        media_id,
+        role: access.role,
+        client_ip: Some(addr.ip()),
+    };
+    let file = SwarmStreamer::stream(state, media_id, range, context).await?;
+    Ok::<_, AppError>(file.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct FileQuery {
+    pub session_id: Uuid, // the live session the stream belongs to
+}
+
+#[derive(Deserialize)]
+pub struct HeartbeatRequest {
+    pub position: f64,         // in seconds
+    pub duration: Option<f64>, // in seconds
+    pub bitrate: Option<u32>,  // in kbps
+    #[serde(default)]
+    pub paused: bool,
+    // Received since the last heartbeat by clients fetching chunks themselves
+    #[serde(default)]
+    pub peer_bytes: u64,
+    #[serde(default)]
+    pub origin_bytes: u64,
+}
+
+// Handler for client heartbeats that keep a stream session alive
+pub async fn session_heartbeat_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(session_id): Path<Uuid>,
+    Json(payload): Json<HeartbeatRequest>,
+) -> Result<impl IntoResponse> {
+    let session = state
+        .sessions
+        .heartbeat(
+            session_id,
+            claims.sub,
+            payload.position,
+            payload.bitrate,
+            payload.paused,
+        )
+        .ok_or(AppError::NotFound)?;
+    state
+        .sessions
+        .record_transfer(session.id, true, payload.peer_bytes);
+    state
+        .sessions
+        .record_transfer(session.id, false, payload.origin_bytes);
+    ProgressTracker::report(
+        &state,
+        session.profile_id,
+        session.media_id,
+        session.position,
+        payload.duration,
+    )
+    .await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(session)).into_response())
+}
+
+// Handler for ending a stream session
+pub async fn stop_session_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(session_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let session = state
+        .sessions
+        .stop(session_id, claims.sub)
+        .ok_or(AppError::NotFound)?;
+    state.transcoder.cancel(session_id);
+    ProgressTracker::report(
+        &state,
+        session.profile_id,
+        session.media_id,
+        session.position,
+        None,
+    )
+    .await?;
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            Json(json!({ "message": "Stream session stopped." })),
+        )
+            .into_response(),
+    )
+}
+
+// Handler for listing the active streams of the caller's household
+pub async fn list_sessions_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<impl IntoResponse> {
+    let profile = authorize_profile(State(state.clone()), &claims, claims.sub).await?;
+    let sessions = state
+        .sessions
+        .list_for_account(profile.parent_id.unwrap_or(profile.id));
+    Ok::<_, AppError>((StatusCode::OK, Json(sessions)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ProgressRequest {
+    pub profile_id: Option<Uuid>, // defaults to the caller
+    pub position: f64,            // in seconds
+    pub duration: Option<f64>,    // in seconds
+}
+
+// Handler for periodic playback progress reports
+pub async fn report_progress_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<Uuid>,
+    Json(payload): Json<ProgressRequest>,
+) -> Result<impl IntoResponse> {
+    let profile_id = payload.profile_id.unwrap_or(claims.sub);
+    authorize_profile(State(state.clone()), &claims, profile_id).await?;
+    let entry = ProgressTracker::report(
+        &state,
+        profile_id,
+        media_id,
+        payload.position,
+        payload.duration,
+    )
+    .await?;
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            Json(json!({
+                "media_id": entry.media_id,
+                "playback_position": entry.playback_position,
+                "duration": entry.duration,
+                "watched": entry.watched,
+            })),
+        )
+            .into_response(),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ProfileQuery {
+    pub profile_id: Option<Uuid>, // defaults to the caller
+    pub limit: Option<u64>,
+}
+
+// Handler for the "continue watching" row of a profile
+pub async fn continue_watching_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(query): Query<ProfileQuery>,
+) -> Result<impl IntoResponse> {
+    let profile_id = query.profile_id.unwrap_or(claims.sub);
+    authorize_profile(State(state.clone()), &claims, profile_id).await?;
+    let items =
+        ProgressTracker::continue_watching(&state, profile_id, query.limit.unwrap_or(20).min(100))
+            .await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(items)).into_response())
+}
+
+// Handler for marking a media item as watched
+pub async fn mark_watched_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<ProfileQuery>,
+) -> Result<impl IntoResponse> {
+    set_watched(state, claims, media_id, query.profile_id, true).await
+}
+
+// Handler for marking a media item as unwatched
+pub async fn mark_unwatched_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<ProfileQuery>,
+) -> Result<impl IntoResponse> {
+    set_watched(state, claims, media_id, query.profile_id, false).await
+}
+
+async fn set_watched(
+    state: AppState,
+    claims: Claims,
+    media_id: Uuid,
+    profile_id: Option<Uuid>,
+    watched: bool,
+) -> Result<impl IntoResponse> {
+    let profile_id = profile_id.unwrap_or(claims.sub);
+    authorize_profile(State(state.clone()), &claims, profile_id).await?;
+    let entry = ProgressTracker::set_watched(&state, profile_id, media_id, watched).await?;
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            Json(json!({ "media_id": entry.media_id, "watched": entry.watched })),
+        )
+            .into_response(),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct TranscodeQuery {
+    pub session_id: Option<Uuid>,
+    pub offset: Option<f64>, // in seconds
+    pub container: Option<TranscodeContainer>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub video_bitrate: Option<u32>, // in kbps
+    pub audio_bitrate: Option<u32>, // in kbps
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+// Handler for on-the-fly transcoding; requesting the same session again with
+// a new offset restarts the transcoder there (seeking)
+pub async fn transcode_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    access: StreamAccess,
+    Path(media_id): Path<Uuid>,
+    Query(query): Query<TranscodeQuery>,
+) -> Result<impl IntoResponse> {
+    let media = entity::media::Entity::find_by_id(media_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let defaults = TranscodeOptions::default();
+    let options = TranscodeOptions {
+        container: query.container.unwrap_or(defaults.container),
+        video_codec: query.video_codec.unwrap_or(defaults.video_codec),
+        audio_codec: query.audio_codec.unwrap_or(defaults.audio_codec),
+        video_bitrate: query.video_bitrate,
+        audio_bitrate: query.audio_bitrate,
+        max_width: query.max_width,
+        max_height: query.max_height,
+    };
+    let session_id = query.session_id.unwrap_or_else(Uuid::new_v4);
+    let offset = query.offset.unwrap_or(0.0).max(0.0);
+
+    let stream = state
+        .transcoder
+        .start(
+            session_id,
+            access.profile_id,
+            media.id,
+            MediaPaths::resolve(&state, &media).await?,
+            media.duration,
+            offset,
+            &options,
+        )
+        .await?;
+    let stream = state.bandwidth.throttle(
+        stream,
+        StreamContext {
+            session_id: Some(session_id),
+            profile_id: access.profile_id,
+            media_id: media.id,
+            role: access.role,
+            client_ip: Some(addr.ip()),
+        },
+    );
+
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            [
+                (
+                    header::CONTENT_TYPE,
+                    options.container.content_type().to_string(),
+                ),
+                (header::CACHE_CONTROL, "no-store".to_string()),
+                (
+                    header::HeaderName::from_static("x-transcode-session"),
+                    session_id.to_string(),
+                ),
+            ],
+            Body::from_stream(stream),
+        )
+            .into_response(),
+    )
+}
+
+// Handler for the chunk hash list that P2P clients verify peer data against
+pub async fn chunk_manifest_handler(
+    State(state): State<AppState>,
+    _access: StreamAccess,
+    Path(media_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let manifest = ChunkStore::load(&state, media_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok::<_, AppError>((StatusCode::OK, Json(manifest)).into_response())
+}
+
+// Handler for listing a library's items, sorted and filtered by duration,
+// bitrate, size, dimensions, codecs, container or mime type
+pub async fn list_library_media_handler(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Path(library_id): Path<Uuid>,
+    Query(query): Query<LibraryMediaQuery>,
+) -> Result<impl IntoResponse> {
+    let items: Vec<LibraryMediaItem> = MediaCatalog::list(&state, library_id, query)
+        .await?
+        .into_iter()
+        .map(LibraryMediaItem::from)
+        .collect();
+    Ok::<_, AppError>((StatusCode::OK, Json(items)).into_response())
+}
+
+// Handler for the cover art embedded in an audio file's tags
+pub async fn cover_art_handler(
+    State(state): State<AppState>,
+    _access: StreamAccess,
+    Path(media_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let cover = MetadataStore::load_cover(&state, media_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, cover.content_type()),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+                // Covers are named by hash, so a changed cover gets a new ETag
+                (header::ETAG, format!("\"{}\"", cover.hash)),
+                (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+            ],
+            cover.data,
+        )
+            .into_response(),
+    )
+}
+
+// Handler for polling the progress of a transcode session
+pub async fn transcode_progress_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(session_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let owner = state
+        .transcoder
+        .owner(session_id)
+        .ok_or(AppError::NotFound)?;
+    authorize_profile(State(state.clone()), &claims, owner).await?;
+    let progress = state
+        .transcoder
+        .progress(session_id)
+        .ok_or(AppError::NotFound)?;
+    Ok::<_, AppError>((StatusCode::OK, Json(progress)).into_response())
+}
+
+// Handler for stopping a transcode session
+pub async fn stop_transcode_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(session_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let owner = state
+        .transcoder
+        .owner(session_id)
+        .ok_or(AppError::NotFound)?;
+    authorize_profile(State(state.clone()), &claims, owner).await?;
+    state.transcoder.cancel(session_id);
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            Json(json!({ "message": "Transcode session stopped." })),
+        )
+            .into_response(),
+    )
+}
+
+// Handler for the current throughput of every open stream, for admins
+pub async fn throughput_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    Ok::<_, AppError>((StatusCode::OK, Json(state.bandwidth.report())).into_response())
+}
+
+// Handler for starting a full scan of a library in the background
+pub async fn start_scan_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(library_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let progress = ScanJobService::start(state, library_id, ScanTrigger::Manual).await?;
+    Ok::<_, AppError>((StatusCode::ACCEPTED, Json(progress)).into_response())
+}
+
+// Handler for a library's include/exclude patterns and minimum file size
+pub async fn get_library_rules_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(library_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let rules = LibraryRules::load(&state, library_id).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(rules)).into_response())
+}
+
+// Handler for replacing a library's scan rules; they apply from the next scan
+pub async fn update_library_rules_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(library_id): Path<Uuid>,
+    Json(rules): Json<LibraryRules>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let rules = LibraryRules::save(&state, library_id, rules).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(rules)).into_response())
+}
+
+// Handler for the scan history, newest first
+pub async fn list_scans_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(query): Query<ScanJobQuery>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let limit = query.limit.unwrap_or(50).min(500);
+    let scans = ScanJobService::list(&state, query.library_id, limit).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(scans)).into_response())
+}
+
+// Handler for one scan job, with live progress while it runs
+pub async fn get_scan_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let (progress, error_details) = ScanJobService::get(&state, job_id).await?;
+    Ok::<_, AppError>(
+        (
+            StatusCode::OK,
+            Json(ScanJobResponse {
+                progress,
+                error_details,
+            }),
+        )
+            .into_response(),
+    )
+}
+
+// Handler for cancelling a running scan
+pub async fn cancel_scan_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    ScanJobService::cancel(&state, job_id).await?;
+    Ok::<_, AppError>(StatusCode::ACCEPTED.into_response())
+}
+
+// Handler for following a scan's progress as server-sent events
+pub async fn scan_events_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(job_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    if claims.role != ROLE_ADMIN {
+        return Err(AppError::AuthorizationError);
+    }
+    let events = ScanJobService::events(&state, job_id)
+        .await?
+        .map(|progress| Event::default().event("progress").json_data(progress));
+    Ok::<_, AppError>(
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response(),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct SignalQuery {
+    pub token: Option<String>, // browsers can't set headers on WebSocket requests
+}
+
+// Handler for the WebRTC signaling WebSocket
+pub async fn p2p_signal_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<SignalQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse> {
+    if !state.allow_peer_to_peer {
+        return Err(MediaError::P2PDisabled.into());
+    }
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or(query.token)
+        .ok_or(AppError::AuthenticationError)?;
+    let claims = verify_jwt(State(state.clone()), token).await?;
+    if state.p2p.is_banned(claims.sub) {
+        return Err(AppError::AuthorizationError);
+    }
+
+    Ok::<_, AppError>(
+        ws.on_upgrade(move |socket| P2PSignaling::run(state, socket, claims, addr))
+            .into_response(),
+    )
+}
+
+// Handler for listing servers and peers discovered on the LAN
+pub async fn lan_discovery_handler(
+    State(state): State<AppState>,
+    _claims: Claims,
+) -> Result<impl IntoResponse> {
+    let peers = PeerRegistry::lan_peers(&state)
+        .await?
+        .into_iter()
+        .map(|peer| LanPeer {
+            peer_id: peer.peer_id,
+            ip_address: peer.ip_address,
+            port: peer.port as u16,
+            last_seen: peer.last_seen,
+        })
+        .collect();
+    let response = LanDiscoveryResponse {
+        servers: state.lan.servers(),
+        peers,
+    };
+    Ok::<_, AppError>((StatusCode::OK, Json(response)).into_response())
+}
@@ -1,7 +1,17 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use strum_macros::*;
 use uuid::Uuid;
 
+use crate::media::services::{
+    chunks::ChunkManifest,
+    discovery::LanServer,
+    ice::IceServer,
+    probe::ProbeResult,
+    scan_jobs::{ScanFileError, ScanProgress},
+    tags::MusicTags,
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MediaFile {
     pub id: Uuid,
@@ -13,9 +23,13 @@ pub struct MediaFile {
     pub duration: Option<f64>,      // in seconds
     pub bitrate: Option<u32>,       // in kbps
     pub resolution: Option<String>, // e.g., "1920x1080"
+    #[serde(skip)]
+    pub probe: Option<ProbeResult>, // container details, stored in `media_metadata`
+    #[serde(skip)]
+    pub tags: Option<MusicTags>, // audio only, stored in `media_metadata`
 }
 
-#[derive(Debug, Serialize, Deserialize, Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString)]
 pub enum MediaType {
     Video,
     Audio,
@@ -26,18 +40,64 @@ pub enum MediaType {
 pub struct StreamRequest {
     pub media_id: Uuid,
     pub profile_id: Uuid,
-    pub seek_position: Option<f64>, // in seconds
+    pub seek_position: Option<f64>, // in seconds, defaults to the saved resume position
     pub prefer_p2p: bool,
+    pub device_profile: Option<DeviceProfile>, // browser defaults when omitted
+}
+
+/// What a client device can play natively.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub name: Option<String>,
+    pub containers: Vec<String>,   // e.g., ["mp4", "webm"]
+    pub video_codecs: Vec<String>, // e.g., ["h264", "vp9"]
+    pub audio_codecs: Vec<String>, // e.g., ["aac", "opus"]
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_bitrate: Option<u32>,      // in kbps
+    pub subtitle_formats: Vec<String>, // e.g., ["vtt", "srt"]
+}
+
+impl Default for DeviceProfile {
+    // Roughly what every current desktop browser plays in a <video> element
+    fn default() -> Self {
+        let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        DeviceProfile {
+            name: None,
+            containers: list(&["mp4", "webm", "mp3", "ogg", "wav", "flac", "aac"]),
+            video_codecs: list(&["h264", "vp8", "vp9", "av1"]),
+            audio_codecs: list(&["aac", "mp3", "opus", "vorbis", "flac", "pcm"]),
+            max_width: None,
+            max_height: None,
+            max_bitrate: None,
+            subtitle_formats: list(&["vtt"]),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StreamResponse {
+    pub session_id: Uuid,
     pub stream_type: StreamType,
     pub url: String,
+    pub start_position: Option<f64>, // in seconds; where the player should start
     pub p2p_peers: Vec<P2PPeer>,
+    pub ice_servers: Vec<IceServer>, // for connecting to `p2p_peers`
+    pub chunk_manifest: Option<ChunkManifest>, // for verifying chunks fetched from `p2p_peers`
+    pub relay_url: Option<String>, // the swarm relayed by the server, for clients that can't join it
+    pub play_method: PlayMethod,
+    pub decision_reasons: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayMethod {
+    DirectPlay, // the original file as-is
+    Remux,      // original streams copied into a container the device understands
+    Transcode,  // re-encoded by ffmpeg
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StreamType {
     P2P,
     HTTP,
@@ -50,4 +110,121 @@ pub struct P2PPeer {
     pub ip_address: String,
     pub port: u16,
     pub has_full_file: bool,
+    pub chunk_size: Option<u32>, // in bytes
+    pub chunks: Vec<u32>,        // indices held when not the full file
+    pub is_local: bool,          // on the server's LAN
+}
+
+#[derive(Debug, Serialize)]
+pub struct LanPeer {
+    pub peer_id: String,
+    pub ip_address: String,
+    pub port: u16,
+    pub last_seen: NaiveDateTime,
+}
+
+/// What mDNS found on the server's LAN.
+#[derive(Debug, Serialize)]
+pub struct LanDiscoveryResponse {
+    pub servers: Vec<LanServer>,
+    pub peers: Vec<LanPeer>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScanJobQuery {
+    pub library_id: Option<Uuid>,
+    pub limit: Option<u64>,
+}
+
+/// A scan job with the files it couldn't process.
+#[derive(Debug, Serialize)]
+pub struct ScanJobResponse {
+    #[serde(flatten)]
+    pub progress: ScanProgress,
+    pub error_details: Vec<ScanFileError>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaSort {
+    #[default]
+    Title,
+    Duration,
+    Bitrate,
+    FileSize,
+    Width,
+    Height,
+    AddedAt,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Sorting and filtering a library's items by their technical attributes;
+/// items that haven't been probed yet never match a range filter.
+#[derive(Debug, Default, Deserialize)]
+pub struct LibraryMediaQuery {
+    #[serde(default)]
+    pub sort: MediaSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub media_type: Option<String>, // "Video", "Audio" or "Image"
+    pub container: Option<String>,  // e.g., "mkv"
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub mime_type: Option<String>, // e.g., "video/mp4", or "video/*"
+    pub min_duration: Option<f64>, // in seconds
+    pub max_duration: Option<f64>,
+    pub min_bitrate: Option<i32>, // in kbps
+    pub max_bitrate: Option<i32>,
+    pub min_width: Option<i32>,
+    pub max_width: Option<i32>,
+    pub min_height: Option<i32>,
+    pub max_height: Option<i32>,
+    pub min_file_size: Option<i64>, // in bytes
+    pub max_file_size: Option<i64>,
+    pub offset: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryMediaItem {
+    pub id: Uuid,
+    pub title: String,
+    pub media_type: String,
+    pub file_size: Option<i64>, // in bytes
+    pub duration: Option<f64>,  // in seconds
+    pub bitrate: Option<i32>,   // in kbps
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub mime_type: Option<String>,
+    pub added_at: NaiveDateTime,
+}
+
+impl From<entity::media::Model> for LibraryMediaItem {
+    fn from(media: entity::media::Model) -> Self {
+        LibraryMediaItem {
+            id: media.id,
+            title: media.title,
+            media_type: media.media_type,
+            file_size: media.file_size,
+            duration: media.duration,
+            bitrate: media.bitrate,
+            container: media.container,
+            video_codec: media.video_codec,
+            audio_codec: media.audio_codec,
+            width: media.width,
+            height: media.height,
+            mime_type: media.mime_type,
+            added_at: media.created_at,
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use entity::{library, media};
+use sea_orm::{
+    ColumnTrait, EntityTrait, Order, QueryFilter, QueryOrder, QuerySelect, Select,
+    sea_query::NullOrdering,
+};
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    media::models::{LibraryMediaQuery, MediaSort, SortOrder},
+    state::AppState,
+};
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+
+/// Lists library items by the technical attributes the scanner stores.
+pub struct MediaCatalog;
+
+impl MediaCatalog {
+    pub async fn list(
+        state: &AppState,
+        library_id: Uuid,
+        query: LibraryMediaQuery,
+    ) -> Result<Vec<media::Model>, AppError> {
+        library::Entity::find_by_id(library_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+
+        let select = Self::filter(
+            media::Entity::find().filter(media::Column::LibraryId.eq(library_id)),
+            &query,
+        );
+        let column = match query.sort {
+            MediaSort::Title => media::Column::Title,
+            MediaSort::Duration => media::Column::Duration,
+            MediaSort::Bitrate => media::Column::Bitrate,
+            MediaSort::FileSize => media::Column::FileSize,
+            MediaSort::Width => media::Column::Width,
+            MediaSort::Height => media::Column::Height,
+            MediaSort::AddedAt => media::Column::CreatedAt,
+        };
+        let order = match query.order {
+            SortOrder::Asc => Order::Asc,
+            SortOrder::Desc => Order::Desc,
+        };
+
+        // Items not probed yet go last either way; the id keeps pages stable
+        Ok(select
+            .order_by_with_nulls(column, order, NullOrdering::Last)
+            .order_by_asc(media::Column::Id)
+            .offset(query.offset.unwrap_or(0))
+            .limit(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT))
+            .all(&state.conn)
+            .await?)
+    }
+
+    fn filter(
+        mut select: Select<media::Entity>,
+        query: &LibraryMediaQuery,
+    ) -> Select<media::Entity> {
+        for (column, value) in [
+            (media::Column::MediaType, &query.media_type),
+            (media::Column::Container, &query.container),
+            (media::Column::VideoCodec, &query.video_codec),
+            (media::Column::AudioCodec, &query.audio_codec),
+        ] {
+            if let Some(value) = value {
+                select = select.filter(column.eq(value.as_str()));
+            }
+        }
+        if let Some(mime_type) = &query.mime_type {
+            select = match mime_type.strip_suffix("/*") {
+                Some(kind) => {
+                    select.filter(media::Column::MimeType.starts_with(format!("{}/", kind)))
+                }
+                None => select.filter(media::Column::MimeType.eq(mime_type.as_str())),
+            };
+        }
+
+        if let Some(min) = query.min_duration {
+            select = select.filter(media::Column::Duration.gte(min));
+        }
+        if let Some(max) = query.max_duration {
+            select = select.filter(media::Column::Duration.lte(max));
+        }
+        for (column, min, max) in [
+            (media::Column::Bitrate, query.min_bitrate, query.max_bitrate),
+            (media::Column::Width, query.min_width, query.max_width),
+            (media::Column::Height, query.min_height, query.max_height),
+        ] {
+            if let Some(min) = min {
+                select = select.filter(column.gte(min));
+            }
+            if let Some(max) = max {
+                select = select.filter(column.lte(max));
+            }
+        }
+        if let Some(min) = query.min_file_size {
+            select = select.filter(media::Column::FileSize.gte(min));
+        }
+        if let Some(max) = query.max_file_size {
+            select = select.filter(media::Column::FileSize.lte(max));
+        }
+        select
+    }
+}
@@ -0,0 +1,247 @@
+use entity::media_metadata;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+};
+use uuid::Uuid;
+
+use crate::{errors::AppError, media::services::metadata::MetadataStore, state::AppState};
+
+pub const CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+const FINGERPRINT_SAMPLE: u64 = 64 * 1024; // bytes read from each end of the file
+const MANIFEST_KEY: &str = "chunks";
+
+/// BLAKE3 hashes of fixed-size chunks of a media file, so data received from
+/// untrusted peers can be checked one chunk at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub algorithm: String, // always "blake3" for now
+    pub chunk_size: u32,   // in bytes; the last chunk may be shorter
+    pub size: u64,         // of the whole file, in bytes
+    pub root: String,      // hash of the concatenated chunk hashes
+    pub hashes: Vec<String>,
+}
+
+impl ChunkManifest {
+    /// Hashes a file on the blocking pool.
+    pub async fn compute(path: PathBuf, chunk_size: u32) -> std::io::Result<Self> {
+        tokio::task::spawn_blocking(move || Self::compute_blocking(&path, chunk_size))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+
+    fn compute_blocking(path: &std::path::Path, chunk_size: u32) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut buffer = vec![0; chunk_size as usize];
+        let mut hashes = Vec::new();
+        let mut root = blake3::Hasher::new();
+
+        loop {
+            // read() may return short counts, so fill the whole chunk first
+            let mut filled = 0;
+            while filled < buffer.len() {
+                match file.read(&mut buffer[filled..])? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            if filled == 0 {
+                break;
+            }
+            let hash = blake3::hash(&buffer[..filled]);
+            root.update(hash.as_bytes());
+            hashes.push(hash.to_hex().to_string());
+            if filled < buffer.len() {
+                break;
+            }
+        }
+
+        Ok(ChunkManifest {
+            algorithm: "blake3".to_string(),
+            chunk_size,
+            size,
+            root: root.finalize().to_hex().to_string(),
+            hashes,
+        })
+    }
+
+    /// Byte offset and length of a chunk.
+    pub fn chunk_range(&self, index: u32) -> Option<(u64, u64)> {
+        if index as usize >= self.hashes.len() {
+            return None;
+        }
+        let offset = index as u64 * self.chunk_size as u64;
+        let length = (self.size - offset).min(self.chunk_size as u64);
+        Some((offset, length))
+    }
+
+    pub fn verify(&self, index: u32, data: &[u8]) -> bool {
+        self.hashes
+            .get(index as usize)
+            .is_some_and(|expected| blake3::hash(data).to_hex().as_str() == expected)
+    }
+}
+
+/// A cheap content identity: BLAKE3 of the size plus the first and last 64 KiB.
+///
+/// Survives renames and moves across filesystems, where inodes don't.
+pub async fn fingerprint(path: PathBuf) -> std::io::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&size.to_le_bytes());
+
+        let mut buffer = Vec::with_capacity(FINGERPRINT_SAMPLE as usize);
+        file.by_ref()
+            .take(FINGERPRINT_SAMPLE)
+            .read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        if size > FINGERPRINT_SAMPLE {
+            buffer.clear();
+            // Small files are hashed whole rather than twice over
+            let tail = (size - FINGERPRINT_SAMPLE).max(FINGERPRINT_SAMPLE);
+            file.seek(SeekFrom::Start(tail))?;
+            file.read_to_end(&mut buffer)?;
+            hasher.update(&buffer);
+        }
+        Ok(hasher.finalize().to_hex().to_string())
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Reads and writes chunk manifests under the `chunks` key of `media_metadata`.
+pub struct ChunkStore;
+
+impl ChunkStore {
+    pub async fn load(state: &AppState, media_id: Uuid) -> Result<Option<ChunkManifest>, AppError> {
+        Ok(Self::find(state, media_id)
+            .await?
+            .and_then(|m| m.metadata)
+            .and_then(|mut m| m.get_mut(MANIFEST_KEY).map(Value::take))
+            .and_then(|manifest| serde_json::from_value(manifest).ok()))
+    }
+
+    pub async fn store(
+        state: &AppState,
+        media_id: Uuid,
+        manifest: &ChunkManifest,
+    ) -> Result<(), AppError> {
+        let manifest = serde_json::to_value(manifest)
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        let mut fields = Map::new();
+        fields.insert(MANIFEST_KEY.to_string(), manifest);
+        MetadataStore::merge(state, media_id, fields).await
+    }
+
+    async fn find(
+        state: &AppState,
+        media_id: Uuid,
+    ) -> Result<Option<media_metadata::Model>, AppError> {
+        Ok(media_metadata::Entity::find()
+            .filter(media_metadata::Column::MediaId.eq(media_id))
+            .one(&state.conn)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    async fn manifest(bytes: &[u8], chunk_size: u32) -> ChunkManifest {
+        let path = fixtures::path(bytes);
+        let manifest = ChunkManifest::compute(path.clone(), chunk_size).await;
+        let _ = std::fs::remove_file(&path);
+        manifest.unwrap()
+    }
+
+    async fn fingerprint_of(bytes: &[u8]) -> String {
+        let path = fixtures::path(bytes);
+        let fingerprint = fingerprint(path.clone()).await;
+        let _ = std::fs::remove_file(&path);
+        fingerprint.unwrap()
+    }
+
+    #[tokio::test]
+    async fn hashes_each_chunk_and_the_root() {
+        let manifest = manifest(b"0123456789", 4).await;
+        assert_eq!(manifest.size, 10);
+        assert_eq!(
+            manifest.hashes,
+            [&b"0123"[..], b"4567", b"89"].map(|chunk| blake3::hash(chunk).to_hex().to_string())
+        );
+
+        let mut root = blake3::Hasher::new();
+        for chunk in [&b"0123"[..], b"4567", b"89"] {
+            root.update(blake3::hash(chunk).as_bytes());
+        }
+        assert_eq!(manifest.root, root.finalize().to_hex().as_str());
+    }
+
+    #[tokio::test]
+    async fn a_file_filling_whole_chunks_has_no_empty_tail() {
+        let manifest = manifest(b"01234567", 4).await;
+        assert_eq!(manifest.hashes.len(), 2);
+        assert_eq!(manifest.chunk_range(1), Some((4, 4)));
+        assert_eq!(manifest.chunk_range(2), None);
+
+        let empty = self::manifest(b"", 4).await;
+        assert!(empty.hashes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_last_chunk_may_be_short() {
+        let manifest = manifest(b"0123456789", 4).await;
+        assert_eq!(manifest.chunk_range(0), Some((0, 4)));
+        assert_eq!(manifest.chunk_range(2), Some((8, 2)));
+        assert_eq!(manifest.chunk_range(3), None);
+    }
+
+    #[tokio::test]
+    async fn verifies_chunks_against_their_own_hash() {
+        let manifest = manifest(b"0123456789", 4).await;
+        assert!(manifest.verify(0, b"0123"));
+        assert!(manifest.verify(2, b"89"));
+        assert!(!manifest.verify(1, b"0123")); // right data, wrong slot
+        assert!(!manifest.verify(1, b"4568"));
+        assert!(!manifest.verify(2, b"8")); // truncated
+        assert!(!manifest.verify(3, b""));
+    }
+
+    #[tokio::test]
+    async fn fingerprints_follow_the_content() {
+        let bytes = vec![7u8; 1000];
+        assert_eq!(fingerprint_of(&bytes).await, fingerprint_of(&bytes).await);
+
+        let mut changed = bytes.clone();
+        changed[500] = 8;
+        assert_ne!(fingerprint_of(&bytes).await, fingerprint_of(&changed).await);
+        assert_ne!(
+            fingerprint_of(&bytes).await,
+            fingerprint_of(&bytes[..999]).await
+        );
+    }
+
+    #[tokio::test]
+    async fn large_files_are_fingerprinted_by_their_ends() {
+        let bytes = vec![7u8; 3 * FINGERPRINT_SAMPLE as usize];
+        let original = fingerprint_of(&bytes).await;
+
+        // The middle isn't sampled
+        let mut middle = bytes.clone();
+        middle[FINGERPRINT_SAMPLE as usize + 10] = 8;
+        assert_eq!(fingerprint_of(&middle).await, original);
+
+        let mut tail = bytes.clone();
+        *tail.last_mut().unwrap() = 8;
+        assert_ne!(fingerprint_of(&tail).await, original);
+    }
+}
@@ -0,0 +1,227 @@
+use chrono::{DateTime, Utc};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+
+use crate::{media::services::peers::PeerRegistry, state::AppState};
+
+/// DNS-SD type advertised by servers, pointing at the HTTP API.
+pub const SERVER_SERVICE: &str = "_smartinis._tcp.local.";
+/// DNS-SD type advertised by clients that can serve chunks to others.
+pub const PEER_SERVICE: &str = "_smartinis-peer._tcp.local.";
+
+const API_PATH: &str = "/v1";
+const SIGNAL_PATH: &str = "/v1/media/p2p/signal";
+
+/// Another server instance found on the LAN.
+#[derive(Debug, Clone, Serialize)]
+pub struct LanServer {
+    pub name: String,
+    pub addresses: Vec<String>,
+    pub port: u16,
+    pub api_path: String,
+    pub signal_path: Option<String>, // only when the instance allows P2P
+    pub version: Option<String>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Servers currently visible over mDNS, keyed by DNS-SD full name.
+#[derive(Clone, Default)]
+pub struct LanDirectory {
+    servers: Arc<RwLock<HashMap<String, LanServer>>>,
+}
+
+impl LanDirectory {
+    pub fn servers(&self) -> Vec<LanServer> {
+        let servers = self.servers.read().unwrap();
+        let mut servers: Vec<LanServer> = servers.values().cloned().collect();
+        servers.sort_by(|a, b| a.name.cmp(&b.name));
+        servers
+    }
+
+    fn insert(&self, fullname: String, server: LanServer) {
+        self.servers.write().unwrap().insert(fullname, server);
+    }
+
+    fn remove(&self, fullname: &str) {
+        self.servers.write().unwrap().remove(fullname);
+    }
+}
+
+/// Advertises this server over mDNS/DNS-SD and browses for other servers and LAN peers.
+pub struct Discovery;
+
+impl Discovery {
+    pub fn spawn(state: AppState, addr: SocketAddr) {
+        let enabled = env::var("MDNS_ENABLED").unwrap_or_else(|_| "true".to_string()) == "true";
+        if !enabled {
+            return;
+        }
+        let daemon = match ServiceDaemon::new() {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                tracing::warn!("mDNS unavailable, LAN discovery disabled: {}", e);
+                return;
+            }
+        };
+
+        let name = env::var("SERVER_NAME").unwrap_or_else(|_| "Smartinis Media Server".to_string());
+        let own_fullname = format!("{}.{}", name, SERVER_SERVICE);
+        // Nobody on the LAN can reach a server bound to loopback
+        if addr.ip().is_loopback() {
+            tracing::info!("bound to {}, not advertising over mDNS", addr);
+        } else if let Err(e) = Self::advertise(&daemon, &state, &name, addr) {
+            tracing::warn!("failed to advertise over mDNS: {}", e);
+        }
+
+        match daemon.browse(SERVER_SERVICE) {
+            Ok(events) => {
+                let lan = state.lan.clone();
+                tokio::spawn(async move {
+                    while let Ok(event) = events.recv_async().await {
+                        match event {
+                            ServiceEvent::ServiceResolved(info)
+                                if info.get_fullname() != own_fullname =>
+                            {
+                                lan.insert(info.get_fullname().to_string(), Self::server(&info));
+                            }
+                            ServiceEvent::ServiceRemoved(_, fullname) => lan.remove(&fullname),
+                            _ => {}
+                        }
+                    }
+                });
+            }
+            Err(e) => tracing::warn!("failed to browse for servers: {}", e),
+        }
+
+        if state.allow_peer_to_peer {
+            match daemon.browse(PEER_SERVICE) {
+                Ok(events) => {
+                    tokio::spawn(Self::track_peers(state, events));
+                }
+                Err(e) => tracing::warn!("failed to browse for peers: {}", e),
+            }
+        }
+    }
+
+    fn advertise(
+        daemon: &ServiceDaemon,
+        state: &AppState,
+        name: &str,
+        addr: SocketAddr,
+    ) -> Result<(), mdns_sd::Error> {
+        let host = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>();
+        let mut properties = vec![("version", env!("CARGO_PKG_VERSION")), ("api", API_PATH)];
+        if state.allow_peer_to_peer {
+            properties.push(("signal", SIGNAL_PATH));
+        }
+
+        // A wildcard bind is reachable on every interface, so let mdns-sd fill them in
+        let service = if addr.ip().is_unspecified() {
+            ServiceInfo::new(
+                SERVER_SERVICE,
+                name,
+                &format!("{}.local.", host),
+                "",
+                addr.port(),
+                &properties[..],
+            )?
+            .enable_addr_auto()
+        } else {
+            ServiceInfo::new(
+                SERVER_SERVICE,
+                name,
+                &format!("{}.local.", host),
+                addr.ip(),
+                addr.port(),
+                &properties[..],
+            )?
+        };
+        daemon.register(service)
+    }
+
+    fn server(info: &ServiceInfo) -> LanServer {
+        LanServer {
+            name: info
+                .get_fullname()
+                .trim_end_matches(info.get_type())
+                .trim_end_matches('.')
+                .to_string(),
+            addresses: info.get_addresses().iter().map(IpAddr::to_string).collect(),
+            port: info.get_port(),
+            api_path: info
+                .get_property_val_str("api")
+                .unwrap_or(API_PATH)
+                .to_string(),
+            signal_path: info.get_property_val_str("signal").map(str::to_string),
+            version: info.get_property_val_str("version").map(str::to_string),
+            last_seen: Utc::now(),
+        }
+    }
+
+    // mDNS only resolves a peer once, so keep its row alive until it goes away
+    async fn track_peers(state: AppState, events: mdns_sd::Receiver<ServiceEvent>) {
+        let mut peers: HashMap<String, (String, SocketAddr)> = HashMap::new();
+        let period = (state.peer_timeout / 2)
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(60));
+        let mut refresh = tokio::time::interval(period);
+
+        loop {
+            tokio::select! {
+                event = events.recv_async() => {
+                    let Ok(event) = event else {
+                        break;
+                    };
+                    match event {
+                        ServiceEvent::ServiceResolved(info) => {
+                            let Some(peer) = Self::peer(&info) else {
+                                continue;
+                            };
+                            if let Err(e) = PeerRegistry::register_lan(&state, &peer.0, peer.1).await {
+                                tracing::warn!("failed to register LAN peer {}: {}", peer.0, e);
+                            }
+                            peers.insert(info.get_fullname().to_string(), peer);
+                        }
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            peers.remove(&fullname);
+                        }
+                        _ => {}
+                    }
+                }
+                _ = refresh.tick() => {
+                    for (peer_id, addr) in peers.values() {
+                        if let Err(e) = PeerRegistry::register_lan(&state, peer_id, *addr).await {
+                            tracing::warn!("failed to refresh LAN peer {}: {}", peer_id, e);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Peers advertise the id the signaling server gave them, so both sightings share a row
+    fn peer(info: &ServiceInfo) -> Option<(String, SocketAddr)> {
+        let peer_id = info.get_property_val_str("peer_id")?.to_string();
+        let ip = info
+            .get_addresses()
+            .iter()
+            .min_by_key(|ip| ip.is_ipv6())
+            .copied()?;
+        Some((peer_id, SocketAddr::new(ip, info.get_port())))
+    }
+}
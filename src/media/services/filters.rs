@@ -0,0 +1,346 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, EntityTrait, IntoActiveModel};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+use crate::{errors::AppError, media::services::metadata::MediaTypes, state::AppState};
+
+/// Per-directory ignore file, in gitignore syntax.
+pub const IGNORE_FILE: &str = ".mediaignore";
+
+// NAS metadata folders, trash, hidden files, samples and unfinished downloads
+const DEFAULT_IGNORES: &[&str] = &[
+    ".*",
+    "@eaDir/",
+    "#recycle/",
+    "#snapshot/",
+    "$RECYCLE.BIN/",
+    "System Volume Information/",
+    "lost+found/",
+    "Thumbs.db",
+    "desktop.ini",
+    "sample/",
+    "*-sample.*",
+    "*.sample.*",
+    "*_sample.*",
+    "*.part",
+    "*.partial",
+    "*.crdownload",
+    "*.download",
+    "*.!qB",
+    "*.!ut",
+    "*.tmp",
+];
+
+/// A library's own scan rules, on top of `.mediaignore` files and the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LibraryRules {
+    #[serde(default)]
+    pub include: Vec<String>, // globs relative to the root; empty means everything
+    #[serde(default)]
+    pub exclude: Vec<String>, // globs relative to the root
+    pub min_file_size: Option<u64>, // in bytes
+}
+
+impl LibraryRules {
+    pub fn of(library: &entity::library::Model) -> Self {
+        let patterns = |value: &Option<serde_json::Value>| {
+            value
+                .clone()
+                .and_then(|value| serde_json::from_value(value).ok())
+                .unwrap_or_default()
+        };
+        LibraryRules {
+            include: patterns(&library.include_patterns),
+            exclude: patterns(&library.exclude_patterns),
+            min_file_size: library.min_file_size.map(|size| size.max(0) as u64),
+        }
+    }
+
+    pub async fn load(state: &AppState, library_id: Uuid) -> Result<Self, AppError> {
+        let library = entity::library::Entity::find_by_id(library_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        Ok(Self::of(&library))
+    }
+
+    /// Replaces the library's rules; they apply from the next scan on.
+    pub async fn save(
+        state: &AppState,
+        library_id: Uuid,
+        rules: LibraryRules,
+    ) -> Result<Self, AppError> {
+        Self::glob_set(&rules.include)?;
+        Self::glob_set(&rules.exclude)?;
+        let library = entity::library::Entity::find_by_id(library_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+
+        let mut library = library.into_active_model();
+        library.include_patterns = Set(Some(serde_json::to_value(&rules.include)?));
+        library.exclude_patterns = Set(Some(serde_json::to_value(&rules.exclude)?));
+        library.min_file_size = Set(rules.min_file_size.map(|size| size as i64));
+        library.updated_at = Set(chrono::Utc::now().naive_utc());
+        let library = library.update(&state.conn).await?;
+        Ok(Self::of(&library))
+    }
+
+    fn glob_set(patterns: &[String]) -> Result<GlobSet, AppError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = Glob::new(pattern).map_err(|e| {
+                AppError::ValidationError(format!("Invalid pattern {}: {}", pattern, e))
+            })?;
+            builder.add(glob);
+        }
+        builder
+            .build()
+            .map_err(|e| AppError::ValidationError(e.to_string()))
+    }
+}
+
+/// Decides what a walk of one library indexes.
+pub struct LibraryFilter {
+    root: PathBuf,
+    media_types: MediaTypes,
+    defaults: Gitignore,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    min_file_size: u64,
+    ignore_files: HashMap<PathBuf, Option<Gitignore>>, // loaded on first use
+}
+
+impl LibraryFilter {
+    pub fn new(
+        root: PathBuf,
+        rules: &LibraryRules,
+        media_types: MediaTypes,
+    ) -> Result<Self, AppError> {
+        let mut defaults = GitignoreBuilder::new(&root);
+        defaults.case_insensitive(true).ok();
+        for line in DEFAULT_IGNORES {
+            defaults
+                .add_line(None, line)
+                .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        }
+        let defaults = defaults
+            .build()
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+        Ok(LibraryFilter {
+            root,
+            media_types,
+            defaults,
+            include: if rules.include.is_empty() {
+                None
+            } else {
+                Some(LibraryRules::glob_set(&rules.include)?)
+            },
+            exclude: LibraryRules::glob_set(&rules.exclude)?,
+            min_file_size: rules.min_file_size.unwrap_or(0),
+            ignore_files: HashMap::new(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether the walk should descend into a directory.
+    pub fn allows_dir(&mut self, path: &Path) -> bool {
+        !self.is_ignored(path, true)
+    }
+
+    /// Whether a file gets indexed.
+    pub fn allows_file(&mut self, path: &Path, size: u64) -> bool {
+        if size < self.min_file_size || self.media_types.of(path).is_none() {
+            return false;
+        }
+        if self.is_ignored(path, false) {
+            return false;
+        }
+        match (&self.include, path.strip_prefix(&self.root)) {
+            (Some(include), Ok(relative)) => include.is_match(relative),
+            _ => true,
+        }
+    }
+
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return true;
+        };
+        if relative.as_os_str().is_empty() {
+            return false;
+        }
+        if self.exclude.is_match(relative) {
+            return true;
+        }
+
+        // As in git, the closest ignore file wins, and any of them beats the defaults
+        let dirs: Vec<PathBuf> = relative
+            .parent()
+            .into_iter()
+            .flat_map(Path::ancestors)
+            .map(|dir| self.root.join(dir))
+            .collect();
+        for dir in dirs {
+            let Some(ignore) = self.ignore_file(&dir) else {
+                continue;
+            };
+            match ignore.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        self.defaults
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    fn ignore_file(&mut self, dir: &Path) -> Option<&Gitignore> {
+        self.ignore_files
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let file = dir.join(IGNORE_FILE);
+                if !file.is_file() {
+                    return None;
+                }
+                let (ignore, error) = Gitignore::new(&file);
+                if let Some(e) = error {
+                    tracing::warn!("problem reading {}: {}", file.display(), e);
+                }
+                Some(ignore)
+            })
+            .as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+
+    /// A library root in the temp directory, removed on drop.
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "filter-fixture-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Root(path)
+        }
+
+        fn ignore_file(&self, dir: &str, lines: &str) {
+            let dir = self.0.join(dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(IGNORE_FILE), lines).unwrap();
+        }
+
+        fn filter(&self, rules: &LibraryRules) -> LibraryFilter {
+            LibraryFilter::new(self.0.clone(), rules, MediaTypes::from_env()).unwrap()
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn rules(include: &[&str], exclude: &[&str]) -> LibraryRules {
+        LibraryRules {
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            min_file_size: None,
+        }
+    }
+
+    #[test]
+    fn defaults_skip_junk_and_unknown_types() {
+        let root = Root::new();
+        let mut filter = root.filter(&LibraryRules::default());
+        let file =
+            |filter: &mut LibraryFilter, path: &str| filter.allows_file(&root.0.join(path), 1);
+
+        assert!(file(&mut filter, "Films/Alien.mkv"));
+        assert!(!file(&mut filter, "Films/notes.txt"));
+        assert!(!file(&mut filter, "Films/.Alien.mkv"));
+        assert!(!file(&mut filter, "Films/Alien-sample.mkv"));
+        assert!(!file(&mut filter, "Films/Alien.mkv.part"));
+        assert!(!file(&mut filter, "Films/@eaDir/Alien.mkv"));
+        assert!(!filter.allows_dir(&root.0.join("Films/Sample")));
+        assert!(filter.allows_dir(&root.0.join("Films")));
+    }
+
+    #[test]
+    fn files_outside_the_root_are_never_indexed() {
+        let root = Root::new();
+        let mut filter = root.filter(&LibraryRules::default());
+        assert!(!filter.allows_file(Path::new("/elsewhere/Alien.mkv"), 1));
+        assert!(filter.allows_dir(&root.0));
+    }
+
+    #[test]
+    fn small_files_are_skipped() {
+        let root = Root::new();
+        let mut filter = root.filter(&LibraryRules {
+            min_file_size: Some(1000),
+            ..LibraryRules::default()
+        });
+        assert!(!filter.allows_file(&root.0.join("a.mkv"), 999));
+        assert!(filter.allows_file(&root.0.join("a.mkv"), 1000));
+    }
+
+    #[test]
+    fn library_globs_are_relative_to_the_root() {
+        let root = Root::new();
+        let mut filter = root.filter(&rules(&["Films/**"], &["**/Extras/**"]));
+
+        assert!(filter.allows_file(&root.0.join("Films/Alien.mkv"), 1));
+        assert!(!filter.allows_file(&root.0.join("Music/a.mp3"), 1));
+        assert!(!filter.allows_file(&root.0.join("Films/Extras/Trailer.mkv"), 1));
+    }
+
+    #[test]
+    fn invalid_globs_are_rejected() {
+        let root = Root::new();
+        let filter = LibraryFilter::new(
+            root.0.clone(),
+            &rules(&["[a-"], &[]),
+            MediaTypes::from_env(),
+        );
+        assert!(matches!(filter, Err(AppError::ValidationError(_))));
+    }
+
+    #[test]
+    fn the_closest_ignore_file_wins() {
+        let root = Root::new();
+        root.ignore_file("", "*.mkv\n");
+        root.ignore_file("Films/Keep", "!*.mkv\n");
+        root.ignore_file("Films/Samples", "!sample/\n");
+        let mut filter = root.filter(&LibraryRules::default());
+
+        assert!(!filter.allows_file(&root.0.join("Films/Alien.mkv"), 1));
+        assert!(filter.allows_file(&root.0.join("Films/Alien.mp4"), 1));
+        assert!(filter.allows_file(&root.0.join("Films/Keep/Alien.mkv"), 1));
+        // Ignore files can bring back what the defaults skip
+        assert!(filter.allows_dir(&root.0.join("Films/Samples/sample")));
+    }
+}
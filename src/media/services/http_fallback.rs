@@ -1,9 +1,58 @@
+use axum::{
+    body::Body,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
 use sea_orm::EntityTrait;
-use std::path::Path;
-use tokio::{fs::File, io::AsyncReadExt};
+use std::io::SeekFrom;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
-use crate::{errors::AppError, state::AppState};
+use crate::{
+    errors::AppError,
+    media::services::{paths::MediaPaths, throttle::StreamContext},
+    state::AppState,
+};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A (partial) media file ready to be sent as a throttled streaming body.
+pub struct FileStream {
+    pub body: Body,
+    pub content_type: String,
+    pub content_length: u64,
+    pub content_range: Option<String>,
+}
+
+impl IntoResponse for FileStream {
+    fn into_response(self) -> Response {
+        let status = if self.content_range.is_some() {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        let mut response = (
+            status,
+            [
+                (header::CONTENT_TYPE, self.content_type),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+                (header::CONTENT_LENGTH, self.content_length.to_string()),
+            ],
+            self.body,
+        )
+            .into_response();
+        if let Some(content_range) = self.content_range
+            && let Ok(value) = content_range.parse()
+        {
+            response.headers_mut().insert(header::CONTENT_RANGE, value);
+        }
+        response
+    }
+}
 
 pub struct HttpStreamer;
 
@@ -12,43 +61,60 @@ impl HttpStreamer {
         state: AppState,
         media_id: Uuid,
         range_header: Option<String>,
-    ) -> Result<(Vec<u8>, String, Option<String>), AppError> {
+        context: StreamContext,
+    ) -> Result<FileStream, AppError> {
         let media = entity::media::Entity::find_by_id(media_id)
             .one(&state.conn)
             .await?
             .ok_or(AppError::NotFound)?;
 
-        let path = Path::new(&media.file_path);
-        let mut file = File::open(path).await.map_err(|_| AppError::NotFound)?;
+        let path = MediaPaths::resolve(&state, &media).await?;
+        let mut file = File::open(&path).await.map_err(|_| AppError::NotFound)?;
+        let file_size = file
+            .metadata()
+            .await
+            .map_err(|e| AppError::MediaStreamingError(e.to_string()))?
+            .len();
 
         // Handle range requests for seeking
-        let (buffer, content_range) = if let Some(range) = range_header {
-            Self::handle_range_request(&mut file, &range).await?
-        } else {
-            let mut buf = Vec::new();
-            let _ = file
-                .read_to_end(&mut buf)
-                .await
-                .map_err(|_| AppError::InternalServerError);
-            (buf, None)
+        let (start, end, content_range) = match range_header {
+            Some(range) => {
+                let (start, end) = Self::parse_range(&range, file_size)?;
+                file.seek(SeekFrom::Start(start))
+                    .await
+                    .map_err(|e| AppError::MediaStreamingError(e.to_string()))?;
+                (
+                    start,
+                    end,
+                    Some(format!("bytes {}-{}/{}", start, end, file_size)),
+                )
+            }
+            None => (0, file_size.saturating_sub(1), None),
         };
+        let content_length = if file_size == 0 { 0 } else { end - start + 1 };
 
-        let content_type = match media.media_type.as_str() {
-            "video" => "video/mp4",
-            "audio" => "audio/mpeg",
-            _ => "application/octet-stream",
-        };
+        let reader = ReaderStream::with_capacity(file.take(content_length), READ_CHUNK_SIZE);
+        let body = Body::from_stream(state.bandwidth.throttle(reader, context));
+
+        let content_type = mime_guess::from_path(&path)
+            .first_or_octet_stream()
+            .to_string();
 
-        Ok((buffer, content_type.to_string(), content_range))
+        Ok(FileStream {
+            body,
+            content_type,
+            content_length,
+            content_range,
+        })
     }
 
-    async fn handle_range_request(
-        file: &mut File,
-        range_header: &str,
-    ) -> Result<(Vec<u8>, Option<String>), AppError> {
-        let _ = file;
-        let _ = range_header;
-        // Implement range request handling
-        Ok((vec![], None))
+    pub fn parse_range(range_header: &str, file_size: u64) -> Result<(u64, u64), AppError> {
+        // Only the first range is served; players never ask for multipart ranges
+        let range = http_range_header::parse_range_header(range_header)
+            .and_then(|parsed| parsed.validate(file_size))
+            .ok()
+            .and_then(|ranges| ranges.into_iter().next())
+            .ok_or(AppError::RangeNotSatisfiable(file_size))?;
+        Ok((*range.start(), *range.end()))
     }
 }
@@ -0,0 +1,169 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::env;
+use webrtc::{
+    ice_transport::ice_server::RTCIceServer, peer_connection::configuration::RTCConfiguration,
+};
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// An ICE server entry in the shape browsers pass to `RTCPeerConnection`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// STUN/TURN servers handed to clients and used by the server's own peers.
+///
+/// With `TURN_SECRET` set, TURN credentials follow the TURN REST scheme
+/// (coturn's `use-auth-secret`): the username is `<expiry>:<user>` and the
+/// password is the base64 HMAC-SHA1 of it, so they expire on their own.
+#[derive(Debug, Clone, Default)]
+pub struct IceConfig {
+    pub stun_urls: Vec<String>,
+    pub turn_urls: Vec<String>,
+    pub turn_secret: Option<String>,
+    pub turn_username: Option<String>, // static credentials, when no secret is set
+    pub turn_password: Option<String>,
+    pub turn_ttl: i64, // in seconds
+}
+
+impl IceConfig {
+    pub fn from_env() -> Self {
+        let list = |key: &str| -> Vec<String> {
+            env::var(key)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+
+        IceConfig {
+            stun_urls: list("STUN_URLS"),
+            turn_urls: list("TURN_URLS"),
+            turn_secret: env::var("TURN_SECRET").ok().filter(|s| !s.is_empty()),
+            turn_username: env::var("TURN_USERNAME").ok(),
+            turn_password: env::var("TURN_PASSWORD").ok(),
+            turn_ttl: env::var("TURN_CREDENTIAL_TTL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24 * 60 * 60),
+        }
+    }
+
+    /// ICE servers for `user`, with fresh TURN credentials when a secret is configured.
+    pub fn servers_for(&self, user: &str) -> Vec<IceServer> {
+        let mut servers = Vec::new();
+        if !self.stun_urls.is_empty() {
+            servers.push(IceServer {
+                urls: self.stun_urls.clone(),
+                username: None,
+                credential: None,
+            });
+        }
+        if !self.turn_urls.is_empty() {
+            let (username, credential) = match &self.turn_secret {
+                Some(secret) => {
+                    let (username, credential) = self.turn_credentials(secret, user);
+                    (Some(username), Some(credential))
+                }
+                None => (self.turn_username.clone(), self.turn_password.clone()),
+            };
+            servers.push(IceServer {
+                urls: self.turn_urls.clone(),
+                username,
+                credential,
+            });
+        }
+        servers
+    }
+
+    /// The same servers for the server's own `webrtc` peer connections.
+    pub fn rtc_configuration(&self) -> RTCConfiguration {
+        RTCConfiguration {
+            ice_servers: self
+                .servers_for("server")
+                .into_iter()
+                .map(|server| RTCIceServer {
+                    urls: server.urls,
+                    username: server.username.unwrap_or_default(),
+                    credential: server.credential.unwrap_or_default(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn turn_credentials(&self, secret: &str, user: &str) -> (String, String) {
+        let expires = Utc::now().timestamp() + self.turn_ttl;
+        let username = format!("{}:{}", expires, user);
+        let credential = turn_password(secret, &username);
+        (username, credential)
+    }
+}
+
+fn turn_password(secret: &str, username: &str) -> String {
+    let mut mac =
+        HmacSha1::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(username.as_bytes());
+    STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(secret: Option<&str>) -> IceConfig {
+        IceConfig {
+            stun_urls: vec!["stun:stun.example.com:3478".to_string()],
+            turn_urls: vec!["turn:turn.example.com:3478".to_string()],
+            turn_secret: secret.map(str::to_string),
+            turn_username: Some("static".to_string()),
+            turn_password: Some("hunter2".to_string()),
+            turn_ttl: 3600,
+        }
+    }
+
+    #[test]
+    fn passwords_match_the_turn_rest_reference() {
+        // echo -n "1334363123:alice" | openssl dgst -sha1 -hmac north -binary | base64
+        assert_eq!(
+            turn_password("north", "1334363123:alice"),
+            "uwQ2njr5XooD0HxVI0pLVZz0Q+k="
+        );
+    }
+
+    #[test]
+    fn usernames_carry_the_expiry_and_user() {
+        let before = Utc::now().timestamp();
+        let (username, credential) = config(Some("north")).turn_credentials("north", "alice");
+
+        let (expires, user) = username.split_once(':').unwrap();
+        let expires: i64 = expires.parse().unwrap();
+        assert_eq!(user, "alice");
+        assert!((before + 3600..=Utc::now().timestamp() + 3600).contains(&expires));
+        assert_eq!(credential, turn_password("north", &username));
+    }
+
+    #[test]
+    fn servers_use_static_credentials_without_a_secret() {
+        let servers = config(None).servers_for("alice");
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].username, None);
+        assert_eq!(servers[1].username.as_deref(), Some("static"));
+        assert_eq!(servers[1].credential.as_deref(), Some("hunter2"));
+
+        let servers = config(Some("north")).servers_for("alice");
+        assert!(servers[1].username.as_deref().unwrap().ends_with(":alice"));
+        assert_ne!(servers[1].credential.as_deref(), Some("hunter2"));
+    }
+}
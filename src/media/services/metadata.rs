@@ -1,35 +1,255 @@
-use std::path::Path;
+use chrono::Utc;
+use entity::{media, media_metadata};
+use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
+use serde_json::{Map, Value};
+use std::{collections::HashMap, env, path::Path, sync::Arc};
 use tokio::fs;
 use uuid::Uuid;
 
-use crate::media::models::{MediaFile, MediaType};
+use crate::{
+    errors::AppError,
+    media::{
+        models::{MediaFile, MediaType},
+        services::{
+            paths::MediaPaths,
+            probe::{MediaProbe, ProbeResult},
+            tags::{CoverArt, MusicTags, TagReader},
+        },
+    },
+    state::AppState,
+};
+
+const TAGS_KEY: &str = "tags";
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "avi", "mov", "wmv", "flv", "mpg", "mpeg", "vob", "ts", "m2ts",
+    "mts", "ogv", "3gp",
+];
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "wav", "aac", "m4a", "m4b", "opus", "ogg", "oga", "wma", "aiff", "aif", "ape",
+    "wv", "mka", "dsf", "alac",
+];
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "heif", "avif", "bmp", "tif", "tiff",
+];
+
+/// Which extensions are indexed, and as what.
+///
+/// `MEDIA_EXTENSIONS` adds to or overrides the built-in list, e.g.
+/// `rmvb=video,dff=audio,gif=none`; `none` stops an extension from being indexed.
+#[derive(Debug, Clone)]
+pub struct MediaTypes {
+    by_extension: Arc<HashMap<String, MediaType>>,
+}
+
+impl MediaTypes {
+    pub fn from_env() -> Self {
+        let mut by_extension = HashMap::new();
+        for (extensions, media_type) in [
+            (VIDEO_EXTENSIONS, MediaType::Video),
+            (AUDIO_EXTENSIONS, MediaType::Audio),
+            (IMAGE_EXTENSIONS, MediaType::Image),
+        ] {
+            for extension in extensions {
+                by_extension.insert(extension.to_string(), media_type);
+            }
+        }
+
+        let overrides = env::var("MEDIA_EXTENSIONS").unwrap_or_default();
+        for entry in overrides
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            let Some((extension, media_type)) = entry.split_once('=') else {
+                tracing::warn!("ignoring malformed MEDIA_EXTENSIONS entry {:?}", entry);
+                continue;
+            };
+            let extension = extension.trim().trim_start_matches('.').to_lowercase();
+            match media_type.trim().to_lowercase().as_str() {
+                "video" => by_extension.insert(extension, MediaType::Video),
+                "audio" => by_extension.insert(extension, MediaType::Audio),
+                "image" => by_extension.insert(extension, MediaType::Image),
+                "none" => by_extension.remove(&extension),
+                other => {
+                    tracing::warn!("unknown media type {:?} for .{}", other, extension);
+                    None
+                }
+            };
+        }
+
+        MediaTypes {
+            by_extension: Arc::new(by_extension),
+        }
+    }
+
+    pub fn of(&self, path: &Path) -> Option<MediaType> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        self.by_extension.get(&extension).copied()
+    }
+}
 
 pub struct MediaMetadataExtractor;
 
 impl MediaMetadataExtractor {
-    pub async fn extract(path: &Path) -> Option<MediaFile> {
+    pub async fn extract(path: &Path, media_types: &MediaTypes) -> Option<MediaFile> {
         let file_name = path.file_name()?.to_str()?.to_string();
-        let extension = path.extension()?.to_str()?.to_lowercase();
-
-        let media_type = match extension.as_str() {
-            "mp4" | "mkv" | "avi" | "mov" => MediaType::Video,
-            "mp3" | "flac" | "wav" | "aac" => MediaType::Audio,
-            "jpg" | "jpeg" | "png" | "gif" => MediaType::Image,
-            _ => return None,
-        };
+        let media_type = media_types.of(path)?;
 
         let metadata = fs::metadata(path).await.ok()?;
+        let probe = match media_type {
+            MediaType::Video | MediaType::Audio => MediaProbe::probe(path.to_path_buf()).await,
+            _ => None,
+        };
+        let tags = match media_type {
+            MediaType::Audio => TagReader::read(path.to_path_buf()).await,
+            _ => None,
+        };
 
         Some(MediaFile {
             id: Uuid::new_v4(),
             library_id: Uuid::nil(),
-            title: file_name,
+            title: tags
+                .as_ref()
+                .and_then(|tags| tags.title.clone())
+                .unwrap_or(file_name),
             file_path: path.to_str()?.to_string(),
             media_type,
             size: metadata.len(),
-            duration: None,
-            bitrate: None,
-            resolution: None,
+            duration: probe.as_ref().and_then(|probe| probe.duration),
+            bitrate: probe.as_ref().and_then(|probe| probe.bitrate),
+            resolution: probe.as_ref().and_then(ProbeResult::resolution),
+            probe,
+            tags,
         })
     }
 }
+
+/// The free-form `media_metadata` document; each writer owns its own keys.
+pub struct MetadataStore;
+
+impl MetadataStore {
+    /// Stores a probe's summary fields and tracks at the top level, where
+    /// `SourceInfo` and progress tracking read them.
+    pub async fn store_probe(
+        state: &AppState,
+        media_id: Uuid,
+        probe: &ProbeResult,
+    ) -> Result<(), AppError> {
+        match serde_json::to_value(probe) {
+            Ok(Value::Object(fields)) => Self::merge(state, media_id, fields).await,
+            Ok(_) => Ok(()),
+            Err(e) => Err(AppError::InternalServerError(e.to_string())),
+        }
+    }
+
+    /// Stores music tags under `tags`, caching the cover image on disk first.
+    pub async fn store_tags(
+        state: &AppState,
+        media_id: Uuid,
+        tags: &MusicTags,
+    ) -> Result<(), AppError> {
+        if let Some(cover) = &tags.cover {
+            Self::save_cover(state, cover).await?;
+        }
+        let tags =
+            serde_json::to_value(tags).map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        let mut fields = Map::new();
+        fields.insert(TAGS_KEY.to_string(), tags);
+        Self::merge(state, media_id, fields).await
+    }
+
+    /// A media item's cover with its image, re-read from the file itself when
+    /// the cache has been cleared since the scan.
+    pub async fn load_cover(
+        state: &AppState,
+        media_id: Uuid,
+    ) -> Result<Option<CoverArt>, AppError> {
+        let metadata = media_metadata::Entity::find()
+            .filter(media_metadata::Column::MediaId.eq(media_id))
+            .one(&state.conn)
+            .await?
+            .and_then(|m| m.metadata);
+        let cover: Option<CoverArt> = metadata
+            .as_ref()
+            .and_then(|m| m.get(TAGS_KEY))
+            .and_then(|tags| tags.get("cover"))
+            .and_then(|cover| serde_json::from_value(cover.clone()).ok());
+        let Some(mut cover) = cover else {
+            return Ok(None);
+        };
+
+        if let Ok(data) = fs::read(state.cover_art_dir.join(cover.file_name())).await {
+            cover.data = data;
+            return Ok(Some(cover));
+        }
+        let media = media::Entity::find_by_id(media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let path = MediaPaths::resolve(state, &media).await?;
+        let Some(cover) = TagReader::read(path).await.and_then(|tags| tags.cover) else {
+            return Ok(None);
+        };
+        Self::save_cover(state, &cover).await?;
+        Ok(Some(cover))
+    }
+
+    /// Writes a cover into the cache unless it's already there; every track of
+    /// an album shares one file.
+    pub async fn save_cover(state: &AppState, cover: &CoverArt) -> Result<(), AppError> {
+        let path = state.cover_art_dir.join(cover.file_name());
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+        // Written aside and renamed so a concurrent reader never sees half an image
+        let partial = path.with_extension(format!("{}.part", Uuid::new_v4()));
+        let written = async {
+            fs::create_dir_all(&state.cover_art_dir).await?;
+            fs::write(&partial, &cover.data).await?;
+            fs::rename(&partial, &path).await
+        };
+        written
+            .await
+            .map_err(|e| AppError::InternalServerError(format!("failed to cache cover: {}", e)))
+    }
+
+    /// Sets `fields` in a media item's metadata, leaving other keys alone.
+    pub async fn merge(
+        state: &AppState,
+        media_id: Uuid,
+        fields: Map<String, Value>,
+    ) -> Result<(), AppError> {
+        let now = Utc::now().naive_utc();
+        let existing = media_metadata::Entity::find()
+            .filter(media_metadata::Column::MediaId.eq(media_id))
+            .one(&state.conn)
+            .await?;
+
+        match existing {
+            Some(existing) => {
+                let mut metadata = match existing.metadata.clone() {
+                    Some(Value::Object(map)) => map,
+                    _ => Map::new(),
+                };
+                metadata.extend(fields);
+                let mut existing: media_metadata::ActiveModel = existing.into();
+                existing.metadata = Set(Some(Value::Object(metadata)));
+                existing.updated_at = Set(now);
+                existing.update(&state.conn).await?;
+            }
+            None => {
+                media_metadata::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    media_id: Set(media_id),
+                    metadata: Set(Some(Value::Object(fields))),
+                    created_at: Set(now),
+                    updated_at: Set(now),
+                }
+                .insert(&state.conn)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
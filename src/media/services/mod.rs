@@ -1,5 +1,22 @@
+pub mod catalog;
+pub mod chunks;
+pub mod discovery;
+pub mod filters;
 pub mod http_fallback;
+pub mod ice;
 pub mod metadata;
 pub mod p2p;
+pub mod paths;
+pub mod peers;
+pub mod probe;
+pub mod progress;
+pub mod scan_jobs;
 pub mod scanner;
+pub mod sessions;
 pub mod streamer;
+pub mod swarm;
+pub mod tags;
+pub mod throttle;
+pub mod transcoder;
+pub mod url_signer;
+pub mod watcher;
@@ -0,0 +1,819 @@
+use axum::extract::ws::{Message, WebSocket};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use sea_orm::EntityTrait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::SeekFrom,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use uuid::Uuid;
+use webrtc::{
+    api::{API, APIBuilder},
+    data_channel::{RTCDataChannel, data_channel_message::DataChannelMessage},
+    ice_transport::ice_candidate::RTCIceCandidateInit,
+    peer_connection::{RTCPeerConnection, sdp::session_description::RTCSessionDescription},
+};
+
+use crate::{
+    auth::models::Claims,
+    media::{
+        errors::MediaError,
+        services::{
+            chunks::{ChunkManifest, ChunkStore},
+            ice::{IceConfig, IceServer},
+            paths::MediaPaths,
+            peers::{Announcement, PeerInfo, PeerRegistry},
+            throttle::StreamContext,
+            url_signer::UrlSigner,
+        },
+    },
+    state::AppState,
+};
+
+// Browsers reject SCTP messages above 16 KiB on some stacks
+const CHUNK_MESSAGE_SIZE: usize = 16 * 1024;
+const MAX_BUFFERED_AMOUNT: usize = 1024 * 1024;
+const BUFFER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Messages exchanged over the signaling WebSocket.
+///
+/// `peer_id` names the other end: the target when sent by a client, the
+/// sender when relayed by the server. `None` means the server's own peer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignalMessage {
+    Welcome {
+        peer_id: String,
+        ice_servers: Vec<IceServer>,
+    },
+    Offer {
+        #[serde(default)]
+        peer_id: Option<String>,
+        sdp: String,
+    },
+    Answer {
+        #[serde(default)]
+        peer_id: Option<String>,
+        sdp: String,
+    },
+    Candidate {
+        #[serde(default)]
+        peer_id: Option<String>,
+        candidate: RTCIceCandidateInit,
+    },
+    /// A client holds (part of) a media item and can serve it to others.
+    Announce {
+        media_id: Uuid,
+        #[serde(default)]
+        has_full_file: bool,
+        chunk_size: Option<u32>, // in bytes
+        #[serde(default)]
+        chunks: Vec<u32>, // indices held when partial
+    },
+    Withdraw {
+        media_id: Uuid,
+    },
+    Heartbeat,
+    /// A peer sent a chunk that failed verification against the manifest.
+    Report {
+        peer_id: String,
+        media_id: Uuid,
+        index: u32,
+        session_id: Uuid, // the reporter's stream session, for the fallback URL
+    },
+    /// Where to fetch a chunk over HTTP after a peer failed to deliver it.
+    Fallback {
+        media_id: Uuid,
+        index: u32,
+        url: String,
+        offset: u64, // send as a Range header
+        length: u64,
+    },
+    PeerLeft {
+        peer_id: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Requests accepted on a data channel; `id` is chosen by the client to match replies.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkRequest {
+    /// The chunk hash list to verify chunks against.
+    Manifest { id: u64, media_id: Uuid },
+    /// One chunk of the manifest.
+    Chunk { id: u64, media_id: Uuid, index: u32 },
+    /// An arbitrary byte range, unverifiable by the client.
+    Range {
+        id: u64,
+        media_id: Uuid,
+        offset: u64,
+        length: Option<u64>, // to the end of the file when omitted
+    },
+}
+
+impl ChunkRequest {
+    fn id(&self) -> u64 {
+        match self {
+            ChunkRequest::Manifest { id, .. }
+            | ChunkRequest::Chunk { id, .. }
+            | ChunkRequest::Range { id, .. } => *id,
+        }
+    }
+}
+
+/// Text frames around the binary chunk data sent on a data channel.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChunkReply {
+    Manifest {
+        id: u64,
+        manifest: ChunkManifest,
+    },
+    Chunk {
+        id: u64,
+        media_id: Uuid,
+        offset: u64,
+        length: u64,
+        size: u64,          // of the whole file
+        index: Option<u32>, // set for manifest chunks
+        hash: Option<String>,
+    },
+    End {
+        id: u64,
+    },
+    Error {
+        id: u64,
+        message: String,
+    },
+}
+
+struct ConnectedPeer {
+    profile_id: Uuid,
+    tx: mpsc::UnboundedSender<SignalMessage>,
+    disconnect: CancellationToken,
+    connection: Option<Arc<RTCPeerConnection>>, // once the client has connected to the server
+}
+
+struct HubInner {
+    api: API,
+    peers: Mutex<HashMap<String, ConnectedPeer>>,
+    // Reporting profiles per peer, so one client alone can't get another banned
+    strikes: Mutex<HashMap<String, HashSet<Uuid>>>,
+    bans: Mutex<HashMap<Uuid, Instant>>, // profile -> banned until
+    ban_threshold: usize,
+    ban_duration: Duration,
+    ice: IceConfig,
+}
+
+/// Connected signaling clients and the WebRTC API used for server-side peers.
+#[derive(Clone)]
+pub struct SignalingHub {
+    inner: Arc<HubInner>,
+}
+
+impl SignalingHub {
+    pub fn new(ban_threshold: usize, ban_duration: Duration, ice: IceConfig) -> Self {
+        SignalingHub {
+            inner: Arc::new(HubInner {
+                // Data channels only, so no media engine or interceptors are needed
+                api: APIBuilder::new().build(),
+                peers: Mutex::new(HashMap::new()),
+                strikes: Mutex::new(HashMap::new()),
+                bans: Mutex::new(HashMap::new()),
+                ban_threshold: ban_threshold.max(1),
+                ban_duration,
+                ice,
+            }),
+        }
+    }
+
+    /// STUN/TURN servers for a client, with TURN credentials minted for `user`.
+    pub fn ice_servers(&self, user: &str) -> Vec<IceServer> {
+        self.inner.ice.servers_for(user)
+    }
+
+    pub fn is_banned(&self, profile_id: Uuid) -> bool {
+        let mut bans = self.inner.bans.lock().unwrap();
+        bans.retain(|_, until| *until > Instant::now());
+        bans.contains_key(&profile_id)
+    }
+
+    fn join(
+        &self,
+        peer_id: &str,
+        profile_id: Uuid,
+        tx: mpsc::UnboundedSender<SignalMessage>,
+        disconnect: CancellationToken,
+    ) {
+        let mut peers = self.inner.peers.lock().unwrap();
+        peers.insert(
+            peer_id.to_string(),
+            ConnectedPeer {
+                profile_id,
+                tx,
+                disconnect,
+                connection: None,
+            },
+        );
+    }
+
+    fn attach(&self, peer_id: &str, connection: Arc<RTCPeerConnection>) {
+        let mut peers = self.inner.peers.lock().unwrap();
+        if let Some(peer) = peers.get_mut(peer_id) {
+            peer.connection = Some(connection);
+        }
+    }
+
+    /// The server's own WebRTC connection to a client, if it has one.
+    pub fn connection(&self, peer_id: &str) -> Option<Arc<RTCPeerConnection>> {
+        let peers = self.inner.peers.lock().unwrap();
+        peers.get(peer_id).and_then(|peer| peer.connection.clone())
+    }
+
+    /// Bans a peer the server itself caught sending corrupt data.
+    pub fn ban(&self, peer_id: &str) {
+        let offender = {
+            let peers = self.inner.peers.lock().unwrap();
+            match peers.get(peer_id) {
+                Some(peer) => peer.profile_id,
+                None => return,
+            }
+        };
+        self.ban_profile(offender);
+    }
+
+    fn leave(&self, peer_id: &str) {
+        self.inner.strikes.lock().unwrap().remove(peer_id);
+        let mut peers = self.inner.peers.lock().unwrap();
+        peers.remove(peer_id);
+        for peer in peers.values() {
+            let _ = peer.tx.send(SignalMessage::PeerLeft {
+                peer_id: peer_id.to_string(),
+            });
+        }
+    }
+
+    /// Forwards a message to another client; false if it isn't connected.
+    fn relay(&self, to: &str, message: SignalMessage) -> bool {
+        let peers = self.inner.peers.lock().unwrap();
+        match peers.get(to) {
+            Some(peer) => peer.tx.send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Records a bad chunk from `peer_id`; bans and disconnects the peer's
+    /// profile once enough different profiles have reported it.
+    fn report(&self, peer_id: &str, reporter: Uuid) -> bool {
+        let offender = {
+            let peers = self.inner.peers.lock().unwrap();
+            match peers.get(peer_id) {
+                Some(peer) if peer.profile_id != reporter => peer.profile_id,
+                _ => return false,
+            }
+        };
+
+        let strikes = {
+            let mut strikes = self.inner.strikes.lock().unwrap();
+            let reporters = strikes.entry(peer_id.to_string()).or_default();
+            reporters.insert(reporter);
+            reporters.len()
+        };
+        if strikes < self.inner.ban_threshold {
+            return false;
+        }
+        self.ban_profile(offender);
+        true
+    }
+
+    fn ban_profile(&self, offender: Uuid) {
+        self.inner
+            .bans
+            .lock()
+            .unwrap()
+            .insert(offender, Instant::now() + self.inner.ban_duration);
+        // Every connection of the banned profile goes, not just the reported one
+        let peers = self.inner.peers.lock().unwrap();
+        for peer in peers.values().filter(|peer| peer.profile_id == offender) {
+            let _ = peer.tx.send(SignalMessage::Error {
+                message: "banned for sending corrupt data".to_string(),
+            });
+            peer.disconnect.cancel();
+        }
+    }
+}
+
+/// Runs one signaling connection until the client disconnects.
+pub struct P2PSignaling;
+
+impl P2PSignaling {
+    pub async fn run(state: AppState, socket: WebSocket, claims: Claims, addr: SocketAddr) {
+        let peer_id = Uuid::new_v4().simple().to_string();
+        let info = PeerInfo {
+            peer_id: peer_id.clone(),
+            profile_id: claims.sub,
+            addr,
+        };
+        if let Err(e) = PeerRegistry::heartbeat(&state, &info).await {
+            tracing::warn!("failed to register peer {}: {}", peer_id, e);
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let disconnect = CancellationToken::new();
+        let hub = state.p2p.clone();
+        hub.join(&peer_id, claims.sub, tx.clone(), disconnect.clone());
+        let _ = tx.send(SignalMessage::Welcome {
+            peer_id: peer_id.clone(),
+            ice_servers: hub.ice_servers(&claims.sub.to_string()),
+        });
+
+        let (mut sink, mut stream) = socket.split();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let Ok(text) = serde_json::to_string(&message) else {
+                    continue;
+                };
+                if sink.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let context = StreamContext {
+            session_id: None,
+            profile_id: claims.sub,
+            media_id: Uuid::nil(), // set per chunk request
+            role: claims.role.clone(),
+            client_ip: Some(addr.ip()),
+        };
+        let mut server_peer: Option<Arc<RTCPeerConnection>> = None;
+
+        loop {
+            let message = tokio::select! {
+                message = stream.next() => message,
+                _ = disconnect.cancelled() => break,
+            };
+            let Some(Ok(message)) = message else {
+                break;
+            };
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let message = match serde_json::from_str::<SignalMessage>(&text) {
+                Ok(message) => message,
+                Err(e) => {
+                    let _ = tx.send(SignalMessage::Error {
+                        message: format!("invalid signaling message: {}", e),
+                    });
+                    continue;
+                }
+            };
+
+            let result = match message {
+                SignalMessage::Offer { peer_id: None, sdp } => {
+                    Self::answer_offer(&state, &peer_id, &mut server_peer, &tx, &context, sdp).await
+                }
+                SignalMessage::Candidate {
+                    peer_id: None,
+                    candidate,
+                } => match &server_peer {
+                    Some(pc) => pc
+                        .add_ice_candidate(candidate)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    None => Err("no server connection to add the candidate to".to_string()),
+                },
+                SignalMessage::Offer {
+                    peer_id: Some(to),
+                    sdp,
+                } => Self::relay(
+                    &hub,
+                    &to,
+                    SignalMessage::Offer {
+                        peer_id: Some(peer_id.clone()),
+                        sdp,
+                    },
+                ),
+                SignalMessage::Answer {
+                    peer_id: Some(to),
+                    sdp,
+                } => Self::relay(
+                    &hub,
+                    &to,
+                    SignalMessage::Answer {
+                        peer_id: Some(peer_id.clone()),
+                        sdp,
+                    },
+                ),
+                SignalMessage::Candidate {
+                    peer_id: Some(to),
+                    candidate,
+                } => Self::relay(
+                    &hub,
+                    &to,
+                    SignalMessage::Candidate {
+                        peer_id: Some(peer_id.clone()),
+                        candidate,
+                    },
+                ),
+                SignalMessage::Announce {
+                    media_id,
+                    has_full_file,
+                    chunk_size,
+                    chunks,
+                } => PeerRegistry::announce(
+                    &state,
+                    &info,
+                    Announcement {
+                        media_id,
+                        has_full_file,
+                        chunk_size,
+                        chunks,
+                    },
+                )
+                .await
+                .map_err(|e| e.to_string()),
+                SignalMessage::Withdraw { media_id } => {
+                    PeerRegistry::withdraw(&state, &peer_id, media_id)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                SignalMessage::Heartbeat => PeerRegistry::heartbeat(&state, &info)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                SignalMessage::Report {
+                    peer_id: offender,
+                    media_id,
+                    index,
+                    session_id,
+                } => {
+                    Self::report(
+                        &state, &claims, addr, &tx, &offender, media_id, index, session_id,
+                    )
+                    .await
+                }
+                _ => Err("unexpected signaling message".to_string()),
+            };
+
+            if let Err(message) = result {
+                let _ = tx.send(SignalMessage::Error { message });
+            }
+        }
+
+        hub.leave(&peer_id);
+        if let Err(e) = PeerRegistry::remove(&state, &peer_id).await {
+            tracing::warn!("failed to remove peer {}: {}", peer_id, e);
+        }
+        if let Some(pc) = server_peer
+            && let Err(e) = pc.close().await
+        {
+            tracing::warn!("failed to close peer connection for {}: {}", peer_id, e);
+        }
+        // Let a ban notice reach the client before closing
+        if disconnect.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        writer.abort();
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn report(
+        state: &AppState,
+        claims: &Claims,
+        addr: SocketAddr,
+        tx: &mpsc::UnboundedSender<SignalMessage>,
+        offender: &str,
+        media_id: Uuid,
+        index: u32,
+        session_id: Uuid,
+    ) -> Result<(), String> {
+        if state.p2p.report(offender, claims.sub) {
+            tracing::warn!(
+                "banned peer {} for corrupt chunks of {}",
+                offender,
+                media_id
+            );
+            PeerRegistry::remove(state, offender)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let (offset, length) = ChunkStore::load(state, media_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .and_then(|manifest| manifest.chunk_range(index))
+            .ok_or_else(|| format!("chunk {} of {} is unknown", index, media_id))?;
+        let url = UrlSigner::sign_url(
+            state,
+            &format!("/v1/media/{}/file?session_id={}", media_id, session_id),
+            media_id,
+            claims.sub,
+            Some(addr.ip()),
+        );
+        let _ = tx.send(SignalMessage::Fallback {
+            media_id,
+            index,
+            url,
+            offset,
+            length,
+        });
+        Ok(())
+    }
+
+    fn relay(hub: &SignalingHub, to: &str, message: SignalMessage) -> Result<(), String> {
+        if hub.relay(to, message) {
+            Ok(())
+        } else {
+            Err(format!("peer {} is not connected", to))
+        }
+    }
+
+    async fn answer_offer(
+        state: &AppState,
+        peer_id: &str,
+        server_peer: &mut Option<Arc<RTCPeerConnection>>,
+        tx: &mpsc::UnboundedSender<SignalMessage>,
+        context: &StreamContext,
+        sdp: String,
+    ) -> Result<(), String> {
+        // Renegotiation reuses the existing connection
+        let pc = match server_peer {
+            Some(pc) => pc.clone(),
+            None => {
+                let pc = Self::server_peer(state, tx, context)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                *server_peer = Some(pc.clone());
+                state.p2p.attach(peer_id, pc.clone());
+                pc
+            }
+        };
+
+        let offer = RTCSessionDescription::offer(sdp).map_err(|e| e.to_string())?;
+        pc.set_remote_description(offer)
+            .await
+            .map_err(|e| e.to_string())?;
+        let answer = pc.create_answer(None).await.map_err(|e| e.to_string())?;
+        pc.set_local_description(answer.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let _ = tx.send(SignalMessage::Answer {
+            peer_id: None,
+            sdp: answer.sdp,
+        });
+        Ok(())
+    }
+
+    async fn server_peer(
+        state: &AppState,
+        tx: &mpsc::UnboundedSender<SignalMessage>,
+        context: &StreamContext,
+    ) -> Result<Arc<RTCPeerConnection>, MediaError> {
+        let pc = Arc::new(
+            state
+                .p2p
+                .inner
+                .api
+                .new_peer_connection(state.p2p.inner.ice.rtc_configuration())
+                .await
+                .map_err(|e| {
+                    tracing::warn!("failed to create peer connection: {}", e);
+                    MediaError::P2PConnectionFailed
+                })?,
+        );
+
+        // Trickle our candidates to the client as they are gathered
+        let candidate_tx = tx.clone();
+        pc.on_ice_candidate(Box::new(move |candidate| {
+            let tx = candidate_tx.clone();
+            Box::pin(async move {
+                if let Some(candidate) = candidate
+                    && let Ok(candidate) = candidate.to_json()
+                {
+                    let _ = tx.send(SignalMessage::Candidate {
+                        peer_id: None,
+                        candidate,
+                    });
+                }
+            })
+        }));
+
+        let state = state.clone();
+        let context = context.clone();
+        pc.on_data_channel(Box::new(move |channel| {
+            MediaChannel::serve(state.clone(), channel, context.clone());
+            Box::pin(async {})
+        }));
+
+        Ok(pc)
+    }
+}
+
+struct RangeRequest {
+    id: u64,
+    media_id: Uuid,
+    offset: u64,
+    length: Option<u64>,
+    index: Option<u32>,
+    hash: Option<String>,
+}
+
+/// Serves media byte ranges over a data channel, one request at a time.
+pub struct MediaChannel;
+
+impl MediaChannel {
+    pub fn serve(state: AppState, channel: Arc<RTCDataChannel>, context: StreamContext) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<ChunkRequest>();
+        let reply_channel = channel.clone();
+        channel.on_message(Box::new(move |message: DataChannelMessage| {
+            let tx = tx.clone();
+            let channel = reply_channel.clone();
+            Box::pin(async move {
+                if !message.is_string {
+                    return;
+                }
+                match serde_json::from_slice::<ChunkRequest>(&message.data) {
+                    Ok(request) => {
+                        let _ = tx.send(request);
+                    }
+                    Err(e) => {
+                        let reply = ChunkReply::Error {
+                            id: 0,
+                            message: format!("invalid chunk request: {}", e),
+                        };
+                        let _ = Self::send_reply(&channel, &reply).await;
+                    }
+                }
+            })
+        }));
+
+        // Replies are sent in order so binary frames never interleave
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                let id = request.id();
+                if let Err(e) = Self::handle(&state, &channel, &context, request).await {
+                    let reply = ChunkReply::Error {
+                        id,
+                        message: e.to_string(),
+                    };
+                    if Self::send_reply(&channel, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn handle(
+        state: &AppState,
+        channel: &RTCDataChannel,
+        context: &StreamContext,
+        request: ChunkRequest,
+    ) -> Result<(), MediaError> {
+        match request {
+            ChunkRequest::Manifest { id, media_id } => {
+                let manifest = Self::manifest(state, media_id).await?;
+                Self::send_reply(channel, &ChunkReply::Manifest { id, manifest }).await
+            }
+            ChunkRequest::Chunk {
+                id,
+                media_id,
+                index,
+            } => {
+                let manifest = Self::manifest(state, media_id).await?;
+                let (offset, length) = manifest.chunk_range(index).ok_or_else(|| {
+                    MediaError::StreamingError(format!("chunk {} does not exist", index))
+                })?;
+                let hash = manifest.hashes.get(index as usize).cloned();
+                let range = RangeRequest {
+                    id,
+                    media_id,
+                    offset,
+                    length: Some(length),
+                    index: Some(index),
+                    hash,
+                };
+                Self::send_range(state, channel, context, range).await
+            }
+            ChunkRequest::Range {
+                id,
+                media_id,
+                offset,
+                length,
+            } => {
+                let range = RangeRequest {
+                    id,
+                    media_id,
+                    offset,
+                    length,
+                    index: None,
+                    hash: None,
+                };
+                Self::send_range(state, channel, context, range).await
+            }
+        }
+    }
+
+    async fn manifest(state: &AppState, media_id: Uuid) -> Result<ChunkManifest, MediaError> {
+        ChunkStore::load(state, media_id)
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?
+            .ok_or_else(|| MediaError::StreamingError("media has not been hashed yet".into()))
+    }
+
+    async fn send_range(
+        state: &AppState,
+        channel: &RTCDataChannel,
+        context: &StreamContext,
+        request: RangeRequest,
+    ) -> Result<(), MediaError> {
+        let media = entity::media::Entity::find_by_id(request.media_id)
+            .one(&state.conn)
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?
+            .ok_or(MediaError::NotFound)?;
+
+        let path = MediaPaths::resolve(state, &media)
+            .await
+            .map_err(|_| MediaError::NotFound)?;
+        let mut file = File::open(&path).await.map_err(|_| MediaError::NotFound)?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?
+            .len();
+        if request.offset > size {
+            return Err(MediaError::StreamingError(format!(
+                "offset {} is past the end of the file",
+                request.offset
+            )));
+        }
+        let length = request
+            .length
+            .unwrap_or(u64::MAX)
+            .min(size - request.offset);
+        file.seek(SeekFrom::Start(request.offset))
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+
+        Self::send_reply(
+            channel,
+            &ChunkReply::Chunk {
+                id: request.id,
+                media_id: media.id,
+                offset: request.offset,
+                length,
+                size,
+                index: request.index,
+                hash: request.hash,
+            },
+        )
+        .await?;
+
+        let context = StreamContext {
+            media_id: media.id,
+            ..context.clone()
+        };
+        let reader = ReaderStream::with_capacity(file.take(length), CHUNK_MESSAGE_SIZE);
+        let mut chunks = state.bandwidth.throttle(reader, context);
+        while let Some(chunk) = chunks.next().await {
+            let chunk: Bytes = chunk.map_err(|e| MediaError::StreamingError(e.to_string()))?;
+            // Don't queue more than the SCTP transport can drain
+            while channel.buffered_amount().await > MAX_BUFFERED_AMOUNT {
+                tokio::time::sleep(BUFFER_POLL_INTERVAL).await;
+            }
+            channel
+                .send(&chunk)
+                .await
+                .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        }
+
+        Self::send_reply(channel, &ChunkReply::End { id: request.id }).await
+    }
+
+    async fn send_reply(channel: &RTCDataChannel, reply: &ChunkReply) -> Result<(), MediaError> {
+        let text =
+            serde_json::to_string(reply).map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        channel
+            .send_text(text)
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        Ok(())
+    }
+}
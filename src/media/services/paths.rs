@@ -0,0 +1,104 @@
+use entity::{library, media};
+use sea_orm::EntityTrait;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{errors::AppError, state::AppState};
+
+/// Media paths are stored relative to their library root with `/` separators,
+/// so moving a library (e.g. a remounted disk) only needs `library.path` updated.
+pub struct MediaPaths;
+
+impl MediaPaths {
+    /// `path` relative to `root`, or `None` if it lies outside of it.
+    pub fn relative(root: &Path, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(root).ok()?;
+        let parts: Option<Vec<&str>> = relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect();
+        let parts = parts?;
+        (!parts.is_empty()).then(|| parts.join("/"))
+    }
+
+    /// Where a stored path lives on disk under `root`.
+    pub fn join(root: &Path, file_path: &str) -> PathBuf {
+        // Rows the migration couldn't convert keep their absolute path
+        if Path::new(file_path).is_absolute() {
+            return PathBuf::from(file_path);
+        }
+        file_path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .fold(PathBuf::from(root), |path, part| path.join(part))
+    }
+
+    /// Where a media item's file lives on disk.
+    pub async fn resolve(state: &AppState, media: &media::Model) -> Result<PathBuf, AppError> {
+        let library = library::Entity::find_by_id(media.library_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        Ok(Self::join(Path::new(&library.path), &media.file_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_round_trip() {
+        let root = Path::new("/srv/media");
+        let path = Path::new("/srv/media/Films/Alien (1979)/Alien.mkv");
+
+        let relative = MediaPaths::relative(root, path).unwrap();
+        assert_eq!(relative, "Films/Alien (1979)/Alien.mkv");
+        assert_eq!(MediaPaths::join(root, &relative), path);
+    }
+
+    #[test]
+    fn moving_the_root_moves_every_file() {
+        let relative = MediaPaths::relative(Path::new("/mnt/old"), Path::new("/mnt/old/a/b.mp3"));
+        assert_eq!(
+            MediaPaths::join(Path::new("/mnt/new"), &relative.unwrap()),
+            Path::new("/mnt/new/a/b.mp3")
+        );
+    }
+
+    #[test]
+    fn paths_outside_the_root_have_no_relative_form() {
+        let root = Path::new("/srv/media");
+        assert_eq!(
+            MediaPaths::relative(root, Path::new("/srv/other/a.mp4")),
+            None
+        );
+        assert_eq!(
+            MediaPaths::relative(root, Path::new("/srv/mediaX/a.mp4")),
+            None
+        );
+        assert_eq!(
+            MediaPaths::relative(root, Path::new("/srv/media/../a.mp4")),
+            None
+        );
+        assert_eq!(MediaPaths::relative(root, root), None);
+    }
+
+    #[test]
+    fn absolute_stored_paths_are_used_as_they_are() {
+        assert_eq!(
+            MediaPaths::join(Path::new("/srv/media"), "/old/place/a.mp4"),
+            Path::new("/old/place/a.mp4")
+        );
+    }
+
+    #[test]
+    fn empty_segments_are_ignored() {
+        assert_eq!(
+            MediaPaths::join(Path::new("/srv/media"), "Films//a.mp4/"),
+            Path::new("/srv/media/Films/a.mp4")
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use chrono::Utc;
+use entity::{peer, peer_media};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter,
+};
+use serde_json::Value;
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    media::{models::P2PPeer, services::throttle::Network},
+    state::AppState,
+};
+
+const MAX_PEERS_PER_STREAM: usize = 8;
+
+/// A connected signaling client acting as a peer.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub profile_id: Uuid,
+    pub addr: SocketAddr,
+}
+
+/// What a peer holds of one media item.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub media_id: Uuid,
+    pub has_full_file: bool,
+    pub chunk_size: Option<u32>, // in bytes
+    pub chunks: Vec<u32>,        // indices held when partial
+}
+
+/// Tracks live peers and the media they can serve, backed by `peer` and `peer_media`.
+pub struct PeerRegistry;
+
+impl PeerRegistry {
+    /// Registers the peer or refreshes its `last_seen`.
+    pub async fn heartbeat(state: &AppState, info: &PeerInfo) -> Result<peer::Model, AppError> {
+        let is_local = Network::of(Some(info.addr.ip())) == Network::Local;
+        Self::upsert(
+            state,
+            &info.peer_id,
+            info.addr,
+            Some(info.profile_id),
+            is_local,
+        )
+        .await
+    }
+
+    /// Registers a peer found over mDNS, or marks a known one as being on the
+    /// LAN; the address of a peer registered over signaling is left alone.
+    pub async fn register_lan(
+        state: &AppState,
+        peer_id: &str,
+        addr: SocketAddr,
+    ) -> Result<peer::Model, AppError> {
+        Self::upsert(state, peer_id, addr, None, true).await
+    }
+
+    async fn upsert(
+        state: &AppState,
+        peer_id: &str,
+        addr: SocketAddr,
+        profile_id: Option<Uuid>,
+        is_local: bool,
+    ) -> Result<peer::Model, AppError> {
+        let now = Utc::now().naive_utc();
+        let existing = peer::Entity::find()
+            .filter(peer::Column::PeerId.eq(peer_id))
+            .one(&state.conn)
+            .await?;
+
+        let peer = match existing {
+            Some(peer) => {
+                // Signaling and mDNS may both see a peer; once seen on the LAN it stays local
+                let was_local = peer.is_local;
+                // mDNS adverts are unauthenticated, so only signaling may move
+                // the address of a peer a profile registered
+                let trusted = profile_id.is_some() || peer.profile_id.is_none();
+                let mut peer = peer.into_active_model();
+                if trusted {
+                    peer.ip_address = Set(addr.ip().to_string());
+                    peer.port = Set(addr.port() as i32);
+                }
+                peer.last_seen = Set(now);
+                peer.is_local = Set(is_local || was_local);
+                if profile_id.is_some() {
+                    peer.profile_id = Set(profile_id);
+                }
+                peer.update(&state.conn).await?
+            }
+            None => {
+                peer::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    peer_id: Set(peer_id.to_string()),
+                    ip_address: Set(addr.ip().to_string()),
+                    port: Set(addr.port() as i32),
+                    last_seen: Set(now),
+                    profile_id: Set(profile_id),
+                    is_local: Set(is_local),
+                }
+                .insert(&state.conn)
+                .await?
+            }
+        };
+
+        Ok(peer)
+    }
+
+    pub async fn announce(
+        state: &AppState,
+        info: &PeerInfo,
+        announcement: Announcement,
+    ) -> Result<(), AppError> {
+        entity::media::Entity::find_by_id(announcement.media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let peer = Self::heartbeat(state, info).await?;
+
+        let chunks = (!announcement.has_full_file).then(|| {
+            Value::Array(
+                announcement
+                    .chunks
+                    .iter()
+                    .map(|index| Value::from(*index))
+                    .collect(),
+            )
+        });
+        let existing = peer_media::Entity::find()
+            .filter(peer_media::Column::PeerId.eq(peer.id))
+            .filter(peer_media::Column::MediaId.eq(announcement.media_id))
+            .one(&state.conn)
+            .await?;
+
+        let mut entry = match existing {
+            Some(entry) => entry.into_active_model(),
+            None => peer_media::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                peer_id: Set(peer.id),
+                media_id: Set(announcement.media_id),
+                ..Default::default()
+            },
+        };
+        entry.has_full_file = Set(announcement.has_full_file);
+        entry.chunk_size = Set(announcement.chunk_size.map(|size| size as i32));
+        entry.chunks = Set(chunks);
+        entry.updated_at = Set(Utc::now().naive_utc());
+        entry.save(&state.conn).await?;
+
+        Ok(())
+    }
+
+    pub async fn withdraw(state: &AppState, peer_id: &str, media_id: Uuid) -> Result<(), AppError> {
+        let Some(peer) = peer::Entity::find()
+            .filter(peer::Column::PeerId.eq(peer_id))
+            .one(&state.conn)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        peer_media::Entity::delete_many()
+            .filter(peer_media::Column::PeerId.eq(peer.id))
+            .filter(peer_media::Column::MediaId.eq(media_id))
+            .exec(&state.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Forgets a peer and everything it announced.
+    pub async fn remove(state: &AppState, peer_id: &str) -> Result<(), AppError> {
+        peer::Entity::delete_many()
+            .filter(peer::Column::PeerId.eq(peer_id))
+            .exec(&state.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Live peers on the server's LAN.
+    pub async fn lan_peers(state: &AppState) -> Result<Vec<peer::Model>, AppError> {
+        let cutoff = (Utc::now() - state.peer_timeout).naive_utc();
+        Ok(peer::Entity::find()
+            .filter(peer::Column::IsLocal.eq(true))
+            .filter(peer::Column::LastSeen.gt(cutoff))
+            .all(&state.conn)
+            .await?)
+    }
+
+    /// Removes peers that stopped sending heartbeats; returns how many.
+    pub async fn reap(state: &AppState) -> Result<u64, AppError> {
+        let cutoff = (Utc::now() - state.peer_timeout).naive_utc();
+        let result = peer::Entity::delete_many()
+            .filter(peer::Column::LastSeen.lt(cutoff))
+            .exec(&state.conn)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    pub fn spawn_reaper(state: AppState) {
+        let period = (state.peer_timeout / 2)
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match Self::reap(&state).await {
+                    Ok(0) => {}
+                    Ok(count) => tracing::debug!("reaped {} stale peers", count),
+                    Err(e) => tracing::warn!("failed to reap stale peers: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Live peers holding `media_id`, complete copies and recent peers first.
+    ///
+    /// With `prefer_local`, peers on the server's LAN come before everything else.
+    pub async fn best_peers(
+        state: &AppState,
+        media_id: Uuid,
+        exclude_profile: Uuid,
+        prefer_local: bool,
+        chunk_size: u32, // the manifest's; peers chunking differently are left out
+    ) -> Result<Vec<P2PPeer>, AppError> {
+        let cutoff = (Utc::now() - state.peer_timeout).naive_utc();
+        let entries = peer_media::Entity::find()
+            .filter(peer_media::Column::MediaId.eq(media_id))
+            .find_also_related(peer::Entity)
+            .filter(peer::Column::LastSeen.gt(cutoff))
+            .all(&state.conn)
+            .await?;
+
+        let mut peers: Vec<(P2PPeer, chrono::NaiveDateTime)> = entries
+            .into_iter()
+            .filter_map(|(entry, peer)| {
+                let peer = peer?;
+                // A profile gains nothing from its own other devices' copies
+                if peer.profile_id == Some(exclude_profile) {
+                    return None;
+                }
+                // Chunk indexes only name the same bytes at the same chunk size
+                let announced = entry.chunk_size.map(|size| size as u32);
+                if announced.map_or(!entry.has_full_file, |size| size != chunk_size) {
+                    return None;
+                }
+                let chunks = if entry.has_full_file {
+                    vec![]
+                } else {
+                    entry
+                        .chunks
+                        .as_ref()
+                        .and_then(Value::as_array)
+                        .map(|chunks| {
+                            chunks
+                                .iter()
+                                .filter_map(|index| index.as_u64().map(|i| i as u32))
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                };
+                Some((
+                    P2PPeer {
+                        peer_id: peer.peer_id,
+                        ip_address: peer.ip_address,
+                        port: peer.port as u16,
+                        has_full_file: entry.has_full_file,
+                        chunk_size: entry.chunk_size.map(|size| size as u32),
+                        chunks,
+                        is_local: peer.is_local,
+                    },
+                    peer.last_seen,
+                ))
+            })
+            .collect();
+
+        peers.sort_by(|(a, a_seen), (b, b_seen)| {
+            (prefer_local && b.is_local)
+                .cmp(&(prefer_local && a.is_local))
+                .then(b.has_full_file.cmp(&a.has_full_file))
+                .then(b.chunks.len().cmp(&a.chunks.len()))
+                .then(b_seen.cmp(a_seen))
+        });
+        peers.truncate(MAX_PEERS_PER_STREAM);
+
+        Ok(peers.into_iter().map(|(peer, _)| peer).collect())
+    }
+}
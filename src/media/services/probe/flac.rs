@@ -0,0 +1,70 @@
+use std::fs::File;
+
+use super::{ProbeResult, Track, TrackKind, read_at};
+
+// STREAMINFO is always the first metadata block, right after the "fLaC" marker
+pub fn probe(file: &mut File) -> Option<ProbeResult> {
+    let block = read_at(file, 4, 4 + 34)?;
+    if block[0] & 0x7F != 0 {
+        return None;
+    }
+    let mut result = ProbeResult::new("flac");
+    let mut track = Track::new(TrackKind::Audio, Some("flac".to_string()));
+    track.default = true;
+    streaminfo(&block[4..], &mut result, &mut track)?;
+    result.tracks.push(track);
+    Some(result)
+}
+
+/// Reads a 34-byte STREAMINFO block; Ogg FLAC embeds the same structure.
+pub(super) fn streaminfo(info: &[u8], result: &mut ProbeResult, track: &mut Track) -> Option<()> {
+    let info = info.get(..18)?;
+    // 20 bits of sample rate, 3 of channels - 1, 5 of bits per sample - 1, 36 of total samples
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+    let sample_rate = (packed >> 44) as u32;
+    let channels = ((packed >> 41) & 0b111) as u32 + 1;
+    let total_samples = packed & 0xF_FFFF_FFFF;
+    if sample_rate == 0 {
+        return None;
+    }
+    track.sample_rate = Some(sample_rate);
+    track.channels = Some(channels);
+    if total_samples > 0 {
+        result.duration = Some(total_samples as f64 / sample_rate as f64);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn flac(sample_rate: u64, channels: u64, total_samples: u64) -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x80, 0, 0, 34]); // last block, STREAMINFO, 34 bytes
+        data.extend_from_slice(&[0; 10]); // block and frame sizes
+        let packed = (sample_rate << 44) | ((channels - 1) << 41) | (15 << 36) | total_samples;
+        data.extend_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&[0; 16]); // MD5
+        data
+    }
+
+    #[test]
+    fn reads_streaminfo() {
+        let result = probe(&mut fixtures::file(&flac(44100, 2, 441_000))).unwrap();
+        assert_eq!(result.duration, Some(10.0));
+        assert_eq!(result.tracks[0].sample_rate, Some(44100));
+        assert_eq!(result.tracks[0].channels, Some(2));
+    }
+
+    #[test]
+    fn malformed_streaminfo_is_none() {
+        assert!(probe(&mut fixtures::file(&flac(0, 2, 441_000))).is_none());
+        let mut data = flac(44100, 2, 441_000);
+        data[4] = 0x84; // not STREAMINFO
+        assert!(probe(&mut fixtures::file(&data)).is_none());
+        data.truncate(20);
+        assert!(probe(&mut fixtures::file(&data)).is_none());
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+use super::{ProbeResult, Track, TrackKind, language, read_at};
+
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_IETF: u32 = 0x22B59D;
+const NAME: u32 = 0x536E;
+const FLAG_DEFAULT: u32 = 0x88;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43B675;
+
+// Reads the EBML header for the doc type, then the segment's Info and Tracks,
+// seeking over everything else without reading it
+pub fn probe(file: &mut File, size: u64) -> Option<ProbeResult> {
+    let (id, header_len, len) = element_header(file, 0)?;
+    if id != EBML {
+        return None;
+    }
+    let header = read_at(file, header_len, len?)?;
+    let container = match elements(&header)
+        .find(|(id, _)| *id == DOC_TYPE)
+        .map(|(_, body)| body)
+    {
+        Some(b"webm") => "webm",
+        _ => "mkv",
+    };
+    let mut result = ProbeResult::new(container);
+
+    let mut offset = header_len + len?;
+    let (id, header_len, _) = element_header(file, offset)?;
+    if id != SEGMENT {
+        return None;
+    }
+    offset += header_len;
+
+    let mut timestamp_scale = 1_000_000; // nanoseconds per tick
+    let mut duration = None;
+    let (mut seen_info, mut seen_tracks) = (false, false);
+    while offset < size && !(seen_info && seen_tracks) {
+        let (id, header_len, len) = element_header(file, offset)?;
+        // Clusters of unknown size (live recordings) can't be skipped over
+        let len = len?;
+        match id {
+            INFO => {
+                let info = read_at(file, offset + header_len, len)?;
+                for (id, body) in elements(&info) {
+                    match id {
+                        TIMESTAMP_SCALE => timestamp_scale = uint(body),
+                        DURATION => duration = float(body),
+                        _ => {}
+                    }
+                }
+                seen_info = true;
+            }
+            TRACKS => {
+                let tracks = read_at(file, offset + header_len, len)?;
+                result.tracks = elements(&tracks)
+                    .filter(|(id, _)| *id == TRACK_ENTRY)
+                    .filter_map(|(_, body)| parse_track(body))
+                    .collect();
+                seen_tracks = true;
+            }
+            CLUSTER if seen_tracks => break,
+            _ => {}
+        }
+        offset += header_len + len;
+    }
+
+    result.duration = duration.map(|ticks| ticks * timestamp_scale as f64 / 1e9);
+    seen_tracks.then_some(result)
+}
+
+fn parse_track(entry: &[u8]) -> Option<Track> {
+    let mut kind = None;
+    let mut codec_id = None;
+    let mut legacy_language = Some("eng".to_string()); // the spec's default
+    let mut ietf_language = None;
+    let mut name = None;
+    let mut default = true;
+    let (mut width, mut height, mut channels, mut sample_rate) = (None, None, None, None);
+
+    for (id, body) in elements(entry) {
+        match id {
+            TRACK_TYPE => {
+                kind = match uint(body) {
+                    1 => Some(TrackKind::Video),
+                    2 => Some(TrackKind::Audio),
+                    0x11 => Some(TrackKind::Subtitle),
+                    _ => None,
+                }
+            }
+            CODEC_ID => codec_id = Some(string(body)),
+            LANGUAGE => legacy_language = language(&string(body)),
+            LANGUAGE_IETF => ietf_language = language(&string(body)),
+            NAME => name = Some(string(body)).filter(|name| !name.is_empty()),
+            FLAG_DEFAULT => default = uint(body) != 0,
+            VIDEO => {
+                for (id, body) in elements(body) {
+                    match id {
+                        PIXEL_WIDTH => width = Some(uint(body) as u32),
+                        PIXEL_HEIGHT => height = Some(uint(body) as u32),
+                        _ => {}
+                    }
+                }
+            }
+            AUDIO => {
+                for (id, body) in elements(body) {
+                    match id {
+                        SAMPLING_FREQUENCY => sample_rate = float(body).map(|rate| rate as u32),
+                        CHANNELS => channels = Some(uint(body) as u32),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut track = Track::new(kind?, codec_id.as_deref().map(codec));
+    track.language = ietf_language.or(legacy_language);
+    track.name = name;
+    track.default = default;
+    track.width = width;
+    track.height = height;
+    track.channels = channels;
+    track.sample_rate = sample_rate;
+    Some(track)
+}
+
+fn codec(codec_id: &str) -> String {
+    let codec = match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_AV1" => "av1",
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_MPEG2" => "mpeg2video",
+        "V_THEORA" => "theora",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        "A_TRUEHD" => "truehd",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_FLAC" => "flac",
+        "A_ALAC" => "alac",
+        "A_MPEG/L3" => "mp3",
+        "A_MPEG/L2" => "mp2",
+        "S_TEXT/UTF8" => "srt",
+        "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA" => "ass",
+        "S_TEXT/WEBVTT" => "vtt",
+        "S_HDMV/PGS" => "pgs",
+        "S_VOBSUB" => "dvdsub",
+        id if id.starts_with("V_MPEG4/ISO/") || id.starts_with("V_MS/VFW") => "mpeg4",
+        id if id.starts_with("A_AAC") => "aac",
+        id if id.starts_with("A_DTS") => "dts",
+        id if id.starts_with("A_PCM/") => "pcm",
+        id => return id.to_lowercase(),
+    };
+    codec.to_string()
+}
+
+// An element's ID, header length and body length (`None` when unknown) at `offset`
+fn element_header(file: &mut File, offset: u64) -> Option<(u32, u64, Option<u64>)> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut header = Vec::with_capacity(12);
+    file.by_ref().take(12).read_to_end(&mut header).ok()?;
+    let (id, id_len) = vint(&header, true)?;
+    let (len, len_len) = vint(header.get(id_len..)?, false)?;
+    let unknown = len == (1 << (7 * len_len)) - 1;
+    Some((
+        id as u32,
+        (id_len + len_len) as u64,
+        (!unknown).then_some(len),
+    ))
+}
+
+// Variable-length integer; IDs keep their length marker, sizes don't
+fn vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        (first as u64) & (0xFF >> len)
+    };
+    for byte in data.get(1..len)? {
+        value = (value << 8) | *byte as u64;
+    }
+    Some((value, len))
+}
+
+// Child elements of a master element held in memory
+fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let (id, id_len) = vint(rest, true)?;
+        let (len, len_len) = vint(rest.get(id_len..)?, false)?;
+        let start = id_len + len_len;
+        let body = rest.get(start..start.checked_add(len as usize)?)?;
+        rest = &rest[start + len as usize..];
+        Some((id as u32, body))
+    })
+}
+
+fn uint(body: &[u8]) -> u64 {
+    body.iter()
+        .take(8)
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+fn float(body: &[u8]) -> Option<f64> {
+    match body.len() {
+        4 => Some(f32::from_be_bytes(body.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(body.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn string(body: &[u8]) -> String {
+    String::from_utf8_lossy(body)
+        .trim_end_matches(char::from(0))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn element(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&[0x40 | (body.len() >> 8) as u8, body.len() as u8]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn mkv(doc_type: &[u8], segment: &[u8]) -> Vec<u8> {
+        let mut data = element(&[0x1A, 0x45, 0xDF, 0xA3], &element(&[0x42, 0x82], doc_type));
+        // A segment of unknown size, as live recorders write it
+        data.extend_from_slice(&[
+            0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ]);
+        data.push(0xFF);
+        data.extend_from_slice(segment);
+        data
+    }
+
+    fn info_and_tracks() -> Vec<u8> {
+        let mut data = element(
+            &[0x15, 0x49, 0xA9, 0x66],
+            &element(&[0x44, 0x89], &1500f64.to_be_bytes()),
+        );
+        let mut entry = element(&[0x83], &[2]);
+        entry.extend(element(&[0x86], b"A_OPUS"));
+        entry.extend(element(&[0x22, 0xB5, 0x9C], b"fre"));
+        data.extend(element(
+            &[0x16, 0x54, 0xAE, 0x6B],
+            &element(&[0xAE], &entry),
+        ));
+        data
+    }
+
+    #[test]
+    fn reads_info_and_tracks() {
+        let data = mkv(b"webm", &info_and_tracks());
+        let result = probe(&mut fixtures::file(&data), data.len() as u64).unwrap();
+        assert_eq!(result.container, "webm");
+        assert_eq!(result.duration, Some(1.5));
+        assert_eq!(result.tracks.len(), 1);
+        assert_eq!(result.tracks[0].codec.as_deref(), Some("opus"));
+        assert_eq!(result.tracks[0].language.as_deref(), Some("fre"));
+    }
+
+    #[test]
+    fn vints_keep_or_drop_the_marker() {
+        assert_eq!(vint(&[0x81], false), Some((1, 1)));
+        assert_eq!(vint(&[0x40, 0x02], false), Some((2, 2)));
+        assert_eq!(vint(&[0x1A, 0x45, 0xDF, 0xA3], true), Some((0x1A45DFA3, 4)));
+        assert_eq!(vint(&[0x00, 0x01], false), None);
+        assert_eq!(vint(&[0x20, 0x01], false), None);
+        assert_eq!(vint(&[], false), None);
+    }
+
+    #[test]
+    fn oversized_children_end_the_element_list() {
+        let mut data = element(&[0x83], &[2]);
+        data.extend_from_slice(&[0x86, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE, 0x00]);
+        assert_eq!(elements(&data).count(), 1);
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_none() {
+        let data = mkv(b"matroska", &info_and_tracks());
+        for len in [0, 3, 10, data.len() - 5] {
+            let data = &data[..len];
+            assert!(probe(&mut fixtures::file(data), data.len() as u64).is_none());
+        }
+        let data = element(&[0x1F, 0x43, 0xB6, 0x75], &[0; 8]);
+        assert!(probe(&mut fixtures::file(&data), data.len() as u64).is_none());
+    }
+}
@@ -0,0 +1,260 @@
+mod flac;
+mod matroska;
+mod mp3;
+pub(crate) mod mp4;
+mod ogg;
+mod wav;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+/// One elementary stream inside a container.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Track {
+    pub kind: TrackKind,
+    pub codec: Option<String>,    // e.g., "h264", "aac" or "srt"
+    pub language: Option<String>, // ISO 639-2 or BCP 47, as the container stores it
+    pub name: Option<String>,
+    pub default: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>, // in Hz
+}
+
+impl Track {
+    fn new(kind: TrackKind, codec: Option<String>) -> Self {
+        Track {
+            kind,
+            codec,
+            language: None,
+            name: None,
+            default: false,
+            width: None,
+            height: None,
+            channels: None,
+            sample_rate: None,
+        }
+    }
+}
+
+/// What the container headers say about a file. The summary fields use the
+/// keys `SourceInfo` reads back from `media_metadata`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub container: String,
+    pub duration: Option<f64>, // in seconds
+    pub bitrate: Option<u32>,  // in kbps, all streams together
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub subtitle_formats: Vec<String>,
+    pub tracks: Vec<Track>,
+}
+
+impl ProbeResult {
+    fn new(container: &str) -> Self {
+        ProbeResult {
+            container: container.to_string(),
+            duration: None,
+            bitrate: None,
+            width: None,
+            height: None,
+            video_codec: None,
+            audio_codec: None,
+            subtitle_formats: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+
+    /// e.g., "1920x1080"
+    pub fn resolution(&self) -> Option<String> {
+        Some(format!("{}x{}", self.width?, self.height?))
+    }
+
+    // Fills the summary from the first track of each kind
+    fn finish(mut self, size: u64) -> Self {
+        self.duration = self.duration.filter(|d| d.is_finite() && *d > 0.0);
+        let first = |kind| self.tracks.iter().find(|track| track.kind == kind);
+        if let Some(video) = first(TrackKind::Video) {
+            self.video_codec = video.codec.clone();
+            self.width = self.width.or(video.width);
+            self.height = self.height.or(video.height);
+        }
+        if let Some(audio) = first(TrackKind::Audio) {
+            self.audio_codec = audio.codec.clone();
+        }
+        for track in &self.tracks {
+            if track.kind == TrackKind::Subtitle
+                && let Some(codec) = &track.codec
+                && !self.subtitle_formats.contains(codec)
+            {
+                self.subtitle_formats.push(codec.clone());
+            }
+        }
+        if self.bitrate.is_none()
+            && let Some(duration) = self.duration
+        {
+            self.bitrate = Some((size as f64 * 8.0 / duration / 1000.0).round() as u32);
+        }
+        self
+    }
+}
+
+pub struct MediaProbe;
+
+impl MediaProbe {
+    /// Probes a file by its leading bytes rather than its extension; `None` when
+    /// the format isn't one we parse or the headers are damaged.
+    pub async fn probe(path: PathBuf) -> Option<ProbeResult> {
+        tokio::task::spawn_blocking(move || Self::probe_blocking(&path))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn probe_blocking(path: &Path) -> Option<ProbeResult> {
+        let mut file = File::open(path).ok()?;
+        let size = file.metadata().ok()?.len();
+        let mut magic = [0u8; 12];
+        let read = file.read(&mut magic).ok()?;
+        let magic = &magic[..read];
+        file.seek(SeekFrom::Start(0)).ok()?;
+
+        let result = if magic.get(4..8) == Some(b"ftyp") {
+            mp4::probe(&mut file, size)
+        } else if magic.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            matroska::probe(&mut file, size)
+        } else if magic.starts_with(b"fLaC") {
+            flac::probe(&mut file)
+        } else if magic.starts_with(b"RIFF") && magic.get(8..12) == Some(b"WAVE") {
+            wav::probe(&mut file)
+        } else if magic.starts_with(b"OggS") {
+            ogg::probe(&mut file, size)
+        } else if magic.starts_with(b"ID3") || mp3::is_frame_sync(magic) {
+            mp3::probe(&mut file, size)
+        } else {
+            None
+        };
+        if result.is_none() {
+            tracing::debug!("couldn't probe {}", path.display());
+        }
+        result.map(|result| result.finish(size))
+    }
+}
+
+// Reads exactly `len` bytes at `offset`, refusing absurd sizes from damaged headers
+pub(crate) fn read_at(file: &mut File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    const MAX_READ: u64 = 64 * 1024 * 1024;
+    if len > MAX_READ {
+        return None;
+    }
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buffer = vec![0; len as usize];
+    file.read_exact(&mut buffer).ok()?;
+    Some(buffer)
+}
+
+// Big-endian cursor over a header already read into memory
+pub(crate) struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Bytes { data, pos: 0 }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    pub(crate) fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        let rest = self.data.get(self.pos..).unwrap_or_default();
+        self.pos = self.data.len();
+        rest
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u32_le(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    pub(crate) fn u16_le(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+}
+
+// "und" and empty strings carry no information
+fn language(value: &str) -> Option<String> {
+    let value = value.trim_matches(char::from(0)).trim();
+    match value {
+        "" | "und" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::{
+        fs::File,
+        path::PathBuf,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+
+    /// A file in the temp directory holding `bytes`; the caller removes it.
+    pub(crate) fn path(bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "probe-fixture-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// An open file holding `bytes`, already unlinked from the temp directory.
+    pub(crate) fn file(bytes: &[u8]) -> File {
+        let path = path(bytes);
+        let file = File::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        file
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs::File;
+
+use super::{Bytes, ProbeResult, Track, TrackKind, read_at};
+
+const SYNC_SEARCH: u64 = 64 * 1024; // how far past the tags to look for the first frame
+
+// in kbps, by [version is MPEG-1][layer - 1][index]
+const BITRATES: [[[u32; 16]; 3]; 2] = [
+    [
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256, 0,
+        ],
+        [
+            0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+        ],
+        [
+            0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0,
+        ],
+    ],
+    [
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448, 0,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 0,
+        ],
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+        ],
+    ],
+];
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000]; // MPEG-1; halved for 2, quartered for 2.5
+
+pub fn is_frame_sync(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0
+}
+
+struct FrameHeader {
+    mpeg1: bool,
+    layer: u8,
+    bitrate: u32, // in kbps
+    sample_rate: u32,
+    channels: u32,
+}
+
+impl FrameHeader {
+    fn parse(header: &[u8]) -> Option<Self> {
+        if !is_frame_sync(header) || header.len() < 4 {
+            return None;
+        }
+        let version = (header[1] >> 3) & 0b11; // 0: 2.5, 2: 2, 3: 1
+        let layer = match (header[1] >> 1) & 0b11 {
+            0b01 => 3,
+            0b10 => 2,
+            0b11 => 1,
+            _ => return None,
+        };
+        let bitrate_index = (header[2] >> 4) as usize;
+        let rate_index = ((header[2] >> 2) & 0b11) as usize;
+        if version == 1 || rate_index == 3 || bitrate_index == 0 || bitrate_index == 15 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+        let sample_rate = SAMPLE_RATES[rate_index]
+            / match version {
+                3 => 1,
+                2 => 2,
+                _ => 4,
+            };
+        Some(FrameHeader {
+            mpeg1,
+            layer,
+            bitrate: BITRATES[mpeg1 as usize][layer as usize - 1][bitrate_index],
+            sample_rate,
+            channels: if header[3] >> 6 == 0b11 { 1 } else { 2 },
+        })
+    }
+
+    fn samples_per_frame(&self) -> u32 {
+        match (self.layer, self.mpeg1) {
+            (1, _) => 384,
+            (3, false) => 576,
+            _ => 1152,
+        }
+    }
+
+    // Where the Xing/Info header sits, after the side information
+    fn side_info_len(&self) -> usize {
+        match (self.mpeg1, self.channels) {
+            (true, 1) => 17,
+            (true, _) => 32,
+            (false, 1) => 9,
+            (false, _) => 17,
+        }
+    }
+}
+
+// Duration comes from a Xing/Info or VBRI header when there is one, otherwise
+// from the first frame's bitrate, which is exact for constant bitrate files
+pub fn probe(file: &mut File, size: u64) -> Option<ProbeResult> {
+    let mut start = 0;
+    let id3 = read_at(file, 0, 10.min(size))?;
+    if id3.starts_with(b"ID3") && id3.len() == 10 {
+        let tag_size = id3[6..10]
+            .iter()
+            .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7F) as u64);
+        let footer = if id3[5] & 0x10 != 0 { 10 } else { 0 };
+        start = 10 + tag_size + footer;
+    }
+
+    let window = read_at(file, start, SYNC_SEARCH.min(size.saturating_sub(start)))?;
+    let (position, header) = (0..window.len().saturating_sub(4))
+        .find_map(|i| FrameHeader::parse(&window[i..i + 4]).map(|header| (i, header)))?;
+    let frame = &window[position..];
+
+    let mut audio_end = size;
+    if size >= 128 && read_at(file, size - 128, 3)?.as_slice() == b"TAG" {
+        audio_end -= 128;
+    }
+    let audio_bytes = audio_end.saturating_sub(start + position as u64);
+
+    let xing = 4 + header.side_info_len();
+    let frames = match frame.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") => {
+            let mut bytes = Bytes::new(&frame[xing + 4..]);
+            let flags = bytes.u32()?;
+            (flags & 1 != 0).then(|| bytes.u32()).flatten()
+        }
+        _ => match frame.get(36..40) {
+            Some(b"VBRI") => {
+                let mut bytes = Bytes::new(&frame[40..]);
+                bytes.skip(10)?; // version, delay, quality and byte count
+                bytes.u32()
+            }
+            _ => None,
+        },
+    };
+
+    let mut result = ProbeResult::new("mp3");
+    result.duration = match frames {
+        Some(frames) => {
+            Some(frames as f64 * header.samples_per_frame() as f64 / header.sample_rate as f64)
+        }
+        None => Some(audio_bytes as f64 * 8.0 / (header.bitrate as f64 * 1000.0)),
+    };
+    result.bitrate = match frames {
+        Some(_) => result
+            .duration
+            .map(|duration| (audio_bytes as f64 * 8.0 / duration / 1000.0).round() as u32),
+        None => Some(header.bitrate),
+    };
+
+    let codec = format!("mp{}", header.layer); // layers I and II are rare, but exist
+    let mut track = Track::new(TrackKind::Audio, Some(codec));
+    track.channels = Some(header.channels);
+    track.sample_rate = Some(header.sample_rate);
+    track.default = true;
+    result.tracks.push(track);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    // MPEG-1 layer III, 128 kbps, 44.1 kHz, joint stereo
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x64];
+
+    fn probe_bytes(data: &[u8]) -> Option<ProbeResult> {
+        probe(&mut fixtures::file(data), data.len() as u64)
+    }
+
+    fn frame_with(marker: &[u8; 4], fields: &[u8]) -> Vec<u8> {
+        let mut frame = HEADER.to_vec();
+        frame.extend_from_slice(&[0; 32]); // side information
+        frame.extend_from_slice(marker);
+        frame.extend_from_slice(fields);
+        frame.resize(417, 0);
+        frame
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let header = FrameHeader::parse(&HEADER).unwrap();
+        assert!(header.mpeg1);
+        assert_eq!(header.layer, 3);
+        assert_eq!(header.bitrate, 128);
+        assert_eq!(header.sample_rate, 44100);
+        assert_eq!(header.channels, 2);
+        // Reserved version, free and bad bitrates, reserved sample rate
+        assert!(FrameHeader::parse(&[0xFF, 0xEB, 0x90, 0x64]).is_none());
+        assert!(FrameHeader::parse(&[0xFF, 0xFB, 0x00, 0x64]).is_none());
+        assert!(FrameHeader::parse(&[0xFF, 0xFB, 0xF0, 0x64]).is_none());
+        assert!(FrameHeader::parse(&[0xFF, 0xFB, 0x9C, 0x64]).is_none());
+        assert!(FrameHeader::parse(&HEADER[..3]).is_none());
+    }
+
+    #[test]
+    fn constant_bitrate_duration_comes_from_the_size() {
+        let mut data = HEADER.to_vec();
+        data.resize(16_000, 0);
+        let result = probe_bytes(&data).unwrap();
+        assert_eq!(result.duration, Some(1.0));
+        assert_eq!(result.bitrate, Some(128));
+    }
+
+    #[test]
+    fn skips_id3v2_tags() {
+        // A 20-byte tag as a syncsafe size, holding something that looks like a frame
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x14".to_vec();
+        data.extend_from_slice(&[0xFF, 0xFB, 0x50, 0x64]);
+        data.resize(30, 0);
+        data.extend_from_slice(&HEADER);
+        data.resize(16_030, 0);
+        let result = probe_bytes(&data).unwrap();
+        assert_eq!(result.duration, Some(1.0));
+    }
+
+    #[test]
+    fn xing_frame_count_gives_the_duration() {
+        let mut fields = 1u32.to_be_bytes().to_vec(); // frame count present
+        fields.extend_from_slice(&100u32.to_be_bytes());
+        let data = frame_with(b"Xing", &fields);
+        let duration = probe_bytes(&data).unwrap().duration.unwrap();
+        assert!((duration - 100.0 * 1152.0 / 44100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn vbri_frame_count_gives_the_duration() {
+        let mut fields = vec![0; 10];
+        fields.extend_from_slice(&200u32.to_be_bytes());
+        let data = frame_with(b"VBRI", &fields);
+        let duration = probe_bytes(&data).unwrap().duration.unwrap();
+        assert!((duration - 200.0 * 1152.0 / 44100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn truncated_xing_header_is_none() {
+        let mut data = HEADER.to_vec();
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(b"Xing\x00");
+        assert!(probe_bytes(&data).is_none());
+    }
+
+    #[test]
+    fn malformed_input_is_none() {
+        assert!(probe_bytes(b"").is_none());
+        assert!(probe_bytes(&[0xFF, 0xFB, 0x90]).is_none());
+        assert!(probe_bytes(&[0; 4096]).is_none());
+        // A tag claiming to run past the end of the file
+        assert!(probe_bytes(b"ID3\x04\x00\x00\x7F\x7F\x7F\x7F").is_none());
+    }
+}
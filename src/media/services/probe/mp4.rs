@@ -0,0 +1,285 @@
+use std::fs::File;
+
+use super::{Bytes, ProbeResult, Track, TrackKind, language, read_at};
+
+pub fn probe(file: &mut File, size: u64) -> Option<ProbeResult> {
+    let (moov, brand) = read_moov(file, size)?;
+    let container = if brand.as_deref() == Some(b"qt  ") {
+        "mov"
+    } else {
+        "mp4"
+    };
+    Some(parse_moov(&moov, container))
+}
+
+// Walks the top-level boxes to `moov`, which may sit before or after `mdat`,
+// and returns its body along with the major brand from `ftyp`. Sizes come
+// from the file, so every step must move forward without overflowing
+pub(crate) fn read_moov(file: &mut File, size: u64) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut offset: u64 = 0;
+    let mut brand = None;
+    while offset.checked_add(8)? <= size {
+        let header = read_at(file, offset, 16.min(size - offset))?;
+        let mut bytes = Bytes::new(&header);
+        let box_size = bytes.u32()? as u64;
+        let name: [u8; 4] = bytes.take(4)?.try_into().ok()?;
+        let (header_len, box_size) = match box_size {
+            0 => (8, size - offset),
+            1 => (16, bytes.u64()?),
+            box_size => (8, box_size),
+        };
+        if box_size < header_len {
+            return None;
+        }
+        match &name {
+            b"ftyp" => brand = Some(bytes.take(4)?.to_vec()),
+            b"moov" => {
+                let moov = read_at(file, offset + header_len, box_size - header_len)?;
+                return Some((moov, brand));
+            }
+            _ => {}
+        }
+        let next = offset.checked_add(box_size)?;
+        if next <= offset {
+            return None;
+        }
+        offset = next;
+    }
+    None
+}
+
+// Child boxes of a container box held in memory
+pub(crate) struct Atoms<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Atoms<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = Bytes::new(self.data);
+        let size = bytes.u32()? as usize;
+        let name: [u8; 4] = bytes.take(4)?.try_into().ok()?;
+        let (header_len, size) = match size {
+            0 => (8, self.data.len()),
+            1 => (16, bytes.u64()? as usize),
+            size => (8, size),
+        };
+        let body = self.data.get(header_len..size)?;
+        self.data = &self.data[size..];
+        Some((name, body))
+    }
+}
+
+pub(crate) fn atoms(data: &[u8]) -> Atoms<'_> {
+    Atoms { data }
+}
+
+pub(crate) fn child<'a>(data: &'a [u8], name: &[u8; 4]) -> Option<&'a [u8]> {
+    atoms(data).find(|(n, _)| n == name).map(|(_, body)| body)
+}
+
+fn parse_moov(moov: &[u8], container: &str) -> ProbeResult {
+    let mut result = ProbeResult::new(container);
+    for (name, body) in atoms(moov) {
+        match &name {
+            b"mvhd" => result.duration = parse_mvhd(body),
+            b"trak" => result.tracks.extend(parse_trak(body)),
+            _ => {}
+        }
+    }
+    result
+}
+
+fn parse_mvhd(body: &[u8]) -> Option<f64> {
+    let mut bytes = Bytes::new(body);
+    let version = bytes.u8()?;
+    bytes.skip(3)?;
+    let (timescale, duration) = if version == 1 {
+        bytes.skip(16)?;
+        (bytes.u32()?, bytes.u64()?)
+    } else {
+        bytes.skip(8)?;
+        (bytes.u32()?, bytes.u32()? as u64)
+    };
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+fn parse_trak(trak: &[u8]) -> Option<Track> {
+    let mdia = child(trak, b"mdia")?;
+    let handler = child(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12))?;
+    let kind = match handler {
+        b"vide" => TrackKind::Video,
+        b"soun" => TrackKind::Audio,
+        b"sbtl" | b"subt" | b"text" | b"clcp" => TrackKind::Subtitle,
+        _ => return None,
+    };
+    let stsd = child(mdia, b"minf")
+        .and_then(|minf| child(minf, b"stbl"))
+        .and_then(|stbl| child(stbl, b"stsd"))
+        .and_then(|stsd| stsd.get(8..)); // version, flags and entry count
+    let entry = stsd.and_then(|stsd| atoms(stsd).next());
+
+    let mut track = Track::new(kind, entry.map(|(format, _)| codec(&format)));
+    track.language = child(mdia, b"mdhd").and_then(parse_mdhd_language);
+    if let Some(tkhd) = child(trak, b"tkhd") {
+        let mut bytes = Bytes::new(tkhd);
+        // Bit 0 of the flags is "enabled", the closest MP4 has to a default flag
+        track.default = bytes.u32().is_some_and(|flags| flags & 1 != 0);
+    }
+    if let Some((_, body)) = entry {
+        let mut bytes = Bytes::new(body);
+        match kind {
+            TrackKind::Video => {
+                bytes.skip(24);
+                track.width = bytes.u16().map(u32::from).filter(|w| *w > 0);
+                track.height = bytes.u16().map(u32::from).filter(|h| *h > 0);
+            }
+            TrackKind::Audio => {
+                bytes.skip(16);
+                track.channels = bytes.u16().map(u32::from);
+                bytes.skip(6);
+                track.sample_rate = bytes.u32().map(|rate| rate >> 16); // 16.16 fixed point
+            }
+            TrackKind::Subtitle => {}
+        }
+    }
+    Some(track)
+}
+
+// Three letters packed as 5-bit values offset by 0x60
+fn parse_mdhd_language(mdhd: &[u8]) -> Option<String> {
+    let mut bytes = Bytes::new(mdhd);
+    let version = bytes.u8()?;
+    bytes.skip(3 + if version == 1 { 28 } else { 16 })?;
+    let packed = bytes.u16()?;
+    let letters: String = [10, 5, 0]
+        .iter()
+        .map(|shift| char::from((((packed >> shift) & 0x1F) as u8) + 0x60))
+        .collect();
+    language(&letters).filter(|letters| letters.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+fn codec(format: &[u8; 4]) -> String {
+    let codec = match format {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"av01" => "av1",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"mp4v" => "mpeg4",
+        b"mp4a" => "aac",
+        b".mp3" => "mp3",
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b"alac" => "alac",
+        b"lpcm" | b"sowt" | b"twos" => "pcm",
+        b"tx3g" => "mov_text",
+        b"wvtt" => "vtt",
+        b"stpp" => "ttml",
+        b"c608" => "eia_608",
+        other => {
+            return String::from_utf8_lossy(other).trim().to_lowercase();
+        }
+    };
+    codec.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn boxed(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn mvhd(timescale: u32, duration: u32) -> Vec<u8> {
+        let mut body = vec![0; 12]; // version, flags, creation and modification times
+        body.extend_from_slice(&timescale.to_be_bytes());
+        body.extend_from_slice(&duration.to_be_bytes());
+        boxed(b"mvhd", &body)
+    }
+
+    fn read(data: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        read_moov(&mut fixtures::file(data), data.len() as u64)
+    }
+
+    #[test]
+    fn finds_moov_after_mdat() {
+        let mut data = boxed(b"ftyp", b"isom\0\0\0\0");
+        data.extend(boxed(b"mdat", &[0; 100]));
+        data.extend(boxed(b"moov", &mvhd(1000, 2500)));
+
+        let (moov, brand) = read(&data).unwrap();
+        assert_eq!(brand.as_deref(), Some(&b"isom"[..]));
+        let result = parse_moov(&moov, "mp4");
+        assert_eq!(result.duration, Some(2.5));
+    }
+
+    #[test]
+    fn truncated_moov_is_none() {
+        let mut data = boxed(b"ftyp", b"isom\0\0\0\0");
+        let mut moov = boxed(b"moov", &mvhd(1000, 2500));
+        moov.truncate(20);
+        data.extend(moov);
+        assert!(read(&data).is_none());
+    }
+
+    #[test]
+    fn box_smaller_than_its_header_is_none() {
+        let mut data = boxed(b"ftyp", b"isom\0\0\0\0");
+        data.extend_from_slice(&[0, 0, 0, 4]);
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&[0; 8]);
+        assert!(read(&data).is_none());
+    }
+
+    #[test]
+    fn overflowing_largesize_is_none() {
+        let mut data = boxed(b"ftyp", b"isom\0\0\0\0");
+        // A 64-bit size that would wrap the offset back to the start of the file
+        let wrap = u64::MAX - data.len() as u64 + 1;
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&wrap.to_be_bytes());
+        data.extend(boxed(b"moov", &mvhd(1000, 2500)));
+        assert!(read(&data).is_none());
+
+        let mut data = boxed(b"ftyp", b"isom\0\0\0\0");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"free");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(read(&data).is_none());
+    }
+
+    #[test]
+    fn atoms_stop_at_malformed_children() {
+        let mut data = boxed(b"udta", b"ab");
+        data.extend_from_slice(&[0, 0, 0, 2]); // smaller than a header
+        data.extend_from_slice(b"meta");
+        assert_eq!(atoms(&data).count(), 1);
+
+        let mut data = 100u32.to_be_bytes().to_vec(); // larger than what's left
+        data.extend_from_slice(b"meta");
+        data.extend_from_slice(&[0; 8]);
+        assert_eq!(atoms(&data).count(), 0);
+
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend_from_slice(b"meta");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(atoms(&data).count(), 0);
+    }
+
+    #[test]
+    fn mvhd_without_timescale_has_no_duration() {
+        let mvhd = mvhd(0, 2500);
+        assert_eq!(parse_mvhd(&mvhd[8..]), None);
+        assert_eq!(parse_mvhd(&mvhd[8..16]), None);
+    }
+}
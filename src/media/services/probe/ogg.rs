@@ -0,0 +1,245 @@
+use std::fs::File;
+
+use super::{Bytes, ProbeResult, Track, TrackKind, flac, read_at};
+
+const HEAD_WINDOW: u64 = 64 * 1024; // holds the beginning-of-stream pages
+const TAIL_WINDOW: u64 = 64 * 1024; // holds the last page of every stream
+
+struct Page<'a> {
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    body: &'a [u8],
+}
+
+struct Stream {
+    serial: u32,
+    granule_rate: f64, // granule units per second
+    pre_skip: u64,     // Opus only
+}
+
+// Identifies each logical stream from its first packet, then takes the duration
+// from the granule position of the last page of the first audio stream
+pub fn probe(file: &mut File, size: u64) -> Option<ProbeResult> {
+    let head = read_at(file, 0, HEAD_WINDOW.min(size))?;
+    let mut result = ProbeResult::new("ogg");
+    let mut streams = Vec::new();
+    for page in pages(&head) {
+        // Beginning-of-stream pages all come first
+        if page.flags & 0x02 == 0 {
+            break;
+        }
+        if let Some((track, stream)) = identify(page.serial, page.body) {
+            if let Some(stream) = stream {
+                streams.push(stream);
+            }
+            result.tracks.push(track);
+        }
+    }
+    if result.tracks.is_empty() {
+        return None;
+    }
+
+    let tail_start = size.saturating_sub(TAIL_WINDOW);
+    let tail = read_at(file, tail_start, size - tail_start)?;
+    if let Some(stream) = streams.first() {
+        let last = pages(&tail)
+            .filter(|page| page.serial == stream.serial && page.granule != u64::MAX)
+            .last();
+        if let Some(page) = last {
+            let samples = page.granule.saturating_sub(stream.pre_skip);
+            result.duration = Some(samples as f64 / stream.granule_rate);
+        }
+    }
+    Some(result)
+}
+
+fn identify(serial: u32, packet: &[u8]) -> Option<(Track, Option<Stream>)> {
+    let mut bytes = Bytes::new(packet);
+    if packet.starts_with(b"OpusHead") {
+        bytes.skip(9)?;
+        let mut track = Track::new(TrackKind::Audio, Some("opus".to_string()));
+        track.channels = Some(bytes.u8()? as u32);
+        let pre_skip = bytes.u16_le()? as u64;
+        track.sample_rate = Some(bytes.u32_le()?).filter(|rate| *rate > 0);
+        track.default = true;
+        // Opus granules always count 48 kHz samples, whatever the input rate was
+        let stream = Stream {
+            serial,
+            granule_rate: 48000.0,
+            pre_skip,
+        };
+        Some((track, Some(stream)))
+    } else if packet.starts_with(b"\x01vorbis") {
+        bytes.skip(11)?;
+        let mut track = Track::new(TrackKind::Audio, Some("vorbis".to_string()));
+        track.channels = Some(bytes.u8()? as u32);
+        let sample_rate = bytes.u32_le()?;
+        track.sample_rate = Some(sample_rate);
+        track.default = true;
+        let stream = (sample_rate > 0).then_some(Stream {
+            serial,
+            granule_rate: sample_rate as f64,
+            pre_skip: 0,
+        });
+        Some((track, stream))
+    } else if packet.starts_with(b"\x7fFLAC") {
+        // Mapping header, then "fLaC" and the STREAMINFO block with its header
+        let info = packet.get(13 + 4..)?;
+        let mut track = Track::new(TrackKind::Audio, Some("flac".to_string()));
+        let mut scratch = ProbeResult::new("ogg");
+        flac::streaminfo(info, &mut scratch, &mut track)?;
+        track.default = true;
+        let stream = track.sample_rate.map(|rate| Stream {
+            serial,
+            granule_rate: rate as f64,
+            pre_skip: 0,
+        });
+        Some((track, stream))
+    } else if packet.starts_with(b"\x80theora") {
+        bytes.skip(10)?;
+        let mut track = Track::new(TrackKind::Video, Some("theora".to_string()));
+        // Frame size in macroblocks, then the picture size in pixels
+        bytes.skip(4)?;
+        let picture = bytes.take(6)?;
+        track.width = Some(u32::from_be_bytes([0, picture[0], picture[1], picture[2]]));
+        track.height = Some(u32::from_be_bytes([0, picture[3], picture[4], picture[5]]));
+        track.default = true;
+        Some((track, None))
+    } else {
+        None
+    }
+}
+
+// Pages in a window of the file; a window may start or end mid-page, so this
+// resynchronises on the capture pattern
+fn pages(data: &[u8]) -> impl Iterator<Item = Page<'_>> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        loop {
+            let start = pos + data.get(pos..)?.windows(4).position(|w| w == b"OggS")?;
+            let page = parse_page(&data[start..]);
+            match page {
+                Some((page, len)) => {
+                    pos = start + len;
+                    return Some(page);
+                }
+                None => pos = start + 4,
+            }
+        }
+    })
+}
+
+fn parse_page(data: &[u8]) -> Option<(Page<'_>, usize)> {
+    let mut bytes = Bytes::new(data);
+    bytes.skip(4)?; // capture pattern
+    if bytes.u8()? != 0 {
+        return None;
+    }
+    let flags = bytes.u8()?;
+    let granule = u64::from_le_bytes(bytes.take(8)?.try_into().ok()?);
+    let serial = bytes.u32_le()?;
+    bytes.skip(8)?; // sequence number and checksum
+    let segments = bytes.u8()? as usize;
+    let lacing = bytes.take(segments)?;
+    let body_len: usize = lacing.iter().map(|len| *len as usize).sum();
+    // Only the first packet matters here, and it ends at the first lacing value below 255
+    let body = bytes.take(body_len)?;
+    let first_packet = lacing
+        .iter()
+        .position(|len| *len < 255)
+        .map(|last| lacing[..=last].iter().map(|len| *len as usize).sum())
+        .unwrap_or(body_len);
+    Some((
+        Page {
+            flags,
+            granule,
+            serial,
+            body: &body[..first_packet],
+        },
+        27 + segments + body_len,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn page(flags: u8, granule: u64, serial: u32, lacing: &[u8]) -> Vec<u8> {
+        let mut data = b"OggS\0".to_vec();
+        data.push(flags);
+        data.extend_from_slice(&granule.to_le_bytes());
+        data.extend_from_slice(&serial.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.push(lacing.len() as u8);
+        data.extend_from_slice(lacing);
+        let body_len: usize = lacing.iter().map(|len| *len as usize).sum();
+        data.extend((0..body_len).map(|i| i as u8));
+        data
+    }
+
+    fn opus_head(pre_skip: u16) -> Vec<u8> {
+        let mut packet = b"OpusHead\x01\x02".to_vec();
+        packet.extend_from_slice(&pre_skip.to_le_bytes());
+        packet.extend_from_slice(&48000u32.to_le_bytes());
+        packet.extend_from_slice(&[0, 0, 0]);
+        packet
+    }
+
+    #[test]
+    fn first_packet_ends_at_the_first_short_lacing_value() {
+        let data = page(0, 0, 1, &[255, 10, 20]);
+        let (page, len) = parse_page(&data).unwrap();
+        assert_eq!(len, 27 + 3 + 285);
+        assert_eq!(page.body.len(), 265);
+
+        // A packet continued on the next page fills the whole body
+        let data = page_continued();
+        let (page, _) = parse_page(&data).unwrap();
+        assert_eq!(page.body.len(), 510);
+    }
+
+    fn page_continued() -> Vec<u8> {
+        page(0, 0, 1, &[255, 255])
+    }
+
+    #[test]
+    fn truncated_pages_are_skipped() {
+        let mut data = page(0, 0, 1, &[255, 10]);
+        data.truncate(100);
+        assert!(parse_page(&data).is_none());
+        assert_eq!(pages(&data).count(), 0);
+
+        // A bad version resynchronises on the next capture pattern
+        let mut data = b"OggS\x01junkOggS".to_vec();
+        data.extend(page(0, 7, 2, &[4]));
+        let found: Vec<u64> = pages(&data).map(|page| page.granule).collect();
+        assert_eq!(found, vec![7]);
+    }
+
+    #[test]
+    fn opus_duration_subtracts_the_pre_skip() {
+        let head = opus_head(312);
+        let mut data = page(0x02, 0, 9, &[head.len() as u8]);
+        let body_start = data.len() - head.len();
+        data[body_start..].copy_from_slice(&head);
+        data.extend(page(0x04, 48_000 + 312, 9, &[10]));
+
+        let result = probe(&mut fixtures::file(&data), data.len() as u64).unwrap();
+        assert_eq!(result.duration, Some(1.0));
+        assert_eq!(result.tracks[0].codec.as_deref(), Some("opus"));
+        assert_eq!(result.tracks[0].channels, Some(2));
+    }
+
+    #[test]
+    fn unknown_or_truncated_streams_are_none() {
+        let data = page(0x02, 0, 1, &[20]);
+        assert!(probe(&mut fixtures::file(&data), data.len() as u64).is_none());
+
+        let mut data = page(0x02, 0, 1, &[9]);
+        let body_start = data.len() - 9;
+        data[body_start..].copy_from_slice(b"OpusHead\x01");
+        assert!(probe(&mut fixtures::file(&data), data.len() as u64).is_none());
+    }
+}
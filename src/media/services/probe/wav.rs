@@ -0,0 +1,108 @@
+use std::fs::File;
+
+use super::{Bytes, ProbeResult, Track, TrackKind, read_at};
+
+// Walks the RIFF chunks for "fmt " and the size of "data"
+pub fn probe(file: &mut File) -> Option<ProbeResult> {
+    let size = file.metadata().ok()?.len();
+    let mut offset = 12;
+    let mut format = None;
+    let mut data_len = None;
+    while offset + 8 <= size && (format.is_none() || data_len.is_none()) {
+        let header = read_at(file, offset, 8)?;
+        let mut bytes = Bytes::new(&header);
+        let id = bytes.take(4)?;
+        let len = bytes.u32_le()? as u64;
+        match id {
+            b"fmt " => format = Some(read_at(file, offset + 8, len.min(40))?),
+            // Streams written on the fly leave the size at 0 or 0xFFFFFFFF
+            b"data" => data_len = Some(len.min(size - offset - 8)),
+            _ => {}
+        }
+        offset += 8 + len + (len & 1); // chunks are padded to an even length
+    }
+
+    let format = format?;
+    let mut bytes = Bytes::new(&format);
+    let codec = match bytes.u16_le()? {
+        0x0001 | 0x0003 | 0xFFFE => "pcm".to_string(),
+        0x0055 => "mp3".to_string(),
+        0x0006 => "alaw".to_string(),
+        0x0007 => "mulaw".to_string(),
+        0x0011 => "adpcm_ima".to_string(),
+        other => format!("wav_{:#06x}", other),
+    };
+    let channels = bytes.u16_le()? as u32;
+    let sample_rate = bytes.u32_le()?;
+    let byte_rate = bytes.u32_le()?;
+
+    let mut result = ProbeResult::new("wav");
+    if byte_rate > 0 {
+        result.bitrate = Some((byte_rate as u64 * 8 / 1000) as u32);
+        result.duration = data_len.map(|len| len as f64 / byte_rate as f64);
+    }
+    let mut track = Track::new(TrackKind::Audio, Some(codec));
+    track.channels = Some(channels);
+    track.sample_rate = Some(sample_rate);
+    track.default = true;
+    result.tracks.push(track);
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn chunk(id: &[u8; 4], len: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn fmt(byte_rate: u32) -> Vec<u8> {
+        let mut body = 1u16.to_le_bytes().to_vec(); // PCM
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&44100u32.to_le_bytes());
+        body.extend_from_slice(&byte_rate.to_le_bytes());
+        body.extend_from_slice(&[4, 0, 16, 0]);
+        chunk(b"fmt ", 16, &body)
+    }
+
+    fn wav(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WAVE".to_vec();
+        for chunk in chunks {
+            data.extend_from_slice(chunk);
+        }
+        data
+    }
+
+    #[test]
+    fn duration_comes_from_the_data_chunk() {
+        let data = wav(&[
+            chunk(b"LIST", 3, &[0; 4]), // odd lengths are padded
+            fmt(176_400),
+            chunk(b"data", 17_640, &[0; 17_640]),
+        ]);
+        let result = probe(&mut fixtures::file(&data)).unwrap();
+        assert_eq!(result.duration, Some(0.1));
+        assert_eq!(result.bitrate, Some(1411));
+        assert_eq!(result.tracks[0].codec.as_deref(), Some("pcm"));
+    }
+
+    #[test]
+    fn streamed_data_sizes_are_clamped_to_the_file() {
+        let data = wav(&[fmt(u32::MAX), chunk(b"data", u32::MAX, &[0; 100])]);
+        let result = probe(&mut fixtures::file(&data)).unwrap();
+        assert_eq!(result.duration, Some(100.0 / u32::MAX as f64));
+    }
+
+    #[test]
+    fn missing_or_truncated_fmt_is_none() {
+        let data = wav(&[chunk(b"data", 4, &[0; 4])]);
+        assert!(probe(&mut fixtures::file(&data)).is_none());
+        let data = wav(&[chunk(b"fmt ", 16, &[1, 0, 2])]);
+        assert!(probe(&mut fixtures::file(&data)).is_none());
+    }
+}
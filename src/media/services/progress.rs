@@ -0,0 +1,203 @@
+use chrono::{NaiveDateTime, Utc};
+use entity::{history, media, media_metadata};
+use sea_orm::{
+    ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+    sea_query::OnConflict,
+};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{errors::AppError, state::AppState};
+
+#[derive(Debug, Serialize)]
+pub struct ContinueWatchingItem {
+    pub media_id: Uuid,
+    pub title: String,
+    pub media_type: String,
+    pub playback_position: i64, // in seconds
+    pub duration: Option<i64>,  // in seconds
+    pub progress_percent: Option<f64>,
+    pub last_played_at: NaiveDateTime,
+}
+
+/// Reads and writes per-profile playback progress in `history`.
+pub struct ProgressTracker;
+
+impl ProgressTracker {
+    /// Records a playback position, marking the item watched past the threshold.
+    pub async fn report(
+        state: &AppState,
+        profile_id: Uuid,
+        media_id: Uuid,
+        position: f64,
+        duration: Option<f64>,
+    ) -> Result<history::Model, AppError> {
+        let media = media::Entity::find_by_id(media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+
+        let existing = Self::find(state, profile_id, media_id).await?;
+        let duration = match duration.filter(|d| *d > 0.0) {
+            Some(d) => Some(d as i64),
+            None => match existing.as_ref().and_then(|e| e.duration) {
+                Some(d) => Some(d),
+                None => Self::media_duration(state, &media).await?,
+            },
+        };
+
+        let position = position.max(0.0) as i64;
+        let percent = duration
+            .filter(|d| *d > 0)
+            .map(|d| position as f64 / d as f64 * 100.0);
+
+        let (playback_position, watched) = match percent {
+            // Finished items restart from the beginning next time
+            Some(p) if p >= state.watched_threshold => (0, true),
+            // Barely started items are not worth resuming
+            Some(p) if p < state.resume_threshold => {
+                (0, existing.as_ref().is_some_and(|e| e.watched))
+            }
+            _ => (position, existing.as_ref().is_some_and(|e| e.watched)),
+        };
+
+        Self::upsert(state, existing, profile_id, media_id, |entry| {
+            entry.playback_position = Set(playback_position);
+            entry.watched = Set(watched);
+            entry.duration = Set(duration);
+        })
+        .await
+    }
+
+    pub async fn set_watched(
+        state: &AppState,
+        profile_id: Uuid,
+        media_id: Uuid,
+        watched: bool,
+    ) -> Result<history::Model, AppError> {
+        media::Entity::find_by_id(media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+
+        let existing = Self::find(state, profile_id, media_id).await?;
+        Self::upsert(state, existing, profile_id, media_id, |entry| {
+            entry.playback_position = Set(0);
+            entry.watched = Set(watched);
+        })
+        .await
+    }
+
+    /// Where playback should resume, if the profile left the item part-way.
+    pub async fn resume_position(
+        state: &AppState,
+        profile_id: Uuid,
+        media_id: Uuid,
+    ) -> Result<Option<f64>, AppError> {
+        Ok(Self::find(state, profile_id, media_id)
+            .await?
+            .filter(|entry| !entry.watched && entry.playback_position > 0)
+            .map(|entry| entry.playback_position as f64))
+    }
+
+    pub async fn continue_watching(
+        state: &AppState,
+        profile_id: Uuid,
+        limit: u64,
+    ) -> Result<Vec<ContinueWatchingItem>, AppError> {
+        let entries = history::Entity::find()
+            .filter(history::Column::ProfileId.eq(profile_id))
+            .filter(history::Column::Watched.eq(false))
+            .filter(history::Column::PlaybackPosition.gt(0))
+            .order_by_desc(history::Column::LastPlayedAt)
+            .limit(limit)
+            .find_also_related(media::Entity)
+            .all(&state.conn)
+            .await?;
+
+        Ok(entries
+            .into_iter()
+            .filter_map(|(entry, media)| {
+                let media = media?;
+                Some(ContinueWatchingItem {
+                    media_id: media.id,
+                    title: media.title,
+                    media_type: media.media_type,
+                    playback_position: entry.playback_position,
+                    duration: entry.duration,
+                    progress_percent: entry
+                        .duration
+                        .filter(|d| *d > 0)
+                        .map(|d| entry.playback_position as f64 / d as f64 * 100.0),
+                    last_played_at: entry.last_played_at,
+                })
+            })
+            .collect())
+    }
+
+    async fn find(
+        state: &AppState,
+        profile_id: Uuid,
+        media_id: Uuid,
+    ) -> Result<Option<history::Model>, AppError> {
+        Ok(history::Entity::find()
+            .filter(history::Column::ProfileId.eq(profile_id))
+            .filter(history::Column::MediaId.eq(media_id))
+            .one(&state.conn)
+            .await?)
+    }
+
+    // Concurrent reports, heartbeats and party saves for one item all land on
+    // its single row instead of racing to insert
+    async fn upsert(
+        state: &AppState,
+        existing: Option<history::Model>,
+        profile_id: Uuid,
+        media_id: Uuid,
+        apply: impl FnOnce(&mut history::ActiveModel),
+    ) -> Result<history::Model, AppError> {
+        let mut entry = history::ActiveModel {
+            id: Set(existing.as_ref().map_or_else(Uuid::new_v4, |e| e.id)),
+            profile_id: Set(profile_id),
+            media_id: Set(media_id),
+            playback_position: Set(existing.as_ref().map_or(0, |e| e.playback_position)),
+            last_played_at: Set(Utc::now().naive_utc()),
+            watched: Set(existing.as_ref().is_some_and(|e| e.watched)),
+            duration: Set(existing.as_ref().and_then(|e| e.duration)),
+        };
+        apply(&mut entry);
+
+        Ok(history::Entity::insert(entry)
+            .on_conflict(
+                OnConflict::columns([history::Column::ProfileId, history::Column::MediaId])
+                    .update_columns([
+                        history::Column::PlaybackPosition,
+                        history::Column::LastPlayedAt,
+                        history::Column::Watched,
+                        history::Column::Duration,
+                    ])
+                    .to_owned(),
+            )
+            .exec_with_returning(&state.conn)
+            .await?)
+    }
+
+    // Fall back to what the scanner found when the client didn't send a duration
+    async fn media_duration(
+        state: &AppState,
+        media: &media::Model,
+    ) -> Result<Option<i64>, AppError> {
+        if let Some(duration) = media.duration {
+            return Ok(Some(duration as i64));
+        }
+        // Rows scanned before the duration column only have it in metadata
+        Ok(media_metadata::Entity::find()
+            .filter(media_metadata::Column::MediaId.eq(media.id))
+            .one(&state.conn)
+            .await?
+            .and_then(|m| m.metadata)
+            .and_then(|m| m.get("duration").and_then(Value::as_f64))
+            .map(|d| d as i64))
+    }
+}
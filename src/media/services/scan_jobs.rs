@@ -0,0 +1,481 @@
+use chrono::{NaiveDateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+use strum_macros::{Display, EnumString};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    media::services::scanner::{MediaScanner, ScanReport},
+    state::AppState,
+};
+
+const MAX_ERROR_DETAILS: usize = 1000; // further errors are only counted
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ScanStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ScanTrigger {
+    Manual,
+    Watch,
+    Periodic,
+}
+
+/// Which part of a library a scan covers.
+pub enum ScanScope {
+    Full,
+    Paths(Vec<PathBuf>), // files or directories reported by the watcher
+}
+
+/// A file the scan couldn't process; the rest of the scan carries on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanFileError {
+    pub path: String,
+    pub message: String,
+}
+
+/// A scan as seen by clients, live while it runs and from history afterwards.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanProgress {
+    pub job_id: Uuid,
+    pub library_id: Uuid,
+    pub status: ScanStatus,
+    pub trigger: ScanTrigger,
+    pub files_seen: usize,
+    pub files_processed: usize,
+    pub errors: usize,
+    #[serde(flatten)]
+    pub report: ScanReport,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub eta_secs: Option<u64>, // known once the walk has counted every file
+    pub message: Option<String>,
+}
+
+/// Handed to the scanner to report progress and check for cancellation.
+pub struct ScanMonitor {
+    job_id: Uuid,
+    library_id: Uuid,
+    progress: watch::Sender<ScanProgress>,
+    cancel: CancellationToken,
+    error_details: Mutex<Vec<ScanFileError>>,
+    counted: AtomicBool,
+    started: Instant,
+}
+
+impl ScanMonitor {
+    fn new(library_id: Uuid, trigger: ScanTrigger) -> Self {
+        let job_id = Uuid::new_v4();
+        let (progress, _) = watch::channel(ScanProgress {
+            job_id,
+            library_id,
+            status: ScanStatus::Running,
+            trigger,
+            files_seen: 0,
+            files_processed: 0,
+            errors: 0,
+            report: ScanReport::default(),
+            started_at: Utc::now().naive_utc(),
+            finished_at: None,
+            eta_secs: None,
+            message: None,
+        });
+        ScanMonitor {
+            job_id,
+            library_id,
+            progress,
+            cancel: CancellationToken::new(),
+            error_details: Mutex::new(Vec::new()),
+            counted: AtomicBool::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Counts files found by the walk.
+    pub fn saw(&self, files: usize) {
+        self.progress
+            .send_modify(|progress| progress.files_seen += files);
+    }
+
+    /// Marks the walk as done, so `files_seen` is the total from here on.
+    pub fn counted(&self) {
+        self.counted.store(true, Ordering::Relaxed);
+    }
+
+    /// Records one more file handled, successfully or not.
+    pub fn processed(&self, report: &ScanReport) {
+        let counted = self.counted.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed();
+        self.progress.send_modify(|progress| {
+            progress.files_processed += 1;
+            progress.report = report.clone();
+            let remaining = progress.files_seen.saturating_sub(progress.files_processed);
+            progress.eta_secs = counted.then(|| {
+                (elapsed.as_secs_f64() * remaining as f64 / progress.files_processed as f64) as u64
+            });
+        });
+    }
+
+    pub fn failed(&self, path: &Path, error: &AppError) {
+        tracing::warn!("failed to scan {}: {}", path.display(), error);
+        let mut details = self.error_details.lock().unwrap();
+        if details.len() < MAX_ERROR_DETAILS {
+            details.push(ScanFileError {
+                path: path.display().to_string(),
+                message: error.to_string(),
+            });
+        }
+        self.progress.send_modify(|progress| progress.errors += 1);
+    }
+
+    pub fn snapshot(&self) -> ScanProgress {
+        self.progress.borrow().clone()
+    }
+
+    pub fn error_details(&self) -> Vec<ScanFileError> {
+        self.error_details.lock().unwrap().clone()
+    }
+
+    fn finish(&self, result: &Result<ScanReport, AppError>) -> ScanProgress {
+        let cancelled = self.is_cancelled();
+        self.progress.send_modify(|progress| {
+            progress.finished_at = Some(Utc::now().naive_utc());
+            progress.eta_secs = None;
+            match result {
+                Ok(report) => {
+                    progress.report = report.clone();
+                    progress.status = if cancelled {
+                        ScanStatus::Cancelled
+                    } else {
+                        ScanStatus::Completed
+                    };
+                }
+                Err(e) => {
+                    progress.status = ScanStatus::Failed;
+                    progress.message = Some(e.to_string());
+                }
+            }
+        });
+        self.snapshot()
+    }
+}
+
+/// Scans currently running, at most one per library.
+#[derive(Clone, Default)]
+pub struct ScanJobs {
+    running: Arc<Mutex<HashMap<Uuid, Arc<ScanMonitor>>>>, // by library
+}
+
+impl ScanJobs {
+    pub fn progress(&self, job_id: Uuid) -> Option<ScanProgress> {
+        self.find(job_id).map(|monitor| monitor.snapshot())
+    }
+
+    pub fn subscribe(&self, job_id: Uuid) -> Option<watch::Receiver<ScanProgress>> {
+        self.find(job_id)
+            .map(|monitor| monitor.progress.subscribe())
+    }
+
+    /// Asks a running scan to stop after the file it's on; returns whether it was running.
+    pub fn cancel(&self, job_id: Uuid) -> bool {
+        match self.find(job_id) {
+            Some(monitor) => {
+                monitor.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find(&self, job_id: Uuid) -> Option<Arc<ScanMonitor>> {
+        self.running
+            .lock()
+            .unwrap()
+            .values()
+            .find(|monitor| monitor.job_id == job_id)
+            .cloned()
+    }
+
+    fn claim(&self, monitor: Arc<ScanMonitor>) -> Result<(), AppError> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(&monitor.library_id) {
+            return Err(AppError::Conflict(
+                "A scan of this library is already running".to_string(),
+            ));
+        }
+        running.insert(monitor.library_id, monitor);
+        Ok(())
+    }
+
+    fn release(&self, library_id: Uuid) {
+        self.running.lock().unwrap().remove(&library_id);
+    }
+}
+
+/// Runs library scans as jobs with progress, cancellation and a history.
+pub struct ScanJobService;
+
+impl ScanJobService {
+    /// Starts a full scan of the library in the background.
+    pub async fn start(
+        state: AppState,
+        library_id: Uuid,
+        trigger: ScanTrigger,
+    ) -> Result<ScanProgress, AppError> {
+        let library = entity::library::Entity::find_by_id(library_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let monitor = Self::begin(&state, library.id, trigger, true).await?;
+        let progress = monitor.snapshot();
+        tokio::spawn(async move {
+            let _ = Self::execute(state, monitor, library.path, ScanScope::Full, true).await;
+        });
+        Ok(progress)
+    }
+
+    /// Runs a scan in the calling task. Targeted scans only make it into the
+    /// history when they changed something or ran into errors.
+    pub async fn run(
+        state: AppState,
+        library_id: Uuid,
+        library_path: String,
+        trigger: ScanTrigger,
+        scope: ScanScope,
+    ) -> Result<ScanReport, AppError> {
+        let recorded = matches!(scope, ScanScope::Full);
+        let monitor = Self::begin(&state, library_id, trigger, recorded).await?;
+        Self::execute(state, monitor, library_path, scope, recorded).await
+    }
+
+    pub async fn list(
+        state: &AppState,
+        library_id: Option<Uuid>,
+        limit: u64,
+    ) -> Result<Vec<ScanProgress>, AppError> {
+        let mut query = entity::scan_job::Entity::find();
+        if let Some(library_id) = library_id {
+            query = query.filter(entity::scan_job::Column::LibraryId.eq(library_id));
+        }
+        let jobs = query
+            .order_by_desc(entity::scan_job::Column::StartedAt)
+            .limit(limit)
+            .all(&state.conn)
+            .await?;
+        Ok(jobs
+            .iter()
+            .map(|job| {
+                state
+                    .scans
+                    .progress(job.id)
+                    .unwrap_or_else(|| Self::progress_of(job))
+            })
+            .collect())
+    }
+
+    /// A job with its per-file errors, live if it's still running.
+    pub async fn get(
+        state: &AppState,
+        job_id: Uuid,
+    ) -> Result<(ScanProgress, Vec<ScanFileError>), AppError> {
+        if let Some(monitor) = state.scans.find(job_id) {
+            return Ok((monitor.snapshot(), monitor.error_details()));
+        }
+        let job = entity::scan_job::Entity::find_by_id(job_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let details = job
+            .error_details
+            .clone()
+            .and_then(|details| serde_json::from_value(details).ok())
+            .unwrap_or_default();
+        Ok((Self::progress_of(&job), details))
+    }
+
+    pub async fn cancel(state: &AppState, job_id: Uuid) -> Result<(), AppError> {
+        if state.scans.cancel(job_id) {
+            return Ok(());
+        }
+        match entity::scan_job::Entity::find_by_id(job_id)
+            .one(&state.conn)
+            .await?
+        {
+            Some(_) => Err(AppError::Conflict("The scan already finished".to_string())),
+            None => Err(AppError::NotFound),
+        }
+    }
+
+    /// Progress updates until the scan finishes; a finished job yields its final state once.
+    pub async fn events(
+        state: &AppState,
+        job_id: Uuid,
+    ) -> Result<BoxStream<'static, ScanProgress>, AppError> {
+        let Some(receiver) = state.scans.subscribe(job_id) else {
+            let (progress, _) = Self::get(state, job_id).await?;
+            return Ok(stream::once(async move { progress }).boxed());
+        };
+        Ok(stream::unfold(Some((receiver, true)), |next| async move {
+            let (mut receiver, first) = next?;
+            if !first && receiver.changed().await.is_err() {
+                return None;
+            }
+            let progress = receiver.borrow_and_update().clone();
+            let running = progress.status == ScanStatus::Running;
+            Some((progress, running.then_some((receiver, false))))
+        })
+        .boxed())
+    }
+
+    /// Jobs left running by a previous process didn't finish; mark them so.
+    pub async fn recover(state: &AppState) -> Result<(), AppError> {
+        entity::scan_job::Entity::update_many()
+            .col_expr(
+                entity::scan_job::Column::Status,
+                ScanStatus::Failed.to_string().into(),
+            )
+            .col_expr(
+                entity::scan_job::Column::Message,
+                "Interrupted by a server restart".into(),
+            )
+            .col_expr(
+                entity::scan_job::Column::FinishedAt,
+                Utc::now().naive_utc().into(),
+            )
+            .filter(entity::scan_job::Column::Status.eq(ScanStatus::Running.to_string()))
+            .exec(&state.conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn begin(
+        state: &AppState,
+        library_id: Uuid,
+        trigger: ScanTrigger,
+        recorded: bool,
+    ) -> Result<Arc<ScanMonitor>, AppError> {
+        let monitor = Arc::new(ScanMonitor::new(library_id, trigger));
+        state.scans.claim(monitor.clone())?;
+        if recorded {
+            let job = Self::active_model(&monitor.snapshot(), Vec::new());
+            if let Err(e) = job.insert(&state.conn).await {
+                state.scans.release(library_id);
+                return Err(e.into());
+            }
+        }
+        Ok(monitor)
+    }
+
+    async fn execute(
+        state: AppState,
+        monitor: Arc<ScanMonitor>,
+        library_path: String,
+        scope: ScanScope,
+        recorded: bool,
+    ) -> Result<ScanReport, AppError> {
+        let library_id = monitor.library_id;
+        let result = match scope {
+            ScanScope::Full => {
+                MediaScanner::scan_library(state.clone(), library_id, library_path, &monitor).await
+            }
+            ScanScope::Paths(paths) => {
+                MediaScanner::scan_paths(state.clone(), library_id, library_path, paths, &monitor)
+                    .await
+            }
+        };
+
+        let progress = monitor.finish(&result);
+        let report = &progress.report;
+        let changed = report.added + report.updated + report.moved + report.removed > 0;
+        if recorded || changed || progress.errors > 0 || progress.status != ScanStatus::Completed {
+            let job = Self::active_model(&progress, monitor.error_details());
+            let saved = if recorded {
+                job.update(&state.conn).await.map(|_| ())
+            } else {
+                job.insert(&state.conn).await.map(|_| ())
+            };
+            if let Err(e) = saved {
+                tracing::warn!("failed to record scan {}: {}", progress.job_id, e);
+            }
+        }
+        state.scans.release(library_id);
+        result
+    }
+
+    fn active_model(
+        progress: &ScanProgress,
+        error_details: Vec<ScanFileError>,
+    ) -> entity::scan_job::ActiveModel {
+        entity::scan_job::ActiveModel {
+            id: Set(progress.job_id),
+            library_id: Set(progress.library_id),
+            status: Set(progress.status.to_string()),
+            trigger: Set(progress.trigger.to_string()),
+            files_seen: Set(progress.files_seen as i32),
+            files_processed: Set(progress.files_processed as i32),
+            added: Set(progress.report.added as i32),
+            updated: Set(progress.report.updated as i32),
+            moved: Set(progress.report.moved as i32),
+            removed: Set(progress.report.removed as i32),
+            unchanged: Set(progress.report.unchanged as i32),
+            errors: Set(progress.errors as i32),
+            error_details: Set(serde_json::to_value(error_details).ok()),
+            message: Set(progress.message.clone()),
+            started_at: Set(progress.started_at),
+            finished_at: Set(progress.finished_at),
+        }
+    }
+
+    fn progress_of(job: &entity::scan_job::Model) -> ScanProgress {
+        ScanProgress {
+            job_id: job.id,
+            library_id: job.library_id,
+            status: ScanStatus::from_str(&job.status).unwrap_or(ScanStatus::Failed),
+            trigger: ScanTrigger::from_str(&job.trigger).unwrap_or(ScanTrigger::Manual),
+            files_seen: job.files_seen as usize,
+            files_processed: job.files_processed as usize,
+            errors: job.errors as usize,
+            report: ScanReport {
+                added: job.added as usize,
+                updated: job.updated as usize,
+                moved: job.moved as usize,
+                removed: job.removed as usize,
+                unchanged: job.unchanged as usize,
+            },
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            eta_secs: None,
+            message: job.message.clone(),
+        }
+    }
+}
@@ -1,98 +1,911 @@
-use crate::{errors::AppError, media::models, state::AppState};
+use crate::{
+    errors::AppError,
+    media::models,
+    music::services::{self as music, TrackSource},
+    state::AppState,
+};
 
-use super::metadata::MediaMetadataExtractor;
+use super::{
+    chunks::{CHUNK_SIZE, ChunkManifest, ChunkStore, fingerprint},
+    filters::{LibraryFilter, LibraryRules},
+    metadata::{MediaMetadataExtractor, MediaTypes, MetadataStore},
+    paths::MediaPaths,
+    scan_jobs::ScanMonitor,
+    tags::MusicTags,
+};
 use chrono::Utc;
 use entity::{self};
-use sea_orm::{ActiveModelTrait, ActiveValue::Set, ColumnTrait, EntityTrait, QueryFilter};
-use std::path::Path;
+use futures::stream::{self, StreamExt};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, Condition, ConnectionTrait, EntityTrait,
+    IntoActiveModel, QueryFilter, TransactionTrait,
+};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+const LOOKUP_BATCH_SIZE: usize = 1000; // file paths per query when loading rows
+
+/// What a scan found compared to the rows already stored.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanReport {
+    pub added: usize,
+    pub updated: usize,
+    pub moved: usize, // renamed or moved within the library, keeping their rows
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// The on-disk identity of a file; rows whose stored state matches are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileState {
+    pub size: i64,
+    pub mtime: Option<i64>, // unix nanoseconds
+    pub inode: Option<i64>,
+}
+
+impl FileState {
+    pub fn of(metadata: &std::fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_nanos() as i64);
+        #[cfg(unix)]
+        let inode = Some(std::os::unix::fs::MetadataExt::ino(metadata) as i64);
+        #[cfg(not(unix))]
+        let inode = None;
+
+        FileState {
+            size: metadata.len() as i64,
+            mtime,
+            inode,
+        }
+    }
+
+    // Rows without a fingerprint are re-read once so moves can be detected later,
+    // and rows without a mime type so their technical attributes get filled in
+    pub fn matches(&self, media: &entity::media::Model) -> bool {
+        media.file_size == Some(self.size)
+            && media.file_mtime == self.mtime
+            && media.file_inode == self.inode
+            && media.fingerprint.is_some()
+            && media.mime_type.is_some()
+    }
+}
+
+// The library being scanned; stored paths are relative to `path`
+struct LibraryRoot {
+    id: Uuid,
+    path: PathBuf,
+}
+
+// A file found by the walk, with its row if it has one
+struct FoundFile {
+    path: PathBuf,
+    file_path: String, // relative to the library root
+    file_state: FileState,
+    current: Option<entity::media::Model>,
+}
+
+// A changed or new media file, read by a worker and ready to be written
+struct PreparedFile {
+    found: FoundFile,
+    metadata: models::MediaFile,
+    fingerprint: Option<String>,
+}
+
+// What writing a prepared file did to its row
+enum Written {
+    Added(Uuid),
+    Updated(Uuid, bool), // whether the contents changed and the manifest needs rehashing
+    Moved(Uuid),
+}
+
 pub struct MediaScanner;
 
 impl MediaScanner {
+    /// Brings the library's rows in line with the disk, only re-reading files
+    /// whose size, mtime or inode changed since the last scan. Files that fail
+    /// are reported to `monitor` and skipped; a cancelled scan removes nothing.
     pub async fn scan_library(
         state: AppState,
         library_id: Uuid,
         library_path: String,
-    ) -> Result<(), AppError> {
-        let _db = &state.conn;
+        monitor: &Arc<ScanMonitor>,
+    ) -> Result<ScanReport, AppError> {
         let path = Path::new(&library_path);
+        let root = LibraryRoot {
+            id: library_id,
+            path: path.to_path_buf(),
+        };
+        // An unmounted disk looks like an empty library; don't wipe it
+        if !path.is_dir() {
+            return Err(AppError::ValidationError(format!(
+                "Library path {} is not a directory",
+                library_path
+            )));
+        }
 
-        // Clean orphaned media first
-        Self::clean_orphaned_media(state.clone(), library_id, library_path.clone()).await?;
-
-        // Process files
-        for entry in WalkDir::new(path)
-            .follow_links(true)
+        let mut existing: HashMap<String, entity::media::Model> = entity::media::Entity::find()
+            .filter(entity::media::Column::LibraryId.eq(library_id))
+            .all(&state.conn)
+            .await?
             .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if entry.file_type().is_file() {
-                if let Some(metadata) = MediaMetadataExtractor::extract(entry.path()).await {
-                    Self::process_media_file(state.clone(), library_id, entry.path(), metadata)
-                        .await?;
-                }
-            }
+            .map(|media| (media.file_path.clone(), media))
+            .collect();
+        let existing_ids: Vec<Uuid> = existing.values().map(|media| media.id).collect();
+        let mut seen = HashSet::new();
+        let mut report = ScanReport::default();
+
+        // Walk first so progress has a total to count towards
+        let filter = Self::filter(&state, &root).await?;
+        let mut files = Self::walk(filter, vec![root.path.clone()], monitor.clone()).await?;
+        monitor.counted();
+        if monitor.is_cancelled() {
+            return Ok(report);
+        }
+        for file in &mut files {
+            file.current = existing.remove(&file.file_path);
         }
 
-        Ok(())
+        Self::process(&state, &root, files, &mut report, &mut seen, monitor).await;
+        if monitor.is_cancelled() {
+            return Ok(report);
+        }
+
+        let missing: Vec<Uuid> = existing_ids
+            .into_iter()
+            .filter(|id| !seen.contains(id))
+            .collect();
+        report.removed = Self::remove(&state, missing).await?;
+        music::prune(&state).await?;
+        Ok(report)
     }
 
-    async fn clean_orphaned_media(
+    /// Rescans only the given files or directories, e.g. after filesystem events.
+    /// Rows below them whose file wasn't written or confirmed are removed: ones
+    /// whose file is gone, is no longer a media file or is now excluded by the rules.
+    pub async fn scan_paths(
         state: AppState,
         library_id: Uuid,
         library_path: String,
-    ) -> Result<(), AppError> {
-        let db = &state.conn;
-        let all_media = entity::media::Entity::find()
+        paths: Vec<PathBuf>,
+        monitor: &Arc<ScanMonitor>,
+    ) -> Result<ScanReport, AppError> {
+        let root = LibraryRoot {
+            id: library_id,
+            path: PathBuf::from(library_path),
+        };
+        let mut report = ScanReport::default();
+
+        let filter = Self::filter(&state, &root).await?;
+        let mut files = Self::walk(filter, paths.clone(), monitor.clone()).await?;
+        monitor.counted();
+        if monitor.is_cancelled() {
+            return Ok(report);
+        }
+        let file_paths: Vec<String> = files.iter().map(|file| file.file_path.clone()).collect();
+        let mut existing = HashMap::new();
+        for file_paths in file_paths.chunks(LOOKUP_BATCH_SIZE) {
+            existing.extend(
+                entity::media::Entity::find()
+                    .filter(entity::media::Column::LibraryId.eq(library_id))
+                    .filter(entity::media::Column::FilePath.is_in(file_paths.to_vec()))
+                    .all(&state.conn)
+                    .await?
+                    .into_iter()
+                    .map(|media| (media.file_path.clone(), media)),
+            );
+        }
+        for file in &mut files {
+            file.current = existing.remove(&file.file_path);
+        }
+
+        let mut seen = HashSet::new();
+        Self::process(&state, &root, files, &mut report, &mut seen, monitor).await;
+        if monitor.is_cancelled() {
+            return Ok(report);
+        }
+
+        let missing: Vec<Uuid> = entity::media::Entity::find()
             .filter(entity::media::Column::LibraryId.eq(library_id))
+            .all(&state.conn)
+            .await?
+            .into_iter()
+            .filter(|media| {
+                let file_path = MediaPaths::join(&root.path, &media.file_path);
+                paths.iter().any(|path| file_path.starts_with(path)) && !seen.contains(&media.id)
+            })
+            .map(|media| media.id)
+            .collect();
+        report.removed = Self::remove(&state, missing).await?;
+        // Retagged or removed files may have left albums or artists empty
+        music::prune(&state).await?;
+        Ok(report)
+    }
+
+    async fn filter(state: &AppState, root: &LibraryRoot) -> Result<LibraryFilter, AppError> {
+        let rules = LibraryRules::load(state, root.id).await?;
+        LibraryFilter::new(root.path.clone(), &rules, state.media_types.clone())
+    }
+
+    // Lists the indexable files below `paths`. Runs on a blocking thread: directory
+    // listings on network storage can stall for seconds
+    async fn walk(
+        filter: LibraryFilter,
+        paths: Vec<PathBuf>,
+        monitor: Arc<ScanMonitor>,
+    ) -> Result<Vec<FoundFile>, AppError> {
+        tokio::task::spawn_blocking(move || {
+            let root = filter.root().to_path_buf();
+            let filter = RefCell::new(filter);
+            let mut files = Vec::new();
+            for path in paths {
+                // Ignored directories are never entered
+                for entry in WalkDir::new(&path)
+                    .follow_links(true)
+                    .into_iter()
+                    .filter_entry(|e| {
+                        !e.file_type().is_dir() || filter.borrow_mut().allows_dir(e.path())
+                    })
+                    .filter_map(|e| e.ok())
+                {
+                    if monitor.is_cancelled() {
+                        return files;
+                    }
+                    if !entry.file_type().is_file() {
+                        continue;
+                    }
+                    let Ok(metadata) = entry.metadata() else {
+                        continue;
+                    };
+                    if !filter
+                        .borrow_mut()
+                        .allows_file(entry.path(), metadata.len())
+                    {
+                        continue;
+                    }
+                    let Some(file_path) = MediaPaths::relative(&root, entry.path()) else {
+                        continue;
+                    };
+                    files.push(FoundFile {
+                        path: entry.into_path(),
+                        file_path,
+                        file_state: FileState::of(&metadata),
+                        current: None,
+                    });
+                    monitor.saw(1);
+                }
+            }
+            files
+        })
+        .await
+        .map_err(|e| AppError::InternalServerError(format!("library walk failed: {}", e)))
+    }
+
+    // Reads changed files on a bounded pool of workers and writes them in batches.
+    // Every row that still has its file ends up in `seen`, failed ones included.
+    async fn process(
+        state: &AppState,
+        root: &LibraryRoot,
+        files: Vec<FoundFile>,
+        report: &mut ScanReport,
+        seen: &mut HashSet<Uuid>,
+        monitor: &ScanMonitor,
+    ) {
+        let mut changed = Vec::new();
+        let mut unchanged = Vec::new();
+        for file in files {
+            match file.current.as_ref() {
+                Some(media) if file.file_state.matches(media) => unchanged.push(file),
+                _ => changed.push(file),
+            }
+        }
+        // Audio rows from before tracks were indexed are re-read once for their tags
+        let untracked = Self::untracked(state, &unchanged).await;
+        for file in unchanged {
+            let Some(media) = file.current.as_ref() else {
+                continue;
+            };
+            if untracked.contains(&media.id) {
+                changed.push(file);
+                continue;
+            }
+            seen.insert(media.id);
+            report.unchanged += 1;
+            monitor.processed(report);
+        }
+
+        let mut prepared = stream::iter(changed)
+            .map(|found| Self::prepare(found, state.media_types.clone()))
+            .buffer_unordered(state.scan_workers);
+        let mut batch = Vec::new();
+        while let Some(file) = prepared.next().await {
+            match file {
+                Ok(file) => batch.push(file),
+                // Not a media file (any more); its row, if any, isn't kept
+                Err(_) => monitor.processed(report),
+            }
+            if batch.len() >= state.scan_batch_size {
+                Self::write_batch(
+                    state,
+                    root,
+                    std::mem::take(&mut batch),
+                    report,
+                    seen,
+                    monitor,
+                )
+                .await;
+            }
+            // Files already read are still written, so their work isn't lost
+            if monitor.is_cancelled() {
+                break;
+            }
+        }
+        if !batch.is_empty() {
+            Self::write_batch(state, root, batch, report, seen, monitor).await;
+        }
+    }
+
+    // The audio rows among `files` that have no track
+    async fn untracked(state: &AppState, files: &[FoundFile]) -> HashSet<Uuid> {
+        let audio: Vec<Uuid> = files
+            .iter()
+            .filter_map(|file| file.current.as_ref())
+            .filter(|media| media.media_type == models::MediaType::Audio.to_string())
+            .map(|media| media.id)
+            .collect();
+        let mut untracked: HashSet<Uuid> = audio.iter().copied().collect();
+        for ids in audio.chunks(LOOKUP_BATCH_SIZE) {
+            let tracks = entity::track::Entity::find()
+                .filter(entity::track::Column::MediaId.is_in(ids.to_vec()))
+                .all(&state.conn)
+                .await;
+            match tracks {
+                Ok(tracks) => {
+                    for track in tracks {
+                        untracked.remove(&track.media_id);
+                    }
+                }
+                Err(e) => {
+                    // Not worth failing the scan over; the next one tries again
+                    tracing::warn!("failed to look up tracks: {}", e);
+                    return HashSet::new();
+                }
+            }
+        }
+        untracked
+    }
+
+    async fn prepare(found: FoundFile, media_types: MediaTypes) -> Result<PreparedFile, FoundFile> {
+        let Some(metadata) = MediaMetadataExtractor::extract(&found.path, &media_types).await
+        else {
+            return Err(found);
+        };
+        let fingerprint = match fingerprint(found.path.clone()).await {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(e) => {
+                tracing::warn!("failed to fingerprint {}: {}", found.path.display(), e);
+                None
+            }
+        };
+        Ok(PreparedFile {
+            found,
+            metadata,
+            fingerprint,
+        })
+    }
+
+    // Writes a batch in one transaction; if that fails, each file is retried on its
+    // own so a single bad row only fails itself
+    async fn write_batch(
+        state: &AppState,
+        root: &LibraryRoot,
+        files: Vec<PreparedFile>,
+        report: &mut ScanReport,
+        seen: &mut HashSet<Uuid>,
+        monitor: &ScanMonitor,
+    ) {
+        let written = match Self::write_all(state, root, &files).await {
+            Ok(written) => written.into_iter().map(Ok).collect(),
+            Err(e) => {
+                tracing::warn!("batch write failed, retrying files one by one: {}", e);
+                let mut written = Vec::with_capacity(files.len());
+                let mut claimed = HashSet::new();
+                for file in &files {
+                    written.push(Self::write_one(state, root, file, &mut claimed).await);
+                }
+                written
+            }
+        };
+
+        let mut manifests = Vec::new();
+        for (file, written) in files.into_iter().zip(written) {
+            match written {
+                Ok(written) => {
+                    let (media_id, force) = match written {
+                        Written::Added(media_id) => {
+                            report.added += 1;
+                            (media_id, false)
+                        }
+                        Written::Updated(media_id, force) => {
+                            report.updated += 1;
+                            (media_id, force)
+                        }
+                        Written::Moved(media_id) => {
+                            report.moved += 1;
+                            (media_id, false)
+                        }
+                    };
+                    seen.insert(media_id);
+                    manifests.push((
+                        media_id,
+                        file.found.path,
+                        file.found.file_path,
+                        force,
+                        file.metadata,
+                    ));
+                }
+                Err(e) => {
+                    // A file that failed is still there; keep its row
+                    if let Some(media) = &file.found.current {
+                        seen.insert(media.id);
+                    }
+                    monitor.failed(&file.found.path, &e);
+                    monitor.processed(report);
+                }
+            }
+        }
+
+        let mut music = Vec::new();
+        let mut hashed = stream::iter(manifests)
+            .map(|(media_id, path, file_path, force, metadata)| {
+                let state = state.clone();
+                async move {
+                    let result =
+                        Self::store_details(&state, media_id, &path, force, &metadata).await;
+                    (media_id, path, file_path, metadata, result)
+                }
+            })
+            .buffer_unordered(state.scan_workers);
+        while let Some((media_id, path, file_path, metadata, result)) = hashed.next().await {
+            if let Err(e) = result {
+                monitor.failed(&path, &e);
+            }
+            if metadata.media_type == models::MediaType::Audio {
+                music.push((media_id, path, file_path, metadata));
+            }
+            monitor.processed(report);
+        }
+
+        // One file at a time, so tracks of one album don't race to create it
+        for (media_id, path, file_path, metadata) in music {
+            if let Err(e) = Self::index_track(state, root, media_id, &file_path, metadata).await {
+                monitor.failed(&path, &e);
+            }
+        }
+    }
+
+    async fn write_all(
+        state: &AppState,
+        root: &LibraryRoot,
+        files: &[PreparedFile],
+    ) -> Result<Vec<Written>, AppError> {
+        let txn = state.conn.begin().await?;
+        let mut inserts = Vec::new();
+        let mut claimed = HashSet::new();
+        let mut written = Vec::with_capacity(files.len());
+        for file in files {
+            written.push(Self::write(&txn, root, file, &mut inserts, &mut claimed).await?);
+        }
+        if !inserts.is_empty() {
+            entity::media::Entity::insert_many(inserts)
+                .exec(&txn)
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(written)
+    }
+
+    async fn write_one(
+        state: &AppState,
+        root: &LibraryRoot,
+        file: &PreparedFile,
+        claimed: &mut HashSet<Uuid>,
+    ) -> Result<Written, AppError> {
+        let mut inserts = Vec::new();
+        let written = Self::write(&state.conn, root, file, &mut inserts, claimed).await?;
+        if !inserts.is_empty() {
+            entity::media::Entity::insert_many(inserts)
+                .exec(&state.conn)
+                .await?;
+        }
+        Ok(written)
+    }
+
+    // Updates or moves an existing row in place; new rows are queued on `inserts`.
+    // Rows moved in this batch are `claimed`, so a copy of a moved file can't take one too
+    async fn write<C: ConnectionTrait>(
+        db: &C,
+        root: &LibraryRoot,
+        file: &PreparedFile,
+        inserts: &mut Vec<entity::media::ActiveModel>,
+        claimed: &mut HashSet<Uuid>,
+    ) -> Result<Written, AppError> {
+        if let Some(media) = file.found.current.clone() {
+            let media_id = media.id;
+            // Rows from before file state was tracked only need it filled in, and
+            // rows re-read for missing attributes kept their contents
+            let file_state = file.found.file_state;
+            let changed = media.file_size.is_some()
+                && (media.file_size, media.file_mtime, media.file_inode)
+                    != (Some(file_state.size), file_state.mtime, file_state.inode);
+            // Titles that were only ever the file name pick up a tagged title
+            let untitled = Path::new(&media.file_path)
+                .file_name()
+                .is_some_and(|name| name.to_str() == Some(media.title.as_str()));
+            let mut media = media.into_active_model();
+            if untitled {
+                media.title = Set(file.metadata.title.clone());
+            }
+            Self::set_file(&mut media, file);
+            media.update(db).await?;
+            return Ok(Written::Updated(media_id, changed));
+        }
+
+        let moved = Self::find_moved(
+            db,
+            root,
+            file.found.file_state,
+            file.fingerprint.as_deref(),
+            claimed,
+        )
+        .await?;
+        if let Some(media) = moved {
+            let media_id = media.id;
+            claimed.insert(media_id);
+            // Titles that were only ever the file name follow the rename
+            let old_name = Path::new(&media.file_path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(str::to_string);
+            let renamed_title = old_name.as_deref() == Some(media.title.as_str());
+
+            let mut media = media.into_active_model();
+            media.file_path = Set(file.found.file_path.clone());
+            if renamed_title {
+                media.title = Set(file.metadata.title.clone());
+            }
+            Self::set_file(&mut media, file);
+            media.update(db).await?;
+            return Ok(Written::Moved(media_id));
+        }
+
+        let mut media = entity::media::ActiveModel {
+            id: Set(file.metadata.id),
+            library_id: Set(root.id),
+            title: Set(file.metadata.title.clone()),
+            file_path: Set(file.found.file_path.clone()),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        Self::set_file(&mut media, file);
+        inserts.push(media);
+        Ok(Written::Added(file.metadata.id))
+    }
+
+    fn set_file(media: &mut entity::media::ActiveModel, file: &PreparedFile) {
+        media.media_type = Set(file.metadata.media_type.to_string());
+        media.file_size = Set(Some(file.found.file_state.size));
+        media.file_mtime = Set(file.found.file_state.mtime);
+        media.file_inode = Set(file.found.file_state.inode);
+        media.fingerprint = Set(file.fingerprint.clone());
+
+        let probe = file.metadata.probe.as_ref();
+        media.duration = Set(file.metadata.duration);
+        media.bitrate = Set(file.metadata.bitrate.map(|bitrate| bitrate as i32));
+        media.container = Set(probe.map(|probe| probe.container.clone()));
+        media.video_codec = Set(probe.and_then(|probe| probe.video_codec.clone()));
+        media.audio_codec = Set(probe.and_then(|probe| probe.audio_codec.clone()));
+        media.width = Set(probe
+            .and_then(|probe| probe.width)
+            .map(|width| width as i32));
+        media.height = Set(probe
+            .and_then(|probe| probe.height)
+            .map(|height| height as i32));
+        media.mime_type = Set(Some(
+            mime_guess::from_path(&file.found.path)
+                .first_or_octet_stream()
+                .to_string(),
+        ));
+        media.updated_at = Set(Utc::now().naive_utc());
+    }
+
+    // A row whose file is gone and that has the same inode and size, or the same fingerprint
+    async fn find_moved<C: ConnectionTrait>(
+        db: &C,
+        root: &LibraryRoot,
+        file_state: FileState,
+        fingerprint: Option<&str>,
+        claimed: &HashSet<Uuid>,
+    ) -> Result<Option<entity::media::Model>, AppError> {
+        let mut identity = Condition::any();
+        if let Some(inode) = file_state.inode {
+            identity = identity.add(
+                Condition::all()
+                    .add(entity::media::Column::FileInode.eq(inode))
+                    .add(entity::media::Column::FileSize.eq(file_state.size)),
+            );
+        }
+        if let Some(fingerprint) = fingerprint {
+            identity = identity.add(entity::media::Column::Fingerprint.eq(fingerprint));
+        }
+        if identity.is_empty() {
+            return Ok(None);
+        }
+
+        let candidates = entity::media::Entity::find()
+            .filter(entity::media::Column::LibraryId.eq(root.id))
+            .filter(identity)
             .all(db)
             .await?;
+        Ok(Self::pick_moved(candidates, root, fingerprint, claimed))
+    }
 
-        for media_item in all_media {
-            let full_path = Path::new(&library_path).join(&media_item.file_path);
-            if !full_path.exists() {
-                entity::media::Entity::delete_by_id(media_item.id)
-                    .exec(db)
-                    .await?;
+    // The first row sharing the file's identity whose own file is gone and that
+    // no other file in the batch has already moved into
+    fn pick_moved(
+        candidates: Vec<entity::media::Model>,
+        root: &LibraryRoot,
+        fingerprint: Option<&str>,
+        claimed: &HashSet<Uuid>,
+    ) -> Option<entity::media::Model> {
+        candidates.into_iter().find(|media| {
+            if claimed.contains(&media.id) {
+                return false;
             }
+            // Inodes get reused after deletes, so the content has to agree when known
+            let same_content = match (media.fingerprint.as_deref(), fingerprint) {
+                (Some(stored), Some(current)) => stored == current,
+                _ => true,
+            };
+            same_content && !MediaPaths::join(&root.path, &media.file_path).exists()
+        })
+    }
+
+    async fn remove(state: &AppState, missing: Vec<Uuid>) -> Result<usize, AppError> {
+        if missing.is_empty() {
+            return Ok(0);
         }
+        entity::media::Entity::delete_many()
+            .filter(entity::media::Column::Id.is_in(missing.clone()))
+            .exec(&state.conn)
+            .await?;
+        Ok(missing.len())
+    }
+
+    // Probe results go into `media_metadata` before the file is hashed
+    async fn store_details(
+        state: &AppState,
+        media_id: Uuid,
+        path: &Path,
+        force: bool,
+        metadata: &models::MediaFile,
+    ) -> Result<(), AppError> {
+        if let Some(probe) = &metadata.probe {
+            MetadataStore::store_probe(state, media_id, probe).await?;
+        }
+        Self::ensure_chunk_manifest(state, media_id, path, metadata.size, force).await
+    }
 
+    // Stores the tags and files the audio file under its artist and album;
+    // untagged files still get a track so they can be browsed
+    async fn index_track(
+        state: &AppState,
+        root: &LibraryRoot,
+        media_id: Uuid,
+        file_path: &str,
+        metadata: models::MediaFile,
+    ) -> Result<(), AppError> {
+        if let Some(tags) = &metadata.tags {
+            MetadataStore::store_tags(state, media_id, tags).await?;
+        }
+        let untagged = MusicTags::default();
+        music::index_track(
+            state,
+            TrackSource {
+                library_id: root.id,
+                media_id,
+                file_path,
+                title: &metadata.title,
+                duration: metadata.duration,
+                tags: metadata.tags.as_ref().unwrap_or(&untagged),
+            },
+        )
+        .await?;
         Ok(())
     }
 
-    async fn process_media_file(
-        state: AppState,
-        library_id: Uuid,
+    // Hash lists let P2P clients verify what other peers send them; `force`
+    // rehashes files whose contents changed without changing size
+    async fn ensure_chunk_manifest(
+        state: &AppState,
+        media_id: Uuid,
         path: &Path,
-        metadata: models::MediaFile,
+        size: u64,
+        force: bool,
     ) -> Result<(), AppError> {
-        let db = &state.conn;
-        let file_path = path
-            .to_str()
-            .ok_or(AppError::ValidationError("Invalid file path".into()))?;
+        let current = ChunkStore::load(state, media_id).await?;
+        if !force
+            && current
+                .is_some_and(|manifest| manifest.size == size && manifest.chunk_size == CHUNK_SIZE)
+        {
+            return Ok(());
+        }
 
-        let existing_media = entity::media::Entity::find()
-            .filter(entity::media::Column::LibraryId.eq(library_id))
-            .filter(entity::media::Column::FilePath.eq(file_path))
-            .one(db)
-            .await?;
+        match ChunkManifest::compute(path.to_path_buf(), CHUNK_SIZE).await {
+            Ok(manifest) => ChunkStore::store(state, media_id, &manifest).await,
+            Err(e) => {
+                tracing::warn!("failed to hash {}: {}", path.display(), e);
+                Ok(())
+            }
+        }
+    }
+}
 
-        if existing_media.is_none() {
-            let new_media = entity::media::ActiveModel {
-                id: Set(metadata.id),
-                library_id: Set(library_id),
-                title: Set(metadata.title),
-                file_path: Set(file_path.to_string()),
-                media_type: Set(metadata.media_type.to_string()),
-                created_at: Set(Utc::now().naive_utc()),
-                updated_at: Set(Utc::now().naive_utc()),
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            new_media.insert(db).await?;
+    fn media(file_path: &str) -> entity::media::Model {
+        entity::media::Model {
+            id: Uuid::new_v4(),
+            library_id: Uuid::nil(),
+            title: file_path.to_string(),
+            file_path: file_path.to_string(),
+            media_type: "video".to_string(),
+            created_at: Default::default(),
+            updated_at: Default::default(),
+            file_size: Some(1000),
+            file_mtime: Some(1_700_000_000_000_000_000),
+            file_inode: Some(42),
+            fingerprint: Some("abc".to_string()),
+            duration: None,
+            bitrate: None,
+            container: None,
+            video_codec: None,
+            audio_codec: None,
+            width: None,
+            height: None,
+            mime_type: Some("video/mp4".to_string()),
         }
+    }
 
-        Ok(())
+    const STATE: FileState = FileState {
+        size: 1000,
+        mtime: Some(1_700_000_000_000_000_000),
+        inode: Some(42),
+    };
+
+    #[test]
+    fn unchanged_files_match_their_row() {
+        assert!(STATE.matches(&media("a.mp4")));
+
+        // Filesystems without inodes
+        let mut row = media("a.mp4");
+        row.file_inode = None;
+        assert!(
+            FileState {
+                inode: None,
+                ..STATE
+            }
+            .matches(&row)
+        );
+    }
+
+    #[test]
+    fn any_change_on_disk_is_a_mismatch() {
+        let row = media("a.mp4");
+        assert!(!FileState { size: 999, ..STATE }.matches(&row));
+        assert!(
+            !FileState {
+                mtime: Some(1),
+                ..STATE
+            }
+            .matches(&row)
+        );
+        assert!(
+            !FileState {
+                mtime: None,
+                ..STATE
+            }
+            .matches(&row)
+        );
+        assert!(
+            !FileState {
+                inode: Some(43),
+                ..STATE
+            }
+            .matches(&row)
+        );
+    }
+
+    #[test]
+    fn rows_missing_a_fingerprint_or_mime_type_are_reread() {
+        let mut row = media("a.mp4");
+        row.fingerprint = None;
+        assert!(!STATE.matches(&row));
+
+        let mut row = media("a.mp4");
+        row.mime_type = None;
+        assert!(!STATE.matches(&row));
+
+        let mut row = media("a.mp4");
+        row.file_size = None;
+        assert!(!STATE.matches(&row));
+    }
+
+    fn root() -> LibraryRoot {
+        LibraryRoot {
+            id: Uuid::nil(),
+            path: std::env::temp_dir(),
+        }
+    }
+
+    fn pick(
+        candidates: &[&entity::media::Model],
+        fingerprint: Option<&str>,
+        claimed: &HashSet<Uuid>,
+    ) -> Option<Uuid> {
+        let candidates = candidates.iter().map(|&media| media.clone()).collect();
+        MediaScanner::pick_moved(candidates, &root(), fingerprint, claimed).map(|media| media.id)
+    }
+
+    #[test]
+    fn a_row_whose_file_is_gone_is_moved() {
+        let gone = media("moved-away-missing.mp4");
+        assert_eq!(pick(&[&gone], Some("abc"), &HashSet::new()), Some(gone.id));
+        // Files that haven't been hashed yet go by inode alone
+        assert_eq!(pick(&[&gone], None, &HashSet::new()), Some(gone.id));
+    }
+
+    #[test]
+    fn a_row_whose_file_still_exists_is_a_copy() {
+        let path = crate::media::services::probe::fixtures::path(b"copy");
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let existing = media(name);
+        let gone = media("moved-away-missing.mp4");
+
+        let picked = pick(&[&existing, &gone], Some("abc"), &HashSet::new());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(picked, Some(gone.id));
+    }
+
+    #[test]
+    fn a_reused_inode_with_other_content_is_not_a_move() {
+        let gone = media("moved-away-missing.mp4");
+        assert_eq!(pick(&[&gone], Some("def"), &HashSet::new()), None);
+    }
+
+    #[test]
+    fn rows_claimed_earlier_in_the_batch_are_skipped() {
+        let first = media("moved-away-missing-1.mp4");
+        let second = media("moved-away-missing-2.mp4");
+        let mut claimed = HashSet::from([first.id]);
+        assert_eq!(
+            pick(&[&first, &second], Some("abc"), &claimed),
+            Some(second.id)
+        );
+
+        claimed.insert(second.id);
+        assert_eq!(pick(&[&first, &second], Some("abc"), &claimed), None);
     }
 }
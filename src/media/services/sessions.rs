@@ -0,0 +1,195 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use uuid::Uuid;
+
+use crate::media::{
+    errors::MediaError,
+    models::{PlayMethod, StreamType},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSession {
+    pub id: Uuid,
+    pub account_id: Uuid, // the household owner's profile
+    pub profile_id: Uuid,
+    pub media_id: Uuid,
+    pub device: Option<String>,
+    pub stream_type: StreamType,
+    pub play_method: PlayMethod,
+    pub position: f64,        // in seconds
+    pub bitrate: Option<u32>, // in kbps
+    pub paused: bool,
+    pub started_at: DateTime<Utc>,
+    pub last_heartbeat: DateTime<Utc>,
+    pub transfer: TransferStats,
+}
+
+/// Where the bytes of a peer-assisted stream came from.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TransferStats {
+    pub p2p_bytes: u64,
+    pub http_bytes: u64,
+    pub p2p_share: f64, // percent of all bytes served by peers
+}
+
+impl TransferStats {
+    fn record(&mut self, from_peer: bool, bytes: u64) {
+        if from_peer {
+            self.p2p_bytes += bytes;
+        } else {
+            self.http_bytes += bytes;
+        }
+        let total = self.p2p_bytes + self.http_bytes;
+        self.p2p_share = if total > 0 {
+            self.p2p_bytes as f64 / total as f64 * 100.0
+        } else {
+            0.0
+        };
+    }
+}
+
+impl StreamSession {
+    // Both the streaming profile and the household owner may control a session
+    fn is_owned_by(&self, caller_id: Uuid) -> bool {
+        self.profile_id == caller_id || self.account_id == caller_id
+    }
+}
+
+/// In-memory registry of everything currently being streamed.
+///
+/// Sessions are kept alive by client heartbeats and forgotten once they stop.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    sessions: Arc<RwLock<HashMap<Uuid, StreamSession>>>,
+    max_streams_per_account: usize, // 0 means unlimited
+    session_timeout: Duration,
+}
+
+impl SessionRegistry {
+    pub fn new(max_streams_per_account: usize, session_timeout: Duration) -> Self {
+        SessionRegistry {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            max_streams_per_account,
+            session_timeout,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn start(
+        &self,
+        account_id: Uuid,
+        profile_id: Uuid,
+        media_id: Uuid,
+        device: Option<String>,
+        stream_type: StreamType,
+        play_method: PlayMethod,
+        position: f64,
+    ) -> Result<StreamSession, MediaError> {
+        let mut sessions = self.sessions.write().unwrap();
+        self.prune(&mut sessions);
+
+        let active = sessions
+            .values()
+            .filter(|s| s.account_id == account_id)
+            .count();
+        if self.max_streams_per_account > 0 && active >= self.max_streams_per_account {
+            return Err(MediaError::StreamLimitReached(self.max_streams_per_account));
+        }
+
+        let now = Utc::now();
+        let session = StreamSession {
+            id: Uuid::new_v4(),
+            account_id,
+            profile_id,
+            media_id,
+            device,
+            stream_type,
+            play_method,
+            position,
+            bitrate: None,
+            paused: false,
+            started_at: now,
+            last_heartbeat: now,
+            transfer: TransferStats::default(),
+        };
+        sessions.insert(session.id, session.clone());
+        Ok(session)
+    }
+
+    pub fn heartbeat(
+        &self,
+        session_id: Uuid,
+        caller_id: Uuid,
+        position: f64,
+        bitrate: Option<u32>,
+        paused: bool,
+    ) -> Option<StreamSession> {
+        let mut sessions = self.sessions.write().unwrap();
+        self.prune(&mut sessions);
+
+        let session = sessions
+            .get_mut(&session_id)
+            .filter(|s| s.is_owned_by(caller_id))?;
+        session.position = position;
+        session.bitrate = bitrate.or(session.bitrate);
+        session.paused = paused;
+        session.last_heartbeat = Utc::now();
+        Some(session.clone())
+    }
+
+    /// The live session a stream URL belongs to; streams only flow while
+    /// their session heartbeats, so the per-account limit can't be skipped.
+    pub fn live(
+        &self,
+        session_id: Uuid,
+        profile_id: Uuid,
+        media_id: Uuid,
+    ) -> Option<StreamSession> {
+        let mut sessions = self.sessions.write().unwrap();
+        self.prune(&mut sessions);
+
+        sessions
+            .get(&session_id)
+            .filter(|s| s.profile_id == profile_id && s.media_id == media_id)
+            .cloned()
+    }
+
+    /// Counts bytes a session received from peers or the origin.
+    pub fn record_transfer(&self, session_id: Uuid, from_peer: bool, bytes: u64) {
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(&session_id) {
+            session.transfer.record(from_peer, bytes);
+        }
+    }
+
+    pub fn stop(&self, session_id: Uuid, caller_id: Uuid) -> Option<StreamSession> {
+        let mut sessions = self.sessions.write().unwrap();
+        match sessions.get(&session_id) {
+            Some(s) if s.is_owned_by(caller_id) => sessions.remove(&session_id),
+            _ => None,
+        }
+    }
+
+    pub fn list_for_account(&self, account_id: Uuid) -> Vec<StreamSession> {
+        let mut sessions = self.sessions.write().unwrap();
+        self.prune(&mut sessions);
+
+        let mut list: Vec<StreamSession> = sessions
+            .values()
+            .filter(|s| s.account_id == account_id)
+            .cloned()
+            .collect();
+        list.sort_by_key(|s| s.started_at);
+        list
+    }
+
+    // Drop sessions whose client stopped sending heartbeats
+    fn prune(&self, sessions: &mut HashMap<Uuid, StreamSession>) {
+        let cutoff = Utc::now() - self.session_timeout;
+        sessions.retain(|_, s| s.last_heartbeat >= cutoff);
+    }
+}
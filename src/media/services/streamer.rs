@@ -0,0 +1,588 @@
+use axum::extract::State;
+use entity::{media, media_metadata};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde_json::Value;
+use std::{net::IpAddr, path::Path};
+use uuid::Uuid;
+
+use crate::{
+    auth::{models::Claims, services::authorize_profile},
+    errors::AppError,
+    media::{
+        models::{DeviceProfile, PlayMethod, StreamRequest, StreamResponse, StreamType},
+        services::{
+            chunks::ChunkStore,
+            peers::PeerRegistry,
+            progress::ProgressTracker,
+            throttle::Network,
+            transcoder::{TranscodeContainer, TranscodeOptions},
+            url_signer::UrlSigner,
+        },
+    },
+    state::AppState,
+};
+
+// Share of the bitrate budget left for audio when transcoding
+const AUDIO_BITRATE: u32 = 192; // in kbps
+
+/// Technical facts about the source file that playback decisions depend on.
+///
+/// Anything unknown is `None`; unknown codecs are assumed to be playable so a
+/// file that hasn't been probed yet is not transcoded needlessly.
+#[derive(Debug, Default, Clone)]
+pub struct SourceInfo {
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub bitrate: Option<u32>,  // in kbps
+    pub duration: Option<f64>, // in seconds
+    pub subtitle_formats: Vec<String>,
+    pub has_video: bool,
+}
+
+impl SourceInfo {
+    pub fn from_media(media: &media::Model, metadata: Option<&Value>) -> Self {
+        let str_field = |key: &str| {
+            metadata
+                .and_then(|m| m.get(key))
+                .and_then(Value::as_str)
+                .map(|s| s.to_lowercase())
+        };
+        let u32_field = |key: &str| {
+            metadata
+                .and_then(|m| m.get(key))
+                .and_then(Value::as_u64)
+                .map(|v| v as u32)
+        };
+
+        let column = |value: Option<i32>| value.map(|v| v as u32);
+
+        // Columns come from the scanner's probe; metadata covers rows scanned before them
+        SourceInfo {
+            container: media
+                .container
+                .clone()
+                .or_else(|| str_field("container"))
+                .or_else(|| Self::container_from_path(&media.file_path)),
+            video_codec: media
+                .video_codec
+                .clone()
+                .or_else(|| str_field("video_codec")),
+            audio_codec: media
+                .audio_codec
+                .clone()
+                .or_else(|| str_field("audio_codec")),
+            width: column(media.width).or_else(|| u32_field("width")),
+            height: column(media.height).or_else(|| u32_field("height")),
+            bitrate: column(media.bitrate).or_else(|| u32_field("bitrate")),
+            duration: media.duration.or_else(|| {
+                metadata
+                    .and_then(|m| m.get("duration"))
+                    .and_then(Value::as_f64)
+            }),
+            subtitle_formats: metadata
+                .and_then(|m| m.get("subtitle_formats"))
+                .and_then(Value::as_array)
+                .map(|formats| {
+                    formats
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|s| s.to_lowercase())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            has_video: media.media_type.eq_ignore_ascii_case("video"),
+        }
+    }
+
+    fn container_from_path(path: &str) -> Option<String> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        let container = match extension.as_str() {
+            "m4v" | "m4a" => "mp4",
+            "mka" => "mkv",
+            "oga" | "ogv" | "opus" => "ogg",
+            other => other,
+        };
+        Some(container.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaybackDecision {
+    pub method: PlayMethod,
+    pub reasons: Vec<String>,
+    pub transcode: Option<TranscodeOptions>,
+}
+
+pub struct MediaStreamer;
+
+impl MediaStreamer {
+    /// Resolves a stream request into a playable URL for the requesting device.
+    pub async fn prepare_stream(
+        state: AppState,
+        claims: Claims,
+        req: StreamRequest,
+        client_ip: Option<IpAddr>,
+    ) -> Result<StreamResponse, AppError> {
+        let profile = authorize_profile(State(state.clone()), &claims, req.profile_id).await?;
+
+        let media = media::Entity::find_by_id(req.media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let metadata = media_metadata::Entity::find()
+            .filter(media_metadata::Column::MediaId.eq(media.id))
+            .one(&state.conn)
+            .await?
+            .and_then(|m| m.metadata);
+
+        let start_position = match req.seek_position {
+            Some(position) => Some(position),
+            None => ProgressTracker::resume_position(&state, profile.id, media.id).await?,
+        };
+
+        let source = SourceInfo::from_media(&media, metadata.as_ref());
+        let device = req.device_profile.unwrap_or_default();
+        let decision = Self::decide(&source, &device);
+
+        // Peers hold the original file, so they only help when it plays as-is,
+        // and only with a manifest to check their chunks against
+        let manifest = if state.allow_peer_to_peer
+            && req.prefer_p2p
+            && decision.method == PlayMethod::DirectPlay
+        {
+            ChunkStore::load(&state, media.id).await?
+        } else {
+            None
+        };
+        let p2p_peers = match &manifest {
+            Some(manifest) => {
+                // A client on the LAN is better served by LAN peers than by anyone remote
+                let client_local = Network::of(client_ip) == Network::Local;
+                PeerRegistry::best_peers(
+                    &state,
+                    media.id,
+                    profile.id,
+                    client_local,
+                    manifest.chunk_size,
+                )
+                .await?
+            }
+            None => vec![],
+        };
+        let stream_type = if p2p_peers.is_empty() {
+            StreamType::HTTP
+        } else {
+            StreamType::P2P
+        };
+
+        let session = state.sessions.start(
+            profile.parent_id.unwrap_or(profile.id),
+            profile.id,
+            media.id,
+            device.name.clone(),
+            stream_type,
+            decision.method,
+            start_position.unwrap_or(0.0),
+        )?;
+
+        let url = match &decision.transcode {
+            None => format!("/v1/media/{}/file?session_id={}", media.id, session.id),
+            Some(options) => Self::transcode_url(media.id, session.id, options, start_position),
+        };
+        let url = UrlSigner::sign_url(&state, &url, media.id, profile.id, client_ip);
+
+        // Clients fetch chunks from peers themselves and the rest from `url`; only
+        // that takes load off the server. Ones that can't still get the relay
+        let (chunk_manifest, relay_url) = if stream_type == StreamType::P2P {
+            let relay_url = format!("/v1/media/{}/swarm?session_id={}", media.id, session.id);
+            (
+                manifest,
+                Some(UrlSigner::sign_url(
+                    &state, &relay_url, media.id, profile.id, client_ip,
+                )),
+            )
+        } else {
+            (None, None)
+        };
+
+        Ok(StreamResponse {
+            session_id: session.id,
+            stream_type,
+            url,
+            start_position,
+            ice_servers: if p2p_peers.is_empty() {
+                vec![]
+            } else {
+                state.p2p.ice_servers(&profile.id.to_string())
+            },
+            p2p_peers,
+            chunk_manifest,
+            relay_url,
+            play_method: decision.method,
+            decision_reasons: decision.reasons,
+        })
+    }
+
+    /// Chooses between direct play, remux and a full transcode.
+    pub fn decide(source: &SourceInfo, device: &DeviceProfile) -> PlaybackDecision {
+        let mut reasons = Vec::new();
+        let supports =
+            |list: &[String], value: &str| list.iter().any(|v| v.eq_ignore_ascii_case(value));
+
+        let container_ok = match &source.container {
+            Some(container) if !supports(&device.containers, container) => {
+                reasons.push(format!(
+                    "container '{}' is not supported by the device",
+                    container
+                ));
+                false
+            }
+            _ => true,
+        };
+
+        let video_codec_ok = match &source.video_codec {
+            Some(codec) if source.has_video && !supports(&device.video_codecs, codec) => {
+                reasons.push(format!(
+                    "video codec '{}' is not supported by the device",
+                    codec
+                ));
+                false
+            }
+            _ => true,
+        };
+
+        let audio_codec_ok = match &source.audio_codec {
+            Some(codec) if !supports(&device.audio_codecs, codec) => {
+                reasons.push(format!(
+                    "audio codec '{}' is not supported by the device",
+                    codec
+                ));
+                false
+            }
+            _ => true,
+        };
+
+        let resolution_ok = match (source.width, source.height) {
+            (Some(width), Some(height)) if source.has_video => {
+                let too_wide = device.max_width.is_some_and(|max| width > max);
+                let too_tall = device.max_height.is_some_and(|max| height > max);
+                if too_wide || too_tall {
+                    reasons.push(format!(
+                        "resolution {}x{} exceeds the device maximum",
+                        width, height
+                    ));
+                }
+                !too_wide && !too_tall
+            }
+            _ => true,
+        };
+
+        let bitrate_ok = match (source.bitrate, device.max_bitrate) {
+            (Some(bitrate), Some(max)) if bitrate > max => {
+                reasons.push(format!(
+                    "bitrate {} kbps exceeds the device maximum of {} kbps",
+                    bitrate, max
+                ));
+                false
+            }
+            _ => true,
+        };
+
+        // Text subtitles can be converted on the side; they never force a transcode
+        let unsupported_subtitles: Vec<&String> = source
+            .subtitle_formats
+            .iter()
+            .filter(|format| !supports(&device.subtitle_formats, format))
+            .collect();
+        if !unsupported_subtitles.is_empty() {
+            reasons.push(format!(
+                "subtitle formats {:?} are not supported and will not be embedded",
+                unsupported_subtitles
+            ));
+        }
+
+        let video_ok = video_codec_ok && resolution_ok && bitrate_ok;
+        let container = Self::output_container(device);
+        if video_ok && audio_codec_ok {
+            if container_ok {
+                reasons.insert(0, "source is compatible with the device".to_string());
+                return PlaybackDecision {
+                    method: PlayMethod::DirectPlay,
+                    reasons,
+                    transcode: None,
+                };
+            }
+
+            // WebM only takes VP8/VP9/AV1 and Opus/Vorbis, so copying into it rarely works
+            if container != TranscodeContainer::WebM {
+                return PlaybackDecision {
+                    method: PlayMethod::Remux,
+                    reasons,
+                    transcode: Some(TranscodeOptions {
+                        container,
+                        video_codec: "copy".to_string(),
+                        audio_codec: "copy".to_string(),
+                        ..Default::default()
+                    }),
+                };
+            }
+        }
+
+        let (video_encoders, audio_encoders): (&[&str], &[&str]) =
+            if container == TranscodeContainer::WebM {
+                (&["vp9", "av1"], &["opus"])
+            } else {
+                (
+                    &["h264", "hevc", "vp9", "av1"],
+                    &["aac", "opus", "mp3", "flac"],
+                )
+            };
+        let copyable = |codec: &Option<String>, encoders: &[&str]| {
+            container != TranscodeContainer::WebM
+                || codec
+                    .as_ref()
+                    .is_some_and(|c| encoders.contains(&c.as_str()))
+        };
+
+        let video_codec =
+            if video_ok && source.has_video && copyable(&source.video_codec, video_encoders) {
+                "copy".to_string()
+            } else {
+                Self::pick_codec(&device.video_codecs, video_encoders)
+            };
+        let audio_codec = if audio_codec_ok && copyable(&source.audio_codec, &["opus", "vorbis"]) {
+            "copy".to_string()
+        } else {
+            Self::pick_codec(&device.audio_codecs, audio_encoders)
+        };
+        let video_bitrate = match (device.max_bitrate, source.bitrate) {
+            (Some(max), Some(source)) => Some(source.min(max).saturating_sub(AUDIO_BITRATE)),
+            (Some(max), None) => Some(max.saturating_sub(AUDIO_BITRATE)),
+            _ => None,
+        }
+        .filter(|bitrate| *bitrate > 0);
+
+        PlaybackDecision {
+            method: PlayMethod::Transcode,
+            reasons,
+            transcode: Some(TranscodeOptions {
+                container,
+                video_codec,
+                audio_codec,
+                video_bitrate,
+                audio_bitrate: Some(AUDIO_BITRATE),
+                max_width: device.max_width,
+                max_height: device.max_height,
+            }),
+        }
+    }
+
+    // Prefer containers that accept any codec; WebM is the last resort
+    fn output_container(device: &DeviceProfile) -> TranscodeContainer {
+        let supports = |names: &[&str]| {
+            device
+                .containers
+                .iter()
+                .any(|c| names.contains(&c.to_lowercase().as_str()))
+        };
+        if supports(&["ts", "mpegts"]) {
+            TranscodeContainer::MpegTs
+        } else if supports(&["mp4"]) {
+            TranscodeContainer::Mp4
+        } else if supports(&["webm"]) {
+            TranscodeContainer::WebM
+        } else {
+            TranscodeContainer::MpegTs
+        }
+    }
+
+    // The first encoder the device can decode, or the most compatible one
+    fn pick_codec(supported: &[String], encoders: &[&str]) -> String {
+        encoders
+            .iter()
+            .find(|codec| supported.iter().any(|s| s.eq_ignore_ascii_case(codec)))
+            .unwrap_or(&encoders[0])
+            .to_string()
+    }
+
+    fn transcode_url(
+        media_id: Uuid,
+        session_id: Uuid,
+        options: &TranscodeOptions,
+        offset: Option<f64>,
+    ) -> String {
+        let container = match options.container {
+            TranscodeContainer::MpegTs => "mpegts",
+            TranscodeContainer::Mp4 => "mp4",
+            TranscodeContainer::WebM => "webm",
+        };
+        let mut url = format!(
+            "/v1/media/{}/transcode?session_id={}&container={}&video_codec={}&audio_codec={}",
+            media_id, session_id, container, options.video_codec, options.audio_codec
+        );
+        let params = [
+            ("offset", offset.map(|o| o.to_string())),
+            (
+                "video_bitrate",
+                options.video_bitrate.map(|v| v.to_string()),
+            ),
+            (
+                "audio_bitrate",
+                options.audio_bitrate.map(|v| v.to_string()),
+            ),
+            ("max_width", options.max_width.map(|v| v.to_string())),
+            ("max_height", options.max_height.map(|v| v.to_string())),
+        ];
+        for (key, value) in params {
+            if let Some(value) = value {
+                url.push_str(&format!("&{}={}", key, value));
+            }
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h264_mkv() -> SourceInfo {
+        SourceInfo {
+            container: Some("mkv".to_string()),
+            video_codec: Some("h264".to_string()),
+            audio_codec: Some("aac".to_string()),
+            width: Some(1920),
+            height: Some(1080),
+            bitrate: Some(8000),
+            duration: Some(5400.0),
+            subtitle_formats: vec![],
+            has_video: true,
+        }
+    }
+
+    fn device(containers: &[&str], video_codecs: &[&str], audio_codecs: &[&str]) -> DeviceProfile {
+        let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        DeviceProfile {
+            containers: list(containers),
+            video_codecs: list(video_codecs),
+            audio_codecs: list(audio_codecs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compatible_sources_play_directly() {
+        let source = SourceInfo {
+            container: Some("MP4".to_string()),
+            ..h264_mkv()
+        };
+        let decision = MediaStreamer::decide(&source, &DeviceProfile::default());
+        assert_eq!(decision.method, PlayMethod::DirectPlay);
+        assert!(decision.transcode.is_none());
+    }
+
+    #[test]
+    fn unprobed_sources_are_assumed_playable() {
+        let decision = MediaStreamer::decide(&SourceInfo::default(), &DeviceProfile::default());
+        assert_eq!(decision.method, PlayMethod::DirectPlay);
+    }
+
+    #[test]
+    fn unsupported_containers_are_remuxed() {
+        let device = device(&["mp4", "ts"], &["h264"], &["aac"]);
+        let decision = MediaStreamer::decide(&h264_mkv(), &device);
+        assert_eq!(decision.method, PlayMethod::Remux);
+        let options = decision.transcode.unwrap();
+        assert_eq!(options.container, TranscodeContainer::MpegTs);
+        assert_eq!(options.video_codec, "copy");
+        assert_eq!(options.audio_codec, "copy");
+        assert!(decision.reasons[0].contains("container 'mkv'"));
+    }
+
+    #[test]
+    fn webm_only_devices_get_a_transcode_instead_of_a_remux() {
+        let device = device(&["webm"], &["h264", "vp9"], &["aac", "opus"]);
+        let decision = MediaStreamer::decide(&h264_mkv(), &device);
+        assert_eq!(decision.method, PlayMethod::Transcode);
+        let options = decision.transcode.unwrap();
+        assert_eq!(options.container, TranscodeContainer::WebM);
+        assert_eq!(options.video_codec, "vp9");
+        assert_eq!(options.audio_codec, "opus");
+    }
+
+    #[test]
+    fn unsupported_audio_keeps_the_video() {
+        let source = SourceInfo {
+            audio_codec: Some("dts".to_string()),
+            ..h264_mkv()
+        };
+        let device = device(&["mp4"], &["h264"], &["aac"]);
+        let decision = MediaStreamer::decide(&source, &device);
+        assert_eq!(decision.method, PlayMethod::Transcode);
+        let options = decision.transcode.unwrap();
+        assert_eq!(options.container, TranscodeContainer::Mp4);
+        assert_eq!(options.video_codec, "copy");
+        assert_eq!(options.audio_codec, "aac");
+        assert!(
+            decision
+                .reasons
+                .iter()
+                .any(|r| r.contains("audio codec 'dts'"))
+        );
+    }
+
+    #[test]
+    fn oversized_or_too_fast_video_is_reencoded_within_the_limits() {
+        let device = DeviceProfile {
+            max_width: Some(1280),
+            max_height: Some(720),
+            max_bitrate: Some(3000),
+            ..device(&["mp4"], &["hevc", "h264"], &["aac"])
+        };
+        let source = SourceInfo {
+            container: Some("mp4".to_string()),
+            ..h264_mkv()
+        };
+        let decision = MediaStreamer::decide(&source, &device);
+        assert_eq!(decision.method, PlayMethod::Transcode);
+        assert_eq!(decision.reasons.len(), 2);
+        let options = decision.transcode.unwrap();
+        assert_eq!(options.video_codec, "h264");
+        assert_eq!(options.video_bitrate, Some(3000 - AUDIO_BITRATE));
+        assert_eq!(
+            (options.max_width, options.max_height),
+            (Some(1280), Some(720))
+        );
+    }
+
+    #[test]
+    fn audio_files_ignore_video_limits() {
+        let source = SourceInfo {
+            container: Some("flac".to_string()),
+            audio_codec: Some("flac".to_string()),
+            has_video: false,
+            ..Default::default()
+        };
+        let device = DeviceProfile {
+            max_width: Some(640),
+            ..device(&["flac"], &[], &["flac"])
+        };
+        let decision = MediaStreamer::decide(&source, &device);
+        assert_eq!(decision.method, PlayMethod::DirectPlay);
+    }
+
+    #[test]
+    fn unsupported_subtitles_never_force_a_transcode() {
+        let source = SourceInfo {
+            container: Some("mp4".to_string()),
+            subtitle_formats: vec!["pgs".to_string()],
+            ..h264_mkv()
+        };
+        let decision = MediaStreamer::decide(&source, &DeviceProfile::default());
+        assert_eq!(decision.method, PlayMethod::DirectPlay);
+        assert!(decision.reasons.iter().any(|r| r.contains("pgs")));
+    }
+}
@@ -0,0 +1,865 @@
+use async_trait::async_trait;
+use axum::body::Body;
+use bytes::{Bytes, BytesMut};
+use futures::{
+    StreamExt,
+    future::{BoxFuture, join_all},
+    stream::FuturesUnordered,
+};
+use sea_orm::EntityTrait;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, mpsc, oneshot},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+use webrtc::{
+    data_channel::{RTCDataChannel, data_channel_message::DataChannelMessage},
+    peer_connection::RTCPeerConnection,
+};
+
+use crate::{
+    errors::AppError,
+    media::{
+        errors::MediaError,
+        models::P2PPeer,
+        services::{
+            chunks::{ChunkManifest, ChunkStore},
+            http_fallback::{FileStream, HttpStreamer},
+            p2p::{ChunkReply, ChunkRequest},
+            paths::MediaPaths,
+            peers::PeerRegistry,
+            throttle::StreamContext,
+        },
+    },
+    state::AppState,
+};
+
+const MAX_SWARM_PEERS: usize = 4;
+const URGENT_CHUNKS: u32 = 2; // next chunks needed by the playhead, fetched from the fastest source
+const PREFETCH_WINDOW: u32 = 16;
+const ORIGIN_SLOTS: usize = 2;
+const PEER_SLOTS: usize = 1;
+const STALL_TIMEOUT: Duration = Duration::from_secs(3); // before the origin races a slow peer
+const PEER_CHUNK_TIMEOUT: Duration = Duration::from_secs(20);
+const CHANNEL_OPEN_TIMEOUT: Duration = Duration::from_secs(5);
+const CHANNEL_IDLE_TIMEOUT: Duration = Duration::from_secs(60); // before a session's channels are closed
+const ASSIGN_INTERVAL: Duration = Duration::from_millis(250);
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+const INITIAL_THROUGHPUT: f64 = 1024.0 * 1024.0; // bytes per second, until measured
+
+/// Somewhere a verified chunk can be fetched from.
+#[async_trait]
+pub trait ChunkSource: Send + Sync {
+    /// The peer id, or `None` for the origin.
+    fn peer_id(&self) -> Option<&str>;
+    fn has_chunk(&self, index: u32) -> bool;
+    async fn fetch(&self, index: u32, offset: u64, length: u64) -> Result<Bytes, MediaError>;
+}
+
+/// The server's own copy, the same bytes `HttpStreamer` serves.
+pub struct OriginSource {
+    path: PathBuf,
+}
+
+#[async_trait]
+impl ChunkSource for OriginSource {
+    fn peer_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn has_chunk(&self, _index: u32) -> bool {
+        true
+    }
+
+    async fn fetch(&self, _index: u32, offset: u64, length: u64) -> Result<Bytes, MediaError> {
+        let mut file = File::open(&self.path)
+            .await
+            .map_err(|_| MediaError::NotFound)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        let mut buffer = vec![0; length as usize];
+        file.read_exact(&mut buffer)
+            .await
+            .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        Ok(Bytes::from(buffer))
+    }
+}
+
+/// A client peer reached over a data channel on its connection to the server.
+pub struct PeerSource {
+    peer_id: String,
+    media_id: Uuid,
+    chunks: Option<HashSet<u32>>, // None when the peer has the full file
+    channel: Arc<RTCDataChannel>,
+    // One request at a time, so replies can't interleave; range requests of
+    // one session share the channel
+    replies: Mutex<mpsc::UnboundedReceiver<DataChannelMessage>>,
+    next_id: AtomicU64,
+}
+
+impl PeerSource {
+    pub async fn open(
+        connection: &RTCPeerConnection,
+        peer: &P2PPeer,
+        media_id: Uuid,
+    ) -> Result<Self, MediaError> {
+        let channel = connection
+            .create_data_channel("swarm", None)
+            .await
+            .map_err(|_| MediaError::P2PConnectionFailed)?;
+
+        let (open_tx, open_rx) = oneshot::channel();
+        let open_tx = std::sync::Mutex::new(Some(open_tx));
+        channel.on_open(Box::new(move || {
+            if let Some(tx) = open_tx.lock().unwrap().take() {
+                let _ = tx.send(());
+            }
+            Box::pin(async {})
+        }));
+        let (reply_tx, reply_rx) = mpsc::unbounded_channel();
+        channel.on_message(Box::new(move |message| {
+            let _ = reply_tx.send(message);
+            Box::pin(async {})
+        }));
+
+        tokio::time::timeout(CHANNEL_OPEN_TIMEOUT, open_rx)
+            .await
+            .map_err(|_| MediaError::P2PConnectionFailed)?
+            .map_err(|_| MediaError::P2PConnectionFailed)?;
+
+        Ok(PeerSource {
+            peer_id: peer.peer_id.clone(),
+            media_id,
+            chunks: (!peer.has_full_file).then(|| peer.chunks.iter().copied().collect()),
+            channel,
+            replies: Mutex::new(reply_rx),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    async fn receive(
+        replies: &mut mpsc::UnboundedReceiver<DataChannelMessage>,
+        id: u64,
+        length: u64,
+    ) -> Result<Bytes, MediaError> {
+        let mut data = BytesMut::with_capacity(length as usize);
+        let mut started = false;
+
+        while let Some(message) = replies.recv().await {
+            if !message.is_string {
+                if started {
+                    data.extend_from_slice(&message.data);
+                }
+                continue;
+            }
+            match serde_json::from_slice::<ChunkReply>(&message.data) {
+                Ok(ChunkReply::Chunk { id: reply_id, .. }) if reply_id == id => started = true,
+                Ok(ChunkReply::End { id: reply_id }) if reply_id == id => {
+                    return Ok(data.freeze());
+                }
+                Ok(ChunkReply::Error {
+                    id: reply_id,
+                    message,
+                }) if reply_id == id => return Err(MediaError::StreamingError(message)),
+                // Leftovers from an earlier request that timed out
+                _ => {}
+            }
+        }
+        Err(MediaError::P2PConnectionFailed)
+    }
+}
+
+#[async_trait]
+impl ChunkSource for PeerSource {
+    fn peer_id(&self) -> Option<&str> {
+        Some(&self.peer_id)
+    }
+
+    fn has_chunk(&self, index: u32) -> bool {
+        self.chunks
+            .as_ref()
+            .is_none_or(|chunks| chunks.contains(&index))
+    }
+
+    async fn fetch(&self, index: u32, _offset: u64, length: u64) -> Result<Bytes, MediaError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::to_string(&ChunkRequest::Chunk {
+            id,
+            media_id: self.media_id,
+            index,
+        })
+        .map_err(|e| MediaError::StreamingError(e.to_string()))?;
+        let mut replies = self.replies.lock().await;
+        self.channel
+            .send_text(request)
+            .await
+            .map_err(|_| MediaError::P2PConnectionFailed)?;
+
+        tokio::time::timeout(PEER_CHUNK_TIMEOUT, Self::receive(&mut replies, id, length))
+            .await
+            .map_err(|_| MediaError::StreamingError(format!("peer {} timed out", self.peer_id)))?
+    }
+}
+
+impl Drop for PeerSource {
+    fn drop(&mut self) {
+        let channel = self.channel.clone();
+        tokio::spawn(async move {
+            let _ = channel.close().await;
+        });
+    }
+}
+
+/// Data channels opened for a relayed session, reused by all of its range
+/// requests so seeking doesn't reopen them. Keyed by profile and session, so
+/// nobody else can attach to a session's channels by sending its id.
+#[derive(Clone, Default)]
+pub struct SwarmChannels {
+    sessions: Arc<std::sync::Mutex<HashMap<(Uuid, Uuid), SessionChannels>>>,
+}
+
+struct SessionChannels {
+    media_id: Uuid,
+    sources: Vec<Arc<dyn ChunkSource>>,
+    last_used: Instant,
+}
+
+impl SwarmChannels {
+    fn get(
+        &self,
+        profile_id: Uuid,
+        session_id: Uuid,
+        media_id: Uuid,
+    ) -> Option<Vec<Arc<dyn ChunkSource>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        Self::prune(&mut sessions);
+        let channels = sessions
+            .get_mut(&(profile_id, session_id))
+            .filter(|channels| channels.media_id == media_id)?;
+        channels.last_used = Instant::now();
+        Some(channels.sources.clone())
+    }
+
+    fn insert(
+        &self,
+        profile_id: Uuid,
+        session_id: Uuid,
+        media_id: Uuid,
+        sources: Vec<Arc<dyn ChunkSource>>,
+    ) {
+        let mut sessions = self.sessions.lock().unwrap();
+        Self::prune(&mut sessions);
+        sessions.insert(
+            (profile_id, session_id),
+            SessionChannels {
+                media_id,
+                sources,
+                last_used: Instant::now(),
+            },
+        );
+    }
+
+    // Dropping the last handle to a source closes its channel
+    fn prune(sessions: &mut HashMap<(Uuid, Uuid), SessionChannels>) {
+        sessions.retain(|_, channels| channels.last_used.elapsed() < CHANNEL_IDLE_TIMEOUT);
+    }
+}
+
+struct SourceState {
+    source: Arc<dyn ChunkSource>,
+    throughput: f64, // smoothed, in bytes per second
+    in_flight: usize,
+    failed: bool,
+}
+
+impl SourceState {
+    fn slots(&self) -> usize {
+        if self.source.peer_id().is_some() {
+            PEER_SLOTS
+        } else {
+            ORIGIN_SLOTS
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        !self.failed && self.in_flight < self.slots()
+    }
+}
+
+type Fetch = BoxFuture<'static, (usize, u32, Instant, Result<Bytes, MediaError>)>;
+
+/// Fetches chunks in parallel from peers and the origin and hands them back in order.
+///
+/// Chunks next to the playhead go to the fastest idle source; the prefetch
+/// window is filled rarest-first so scarce chunks are taken while their
+/// holders are still around. Everything is verified against the manifest.
+pub struct SwarmScheduler {
+    state: AppState,
+    session_id: Option<Uuid>,
+    manifest: ChunkManifest,
+    sources: Vec<SourceState>,
+}
+
+impl SwarmScheduler {
+    pub fn new(
+        state: AppState,
+        session_id: Option<Uuid>,
+        manifest: ChunkManifest,
+        origin: Arc<dyn ChunkSource>,
+        peers: Vec<Arc<dyn ChunkSource>>,
+    ) -> Self {
+        let sources = std::iter::once(origin)
+            .chain(peers)
+            .map(|source| SourceState {
+                source,
+                throughput: INITIAL_THROUGHPUT,
+                in_flight: 0,
+                failed: false,
+            })
+            .collect();
+        SwarmScheduler {
+            state,
+            session_id,
+            manifest,
+            sources,
+        }
+    }
+
+    /// Sends bytes `start..=end` of the file, in order, until done or the receiver is dropped.
+    pub async fn run(mut self, start: u64, end: u64, tx: mpsc::Sender<std::io::Result<Bytes>>) {
+        let chunk_size = self.manifest.chunk_size as u64;
+        let first = (start / chunk_size) as u32;
+        let last = (end / chunk_size) as u32;
+
+        let mut in_flight: FuturesUnordered<Fetch> = FuturesUnordered::new();
+        let mut requested: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut started_at: HashMap<u32, Instant> = HashMap::new();
+        let mut completed: BTreeMap<u32, Bytes> = BTreeMap::new();
+        let mut next = first;
+
+        while next <= last {
+            while let Some(data) = completed.remove(&next) {
+                let (offset, _) = self.manifest.chunk_range(next).unwrap_or_default();
+                let from = start.saturating_sub(offset) as usize;
+                let to = ((end + 1).saturating_sub(offset) as usize).min(data.len());
+                if tx.send(Ok(data.slice(from..to))).await.is_err() {
+                    return;
+                }
+                next += 1;
+            }
+            if next > last {
+                break;
+            }
+
+            self.assign(
+                next,
+                last,
+                &mut requested,
+                &mut started_at,
+                &completed,
+                &mut in_flight,
+            );
+
+            let Ok(Some((source, index, began, result))) =
+                tokio::time::timeout(ASSIGN_INTERVAL, in_flight.next()).await
+            else {
+                continue;
+            };
+            self.sources[source].in_flight -= 1;
+            if let Some(holders) = requested.get_mut(&index) {
+                holders.retain(|holder| *holder != source);
+            }
+            // Another source already won the race for this chunk
+            if index < next || completed.contains_key(&index) {
+                continue;
+            }
+
+            match result {
+                Ok(data) if self.manifest.verify(index, &data) => {
+                    let elapsed = began.elapsed().as_secs_f64().max(0.001);
+                    let state = &mut self.sources[source];
+                    state.throughput = state.throughput * (1.0 - THROUGHPUT_SMOOTHING)
+                        + data.len() as f64 / elapsed * THROUGHPUT_SMOOTHING;
+                    if let Some(session_id) = self.session_id {
+                        self.state.sessions.record_transfer(
+                            session_id,
+                            state.source.peer_id().is_some(),
+                            data.len() as u64,
+                        );
+                    }
+                    requested.remove(&index);
+                    started_at.remove(&index);
+                    completed.insert(index, data);
+                }
+                Ok(_) => {
+                    let state = &mut self.sources[source];
+                    state.failed = true;
+                    match state.source.peer_id() {
+                        Some(peer_id) => {
+                            tracing::warn!("peer {} sent a corrupt chunk {}", peer_id, index);
+                            self.state.p2p.ban(peer_id);
+                        }
+                        None => {
+                            let _ = tx
+                                .send(Err(std::io::Error::other(
+                                    "media file changed since it was hashed",
+                                )))
+                                .await;
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let state = &mut self.sources[source];
+                    if state.source.peer_id().is_none() {
+                        let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                        return;
+                    }
+                    tracing::debug!("dropping swarm peer after error: {}", e);
+                    state.failed = true;
+                }
+            }
+        }
+    }
+
+    fn assign(
+        &mut self,
+        next: u32,
+        last: u32,
+        requested: &mut HashMap<u32, Vec<usize>>,
+        started_at: &mut HashMap<u32, Instant>,
+        completed: &BTreeMap<u32, Bytes>,
+        in_flight: &mut FuturesUnordered<Fetch>,
+    ) {
+        let window_end = last.min(next + PREFETCH_WINDOW - 1);
+        let pending: Vec<u32> = (next..=window_end)
+            .filter(|index| !completed.contains_key(index))
+            .filter(|index| {
+                requested
+                    .get(index)
+                    .is_none_or(|holders| holders.is_empty())
+            })
+            .collect();
+
+        // The playhead can't wait for rarity, so use whoever is fastest
+        for &index in pending
+            .iter()
+            .filter(|index| **index < next + URGENT_CHUNKS)
+        {
+            if let Some(source) = self.fastest_idle(index, false) {
+                self.start(source, index, requested, started_at, in_flight);
+            }
+        }
+
+        // The origin races peers that are too slow with the next chunk
+        if let Some(began) = started_at.get(&next)
+            && began.elapsed() > STALL_TIMEOUT
+            && requested
+                .get(&next)
+                .is_some_and(|holders| holders.iter().all(|s| *s != 0))
+            && self.sources[0].is_idle()
+        {
+            self.start(0, next, requested, started_at, in_flight);
+        }
+
+        let mut prefetch: Vec<(usize, u32)> = pending
+            .into_iter()
+            .filter(|index| *index >= next + URGENT_CHUNKS)
+            .filter(|index| {
+                requested
+                    .get(index)
+                    .is_none_or(|holders| holders.is_empty())
+            })
+            .map(|index| (self.rarity(index), index))
+            .collect();
+        prefetch.sort();
+
+        for (rarity, index) in prefetch {
+            // Chunks no peer holds can only come from the origin
+            let source = if rarity == 0 {
+                self.sources[0].is_idle().then_some(0)
+            } else {
+                self.fastest_idle(index, true)
+            };
+            if let Some(source) = source {
+                self.start(source, index, requested, started_at, in_flight);
+            }
+        }
+    }
+
+    fn rarity(&self, index: u32) -> usize {
+        self.sources
+            .iter()
+            .filter(|s| !s.failed && s.source.peer_id().is_some() && s.source.has_chunk(index))
+            .count()
+    }
+
+    fn fastest_idle(&self, index: u32, peers_only: bool) -> Option<usize> {
+        let chunk_length = self
+            .manifest
+            .chunk_range(index)
+            .map(|(_, length)| length)
+            .unwrap_or(self.manifest.chunk_size as u64) as f64;
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_idle() && s.source.has_chunk(index))
+            .filter(|(_, s)| !peers_only || s.source.peer_id().is_some())
+            // Expected time to finish, counting work already queued on the source
+            .min_by(|(_, a), (_, b)| {
+                let a = chunk_length * (a.in_flight + 1) as f64 / a.throughput;
+                let b = chunk_length * (b.in_flight + 1) as f64 / b.throughput;
+                a.total_cmp(&b)
+            })
+            .map(|(source, _)| source)
+    }
+
+    fn start(
+        &mut self,
+        source: usize,
+        index: u32,
+        requested: &mut HashMap<u32, Vec<usize>>,
+        started_at: &mut HashMap<u32, Instant>,
+        in_flight: &mut FuturesUnordered<Fetch>,
+    ) {
+        let Some((offset, length)) = self.manifest.chunk_range(index) else {
+            return;
+        };
+        let state = &mut self.sources[source];
+        state.in_flight += 1;
+        requested.entry(index).or_default().push(source);
+        started_at.entry(index).or_insert_with(Instant::now);
+
+        let chunk_source = state.source.clone();
+        in_flight.push(Box::pin(async move {
+            let began = Instant::now();
+            let result = chunk_source.fetch(index, offset, length).await;
+            (source, index, began, result)
+        }));
+    }
+}
+
+/// Relays a media file from the swarm for clients that can't open peer
+/// connections themselves, falling back to plain HTTP when there is nothing
+/// to gain from peers. The bytes still leave the server, so clients that can
+/// should fetch chunks from peers directly instead.
+pub struct SwarmStreamer;
+
+impl SwarmStreamer {
+    pub async fn stream(
+        state: AppState,
+        media_id: Uuid,
+        range_header: Option<String>,
+        context: StreamContext,
+    ) -> Result<FileStream, AppError> {
+        let media = entity::media::Entity::find_by_id(media_id)
+            .one(&state.conn)
+            .await?
+            .ok_or(AppError::NotFound)?;
+        let manifest = ChunkStore::load(&state, media.id)
+            .await?
+            .filter(|m| m.size > 0);
+        let peers = match &manifest {
+            Some(manifest) => Self::connect_peers(&state, media.id, manifest, &context).await?,
+            None => vec![],
+        };
+
+        let Some(manifest) = manifest.filter(|_| !peers.is_empty()) else {
+            return HttpStreamer::stream_file(state, media_id, range_header, context).await;
+        };
+
+        let size = manifest.size;
+        let (start, end, content_range) = match range_header {
+            Some(range) => {
+                let (start, end) = HttpStreamer::parse_range(&range, size)?;
+                (
+                    start,
+                    end,
+                    Some(format!("bytes {}-{}/{}", start, end, size)),
+                )
+            }
+            None => (0, size - 1, None),
+        };
+
+        let origin: Arc<dyn ChunkSource> = Arc::new(OriginSource {
+            path: MediaPaths::resolve(&state, &media).await?,
+        });
+        let scheduler =
+            SwarmScheduler::new(state.clone(), context.session_id, manifest, origin, peers);
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(scheduler.run(start, end, tx));
+
+        let content_type = mime_guess::from_path(&media.file_path)
+            .first_or_octet_stream()
+            .to_string();
+        let body = Body::from_stream(
+            state
+                .bandwidth
+                .throttle(ReceiverStream::new(rx), context.clone()),
+        );
+
+        Ok(FileStream {
+            body,
+            content_type,
+            content_length: end - start + 1,
+            content_range,
+        })
+    }
+
+    // Only peers already connected to the server can be used, nearest first.
+    // A session reuses the channels its first range request opened
+    async fn connect_peers(
+        state: &AppState,
+        media_id: Uuid,
+        manifest: &ChunkManifest,
+        context: &StreamContext,
+    ) -> Result<Vec<Arc<dyn ChunkSource>>, AppError> {
+        if !state.allow_peer_to_peer {
+            return Ok(vec![]);
+        }
+
+        let (profile_id, session_id) = (context.profile_id, context.session_id);
+        let cached = session_id.and_then(|id| state.swarm.get(profile_id, id, media_id));
+        if let Some(mut sources) = cached {
+            // Peers that left or were banned since are dropped
+            sources.retain(|source| {
+                source
+                    .peer_id()
+                    .is_some_and(|peer_id| state.p2p.connection(peer_id).is_some())
+            });
+            if !sources.is_empty() {
+                return Ok(sources);
+            }
+        }
+
+        let peers: Vec<_> =
+            PeerRegistry::best_peers(state, media_id, profile_id, true, manifest.chunk_size)
+                .await?
+                .into_iter()
+                .filter_map(|peer| {
+                    let connection = state.p2p.connection(&peer.peer_id)?;
+                    Some((peer, connection))
+                })
+                .take(MAX_SWARM_PEERS)
+                .collect();
+        // Opened at once, so a peer that never answers costs one timeout in total
+        let opened = join_all(
+            peers
+                .iter()
+                .map(|(peer, connection)| PeerSource::open(connection, peer, media_id)),
+        )
+        .await;
+
+        let mut sources: Vec<Arc<dyn ChunkSource>> = Vec::new();
+        for ((peer, _), source) in peers.iter().zip(opened) {
+            match source {
+                Ok(source) => sources.push(Arc::new(source)),
+                Err(e) => tracing::debug!("couldn't open swarm channel to {}: {}", peer.peer_id, e),
+            }
+        }
+        if let Some(session_id) = session_id
+            && !sources.is_empty()
+        {
+            state
+                .swarm
+                .insert(profile_id, session_id, media_id, sources.clone());
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK: u32 = 4;
+
+    struct FakeSource {
+        peer_id: Option<String>,
+        chunks: Option<HashSet<u32>>, // `None` holds everything
+        data: Bytes,
+        corrupt: bool,
+    }
+
+    #[async_trait]
+    impl ChunkSource for FakeSource {
+        fn peer_id(&self) -> Option<&str> {
+            self.peer_id.as_deref()
+        }
+
+        fn has_chunk(&self, index: u32) -> bool {
+            self.chunks.as_ref().is_none_or(|c| c.contains(&index))
+        }
+
+        async fn fetch(&self, _index: u32, offset: u64, length: u64) -> Result<Bytes, MediaError> {
+            let data = self.data.slice(offset as usize..(offset + length) as usize);
+            if self.corrupt {
+                return Ok(Bytes::from(vec![0; data.len()]));
+            }
+            Ok(data)
+        }
+    }
+
+    fn data(chunks: u32) -> Bytes {
+        (0..chunks * CHUNK).map(|b| b as u8 + 1).collect()
+    }
+
+    fn manifest(data: &Bytes) -> ChunkManifest {
+        ChunkManifest {
+            algorithm: "blake3".to_string(),
+            chunk_size: CHUNK,
+            size: data.len() as u64,
+            root: String::new(),
+            hashes: data
+                .chunks(CHUNK as usize)
+                .map(|chunk| blake3::hash(chunk).to_hex().to_string())
+                .collect(),
+        }
+    }
+
+    fn origin(data: &Bytes) -> Arc<dyn ChunkSource> {
+        Arc::new(FakeSource {
+            peer_id: None,
+            chunks: None,
+            data: data.clone(),
+            corrupt: false,
+        })
+    }
+
+    fn peer(id: &str, chunks: &[u32], data: &Bytes) -> Arc<dyn ChunkSource> {
+        Arc::new(FakeSource {
+            peer_id: Some(id.to_string()),
+            chunks: Some(chunks.iter().copied().collect()),
+            data: data.clone(),
+            corrupt: false,
+        })
+    }
+
+    fn scheduler(data: &Bytes, peers: Vec<Arc<dyn ChunkSource>>) -> SwarmScheduler {
+        SwarmScheduler::new(
+            AppState::for_tests(),
+            None,
+            manifest(data),
+            origin(data),
+            peers,
+        )
+    }
+
+    /// Runs one assignment pass and returns who was asked for which chunk.
+    fn assign(
+        scheduler: &mut SwarmScheduler,
+        next: u32,
+        requested: &mut HashMap<u32, Vec<usize>>,
+        started_at: &mut HashMap<u32, Instant>,
+    ) {
+        let last = scheduler.manifest.hashes.len() as u32 - 1;
+        let mut in_flight = FuturesUnordered::new();
+        scheduler.assign(
+            next,
+            last,
+            requested,
+            started_at,
+            &BTreeMap::new(),
+            &mut in_flight,
+        );
+    }
+
+    #[test]
+    fn urgent_chunks_go_to_the_fastest_source() {
+        let data = data(2);
+        let mut scheduler = scheduler(&data, vec![peer("fast", &[0, 1], &data)]);
+        scheduler.sources[1].throughput = INITIAL_THROUGHPUT * 10.0;
+
+        let mut requested = HashMap::new();
+        assign(&mut scheduler, 0, &mut requested, &mut HashMap::new());
+
+        // The peer only has one slot, so the origin takes the second chunk
+        assert_eq!(requested[&0], [1]);
+        assert_eq!(requested[&1], [0]);
+    }
+
+    #[test]
+    fn prefetch_takes_the_rarest_chunks_first() {
+        let data = data(4);
+        let mut scheduler = scheduler(
+            &data,
+            vec![peer("fast", &[2, 3], &data), peer("slow", &[2], &data)],
+        );
+        scheduler.sources[1].throughput = INITIAL_THROUGHPUT * 10.0;
+        // The urgent chunks are already on their way from the origin
+        scheduler.sources[0].in_flight = ORIGIN_SLOTS;
+        let mut requested = HashMap::from([(0, vec![0]), (1, vec![0])]);
+        assign(&mut scheduler, 0, &mut requested, &mut HashMap::new());
+
+        // Chunk 3 has one holder, so it's claimed before chunk 2 can take it
+        assert_eq!(requested[&3], [1]);
+        assert_eq!(requested[&2], [2]);
+    }
+
+    #[test]
+    fn chunks_no_peer_holds_come_from_the_origin() {
+        let data = data(4);
+        let mut scheduler = scheduler(&data, vec![peer("peer", &[2], &data)]);
+        scheduler.sources[0].in_flight = 1;
+        scheduler.sources[1].in_flight = 1;
+        let mut requested = HashMap::from([(0, vec![0]), (1, vec![1])]);
+        assign(&mut scheduler, 0, &mut requested, &mut HashMap::new());
+
+        assert_eq!(requested[&3], [0]);
+        // Its only holder is busy, and prefetching doesn't fall back to the origin
+        assert!(!requested.contains_key(&2));
+    }
+
+    #[test]
+    fn the_origin_races_a_stalled_peer() {
+        let data = data(1);
+        let mut scheduler = scheduler(&data, vec![peer("slow", &[0], &data)]);
+        scheduler.sources[1].in_flight = 1;
+
+        let mut requested = HashMap::from([(0, vec![1])]);
+        let mut started_at = HashMap::from([(0, Instant::now())]);
+        assign(&mut scheduler, 0, &mut requested, &mut started_at);
+        assert_eq!(requested[&0], [1]);
+
+        started_at.insert(0, Instant::now() - STALL_TIMEOUT * 2);
+        assign(&mut scheduler, 0, &mut requested, &mut started_at);
+        assert_eq!(requested[&0], [1, 0]);
+
+        // Only once, however long the peer takes
+        assign(&mut scheduler, 0, &mut requested, &mut started_at);
+        assert_eq!(requested[&0], [1, 0]);
+    }
+
+    #[tokio::test]
+    async fn sends_the_requested_range_in_order_despite_corrupt_peers() {
+        let data = data(8);
+        let corrupt = Arc::new(FakeSource {
+            peer_id: Some("corrupt".to_string()),
+            chunks: None,
+            data: data.clone(),
+            corrupt: true,
+        });
+        let scheduler = scheduler(&data, vec![corrupt, peer("good", &[3, 4, 5], &data)]);
+
+        let (tx, mut rx) = mpsc::channel(64);
+        scheduler.run(5, 29, tx).await;
+        let mut received = BytesMut::new();
+        while let Some(chunk) = rx.recv().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(&received[..], &data[5..=29]);
+    }
+}
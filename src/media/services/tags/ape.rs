@@ -0,0 +1,137 @@
+use std::fs::File;
+
+use super::{CoverArt, MusicTags, read_at};
+use crate::media::services::probe::Bytes;
+
+const FOOTER_LEN: u64 = 32;
+
+/// Reads an APEv1/APEv2 tag from the end of the file, which may sit in front
+/// of an ID3v1 tag.
+pub(super) fn read(file: &mut File, size: u64) -> Option<MusicTags> {
+    let mut end = size;
+    if size >= 128 && read_at(file, size - 128, 3)?.as_slice() == b"TAG" {
+        end -= 128;
+    }
+    let footer = read_at(file, end.checked_sub(FOOTER_LEN)?, FOOTER_LEN)?;
+    let mut bytes = Bytes::new(&footer);
+    if bytes.take(8)? != b"APETAGEX" {
+        return None;
+    }
+    bytes.skip(4)?; // version
+    let tag_len = bytes.u32_le()? as u64; // items and footer, not the header
+    let count = bytes.u32_le()?;
+    let items = read_at(
+        file,
+        end.checked_sub(tag_len)?,
+        tag_len.checked_sub(FOOTER_LEN)?,
+    )?;
+
+    let mut tags = MusicTags::default();
+    let mut bytes = Bytes::new(&items);
+    for _ in 0..count {
+        let Some((key, flags, value)) = item(&mut bytes) else {
+            break;
+        };
+        // Bits 1-2 of the flags: 0 is UTF-8 text, 1 binary, 2 an external reference
+        match (flags >> 1) & 0b11 {
+            0 => {
+                // Multiple values are separated by NULs
+                let value = String::from_utf8_lossy(value).replace('\0', "; ");
+                tags.set(&key, &value);
+            }
+            1 if key.to_lowercase().starts_with("cover art") => {
+                // A file name, NUL, then the image
+                let image = match value.iter().position(|byte| *byte == 0) {
+                    Some(end) => &value[end + 1..],
+                    None => value,
+                };
+                let front = key.eq_ignore_ascii_case("Cover Art (Front)");
+                tags.set_cover(CoverArt::new("", image.to_vec()), front);
+            }
+            _ => {}
+        }
+    }
+    Some(tags)
+}
+
+// A value length, flags, a NUL-terminated ASCII key and the value
+fn item<'a>(bytes: &mut Bytes<'a>) -> Option<(String, u32, &'a [u8])> {
+    let len = bytes.u32_le()? as usize;
+    let flags = bytes.u32_le()?;
+    let mut key = Vec::new();
+    loop {
+        match bytes.u8()? {
+            0 => break,
+            byte => key.push(byte),
+        }
+    }
+    let value = bytes.take(len)?;
+    Some((String::from_utf8_lossy(&key).into_owned(), flags, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn ape_item(key: &str, flags: u32, value: &[u8]) -> Vec<u8> {
+        let mut data = (value.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value);
+        data
+    }
+
+    fn ape_tag(items: &[Vec<u8>]) -> Vec<u8> {
+        let body = items.concat();
+        let mut data = body.clone();
+        data.extend_from_slice(b"APETAGEX");
+        data.extend_from_slice(&2000u32.to_le_bytes());
+        data.extend_from_slice(&((body.len() as u64 + FOOTER_LEN) as u32).to_le_bytes());
+        data.extend_from_slice(&(items.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 12]); // flags and reserved
+        data
+    }
+
+    fn read_bytes(data: &[u8]) -> Option<MusicTags> {
+        read(&mut fixtures::file(data), data.len() as u64)
+    }
+
+    #[test]
+    fn reads_items_in_front_of_id3v1() {
+        let mut data = vec![0xFF; 32];
+        data.extend(ape_tag(&[
+            ape_item("Title", 0, b"Song"),
+            ape_item("Genre", 0, b"Jazz\0Funk"),
+            ape_item("Cover Art (Front)", 2, b"cover.jpg\0\xFF\xD8\xFF\xE0"),
+        ]));
+        data.extend(b"TAG");
+        data.extend([0; 125]);
+
+        let tags = read_bytes(&data).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.genres, ["Jazz", "Funk"]);
+        assert_eq!(tags.cover.unwrap().mime_type, "image/jpeg");
+    }
+
+    #[test]
+    fn malformed_tags_are_none_or_partial() {
+        // A tag length shorter than the footer, or longer than the file
+        for tag_len in [8u32, 10_000] {
+            let mut data = ape_tag(&[ape_item("Title", 0, b"Song")]);
+            let at = data.len() - 20;
+            data[at..at + 4].copy_from_slice(&tag_len.to_le_bytes());
+            assert!(read_bytes(&data).is_none());
+        }
+        assert!(read_bytes(b"APETAGEX").is_none());
+
+        // An item whose value runs past the tag ends the item list
+        let mut item = ape_item("Album", 0, b"Record");
+        item[0] = 0xFF;
+        let data = ape_tag(&[ape_item("Title", 0, b"Song"), item]);
+        let tags = read_bytes(&data).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.album, None);
+    }
+}
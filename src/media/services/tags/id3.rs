@@ -0,0 +1,580 @@
+use std::fs::File;
+
+use super::{CoverArt, MusicTags, read_at, year};
+use crate::media::services::probe::Bytes;
+
+// The standard list and Winamp's extensions up to 125
+const GENRES: &[&str] = &[
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+    "Folk",
+    "Folk-Rock",
+    "National Folk",
+    "Swing",
+    "Fast Fusion",
+    "Bebop",
+    "Latin",
+    "Revival",
+    "Celtic",
+    "Bluegrass",
+    "Avantgarde",
+    "Gothic Rock",
+    "Progressive Rock",
+    "Psychedelic Rock",
+    "Symphonic Rock",
+    "Slow Rock",
+    "Big Band",
+    "Chorus",
+    "Easy Listening",
+    "Acoustic",
+    "Humour",
+    "Speech",
+    "Chanson",
+    "Opera",
+    "Chamber Music",
+    "Sonata",
+    "Symphony",
+    "Booty Bass",
+    "Primus",
+    "Porn Groove",
+    "Satire",
+    "Slow Jam",
+    "Club",
+    "Tango",
+    "Samba",
+    "Folklore",
+    "Ballad",
+    "Power Ballad",
+    "Rhythmic Soul",
+    "Freestyle",
+    "Duet",
+    "Punk Rock",
+    "Drum Solo",
+    "A Cappella",
+    "Euro-House",
+    "Dance Hall",
+];
+
+pub(super) fn genre(index: usize) -> Option<String> {
+    GENRES.get(index).map(|genre| genre.to_string())
+}
+
+/// Reads an ID3v2 tag at the start of the file, returning it along with the
+/// offset where the audio (or the next container) starts.
+pub(super) fn read_v2(file: &mut File, size: u64) -> (Option<MusicTags>, u64) {
+    let Some(header) = read_at(file, 0, 10.min(size)) else {
+        return (None, 0);
+    };
+    if header.len() < 10 || !header.starts_with(b"ID3") {
+        return (None, 0);
+    }
+    let version = header[3];
+    let flags = header[5];
+    let tag_size = syncsafe(&header[6..10]);
+    let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+    let end = 10 + tag_size + footer;
+    if !(2..=4).contains(&version) {
+        return (None, end);
+    }
+
+    let tags = read_at(file, 10, tag_size).and_then(|body| {
+        // Before 2.4 unsynchronisation applies to the whole tag, in 2.4 to each frame
+        let body = if flags & 0x80 != 0 && version < 4 {
+            resync(&body)
+        } else {
+            body
+        };
+        parse_frames(&body, version, flags & 0x40 != 0)
+    });
+    (tags, end)
+}
+
+fn parse_frames(body: &[u8], version: u8, extended_header: bool) -> Option<MusicTags> {
+    let mut bytes = Bytes::new(body);
+    if extended_header && version >= 3 {
+        let size = Bytes::new(body).take(4)?;
+        let len = if version == 4 {
+            syncsafe(size) as usize // includes itself
+        } else {
+            u32::from_be_bytes(size.try_into().ok()?) as usize + 4
+        };
+        bytes.skip(len)?;
+    }
+
+    let mut tags = MusicTags::default();
+    loop {
+        let (id, frame_flags, data) = if version == 2 {
+            let Some(header) = bytes.take(6) else { break };
+            let id = v22_frame_id(&header[..3]);
+            let len = u32::from_be_bytes([0, header[3], header[4], header[5]]) as usize;
+            (id, 0, bytes.take(len))
+        } else {
+            let Some(header) = bytes.take(10) else { break };
+            let id: [u8; 4] = header[..4].try_into().ok()?;
+            let len = if version == 4 {
+                syncsafe(&header[4..8]) as usize
+            } else {
+                u32::from_be_bytes(header[4..8].try_into().ok()?) as usize
+            };
+            (
+                Some(id),
+                u16::from_be_bytes([header[8], header[9]]),
+                bytes.take(len),
+            )
+        };
+        // Padding, or a frame that runs past the tag
+        let Some(data) = data else { break };
+        if id.is_some_and(|id| id[0] == 0) {
+            break;
+        }
+        let Some(id) = id else { continue };
+        if let Some(data) = frame_data(data, version, frame_flags) {
+            parse_frame(&mut tags, &id, &data, version);
+        }
+    }
+    Some(tags)
+}
+
+// Undoes per-frame unsynchronisation and data length indicators; compressed
+// and encrypted frames are skipped
+fn frame_data(data: &[u8], version: u8, flags: u16) -> Option<Vec<u8>> {
+    match version {
+        3 => {
+            if flags & 0x00C0 != 0 {
+                return None;
+            }
+            let grouping = if flags & 0x0020 != 0 { 1 } else { 0 };
+            Some(data.get(grouping..)?.to_vec())
+        }
+        4 => {
+            if flags & 0x000C != 0 {
+                return None;
+            }
+            let mut start = if flags & 0x0040 != 0 { 1 } else { 0 };
+            if flags & 0x0001 != 0 {
+                start += 4;
+            }
+            let data = data.get(start..)?;
+            Some(if flags & 0x0002 != 0 {
+                resync(data)
+            } else {
+                data.to_vec()
+            })
+        }
+        _ => Some(data.to_vec()),
+    }
+}
+
+fn parse_frame(tags: &mut MusicTags, id: &[u8; 4], data: &[u8], version: u8) {
+    match id {
+        b"TIT2" => tags.set("TITLE", &text(data)),
+        b"TPE1" => tags.set("ARTIST", &text(data)),
+        b"TPE2" => tags.set("ALBUMARTIST", &text(data)),
+        b"TALB" => tags.set("ALBUM", &text(data)),
+        b"TRCK" => tags.set("TRACKNUMBER", &text(data)),
+        b"TPOS" => tags.set("DISCNUMBER", &text(data)),
+        b"TDRC" | b"TYER" | b"TDOR" | b"TORY" => tags.set("DATE", &text(data)),
+        b"TCOM" => tags.set("COMPOSER", &text(data)),
+        b"TCMP" => tags.set("COMPILATION", &text(data)),
+        b"TCON" => {
+            for value in text(data).split("; ") {
+                for genre in content_types(value) {
+                    tags.set("GENRE", &genre);
+                }
+            }
+        }
+        b"TXXX" => {
+            let Some((&encoding, rest)) = data.split_first() else {
+                return;
+            };
+            let (description, value) = split_terminated(rest, encoding);
+            tags.set(
+                &decode(description, encoding),
+                &decode(value, encoding).replace('\0', "; "),
+            );
+        }
+        b"UFID" => {
+            let (owner, identifier) = split_terminated(data, 0);
+            if owner == b"http://musicbrainz.org" {
+                tags.set("MUSICBRAINZ_TRACKID", &String::from_utf8_lossy(identifier));
+            }
+        }
+        b"APIC" => {
+            let Some((&encoding, rest)) = data.split_first() else {
+                return;
+            };
+            // v2.2 "PIC" frames have a three-letter image format instead of a mime type
+            let (mime_type, rest) = if version == 2 {
+                let Some(format) = rest.get(..3) else { return };
+                (format!("image/{}", decode(format, 0)), &rest[3..])
+            } else {
+                let (mime_type, rest) = split_terminated(rest, 0);
+                (decode(mime_type, 0), rest)
+            };
+            let Some((&picture_type, rest)) = rest.split_first() else {
+                return;
+            };
+            let (_, image) = split_terminated(rest, encoding);
+            tags.set_cover(CoverArt::new(&mime_type, image.to_vec()), picture_type == 3);
+        }
+        _ => {}
+    }
+}
+
+// v2.2 frames use three-letter IDs; these are the ones we read, under their v2.3 names
+fn v22_frame_id(id: &[u8]) -> Option<[u8; 4]> {
+    let id = match id {
+        b"TT2" => b"TIT2",
+        b"TP1" => b"TPE1",
+        b"TP2" => b"TPE2",
+        b"TAL" => b"TALB",
+        b"TRK" => b"TRCK",
+        b"TPA" => b"TPOS",
+        b"TYE" => b"TYER",
+        b"TOR" => b"TORY",
+        b"TCO" => b"TCON",
+        b"TCM" => b"TCOM",
+        b"TCP" => b"TCMP",
+        b"TXX" => b"TXXX",
+        b"UFI" => b"UFID",
+        b"PIC" => b"APIC",
+        [0, ..] => b"\0\0\0\0",
+        _ => return None,
+    };
+    Some(*id)
+}
+
+// "(17)", "(17)Rock", "17", "Rock", or the "(RX)" remix and "(CR)" cover markers
+fn content_types(value: &str) -> Vec<String> {
+    let mut genres = Vec::new();
+    let mut rest = value.trim();
+    while let Some(inner) = rest.strip_prefix('(') {
+        let Some(end) = inner.find(')') else { break };
+        match &inner[..end] {
+            "RX" => genres.push("Remix".to_string()),
+            "CR" => genres.push("Cover".to_string()),
+            number => genres.extend(number.parse().ok().and_then(genre)),
+        }
+        rest = &inner[end + 1..];
+    }
+    match rest.parse() {
+        Ok(number) => genres.extend(genre(number)),
+        Err(_) if !rest.is_empty() => genres.push(rest.to_string()),
+        Err(_) => {}
+    }
+    genres
+}
+
+/// Reads the fixed 128-byte ID3v1 tag at the end of the file, if there is one.
+pub(super) fn read_v1(file: &mut File, size: u64) -> Option<MusicTags> {
+    if size < 128 {
+        return None;
+    }
+    let tag = read_at(file, size - 128, 128)?;
+    if !tag.starts_with(b"TAG") {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| {
+        let value = decode(&tag[range], 0);
+        value.trim_end_matches(['\0', ' ']).to_string()
+    };
+
+    let mut tags = MusicTags::default();
+    tags.set("TITLE", &field(3..33));
+    tags.set("ARTIST", &field(33..63));
+    tags.set("ALBUM", &field(63..93));
+    tags.year = year(&field(93..97));
+    // ID3v1.1 steals the comment's last two bytes for the track number
+    if tag[125] == 0 && tag[126] != 0 {
+        tags.track_number = Some(tag[126] as u32);
+    }
+    tags.genres.extend(genre(tag[127] as usize));
+    Some(tags)
+}
+
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |size, byte| (size << 7) | (*byte & 0x7F) as u64)
+}
+
+// Drops the zero byte inserted after every 0xFF
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0) {
+            out.push(byte);
+        }
+        previous = byte;
+    }
+    out
+}
+
+// A text frame's value; v2.4 separates multiple values with NULs
+fn text(data: &[u8]) -> String {
+    match data.split_first() {
+        Some((&encoding, value)) => decode(value, encoding)
+            .trim_end_matches('\0')
+            .replace('\0', "; "),
+        None => String::new(),
+    }
+}
+
+// Splits at the string terminator, which is two bytes wide for UTF-16
+fn split_terminated(data: &[u8], encoding: u8) -> (&[u8], &[u8]) {
+    if matches!(encoding, 1 | 2) {
+        let end = data
+            .chunks_exact(2)
+            .position(|pair| pair == [0, 0])
+            .map(|pair| pair * 2);
+        match end {
+            Some(end) => (&data[..end], &data[end + 2..]),
+            None => (data, &[]),
+        }
+    } else {
+        match data.iter().position(|byte| *byte == 0) {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => (data, &[]),
+        }
+    }
+}
+
+// 0: ISO-8859-1, 1: UTF-16 with a BOM, 2: UTF-16BE, 3: UTF-8
+fn decode(data: &[u8], encoding: u8) -> String {
+    match encoding {
+        1 | 2 => {
+            let mut big_endian = encoding == 2;
+            let mut units = Vec::with_capacity(data.len() / 2);
+            for pair in data.chunks_exact(2) {
+                match pair {
+                    [0xFE, 0xFF] => big_endian = true,
+                    [0xFF, 0xFE] => big_endian = false,
+                    [a, b] if big_endian => units.push(u16::from_be_bytes([*a, *b])),
+                    [a, b] => units.push(u16::from_le_bytes([*a, *b])),
+                    _ => {}
+                }
+            }
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(data).into_owned(),
+        _ => data.iter().map(|byte| char::from(*byte)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn tag(version: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![b'I', b'D', b'3', version, 0, flags];
+        let size = body.len() as u32;
+        data.extend([21, 14, 7, 0].map(|shift| ((size >> shift) & 0x7F) as u8));
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn frame(id: &[u8; 4], flags: u16, body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        data.extend_from_slice(&flags.to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn v22_frame(id: &[u8; 3], body: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn read(data: &[u8]) -> (Option<MusicTags>, u64) {
+        read_v2(&mut fixtures::file(data), data.len() as u64)
+    }
+
+    #[test]
+    fn reads_v23_text_frames() {
+        let mut body = frame(b"TIT2", 0, b"\x03Song");
+        // UTF-16 with a little-endian BOM
+        body.extend(frame(b"TPE1", 0, b"\x01\xFF\xFEA\0b\0"));
+        body.extend(frame(b"TCON", 0, b"\x00(17)(RX)"));
+        body.extend([0; 16]); // padding
+        let data = tag(3, 0, &body);
+
+        let (tags, end) = read(&data);
+        let tags = tags.unwrap();
+        assert_eq!(end, data.len() as u64);
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist.as_deref(), Some("Ab"));
+        assert_eq!(tags.genres, ["Rock", "Remix"]);
+    }
+
+    #[test]
+    fn undoes_whole_tag_unsynchronisation_before_v24() {
+        // The frame size counts the bytes after resynchronisation
+        let body = [
+            b"TIT2".as_slice(),
+            &[0, 0, 0, 4, 0, 0],
+            &[0, b'a', 0xFF, 0x00, b'b'],
+        ]
+        .concat();
+        let (tags, _) = read(&tag(3, 0x80, &body));
+        assert_eq!(tags.unwrap().title.as_deref(), Some("a\u{FF}b"));
+    }
+
+    #[test]
+    fn undoes_v24_frame_unsynchronisation_and_data_length() {
+        // Unsynchronised, with a data length indicator in front of the value
+        let body = frame(b"TIT2", 0x0003, &[0, 0, 0, 4, 0, b'a', 0xFF, 0x00, b'b']);
+        let (tags, _) = read(&tag(4, 0, &body));
+        assert_eq!(tags.unwrap().title.as_deref(), Some("a\u{FF}b"));
+
+        // Compressed frames are skipped
+        let mut body = frame(b"TIT2", 0x0008, b"\x00zlib");
+        body.extend(frame(b"TALB", 0, b"\x00Album"));
+        let tags = read(&tag(4, 0, &body)).0.unwrap();
+        assert_eq!(tags.title, None);
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+    }
+
+    #[test]
+    fn maps_v22_frames() {
+        let mut body = v22_frame(b"TT2", b"\x00Title");
+        body.extend(v22_frame(b"XYZ", b"\x00ignored"));
+        body.extend(v22_frame(b"TP1", b"\x00Artist"));
+        body.extend(v22_frame(
+            b"PIC",
+            &[b"\x00PNG\x03\0".as_slice(), PNG].concat(),
+        ));
+        let tags = read(&tag(2, 0, &body)).0.unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.cover.unwrap().mime_type, "image/png");
+    }
+
+    #[test]
+    fn frames_past_the_tag_end_the_frame_list() {
+        let mut body = frame(b"TIT2", 0, b"\x00Title");
+        body.extend(b"TALB\x00\x00\x10\x00\x00\x00\x00Album");
+        let tags = read(&tag(3, 0, &body)).0.unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.album, None);
+    }
+
+    #[test]
+    fn malformed_tags_are_none() {
+        assert_eq!(read(b"ID3\x03").1, 0);
+        assert!(read(b"ID3\x03").0.is_none());
+        assert!(read(b"\xFF\xFB\x50\x64").0.is_none());
+
+        // A tag larger than the file still reports where the audio would start
+        let mut data = tag(3, 0, &frame(b"TIT2", 0, b"\x00Title"));
+        data[9] = 0x7F;
+        let (tags, end) = read(&data);
+        assert!(tags.is_none());
+        assert_eq!(end, 10 + 0x7F + ((data[8] as u64) << 7));
+
+        let data = tag(5, 0, &frame(b"TIT2", 0, b"\x00Title"));
+        assert!(read(&data).0.is_none());
+    }
+
+    #[test]
+    fn reads_v11_track_numbers() {
+        let mut tag = b"TAG".to_vec();
+        tag.extend(format!("{:<30}", "Title").bytes());
+        tag.extend([0; 60]);
+        tag.extend(b"1999");
+        tag.extend([0; 28]);
+        tag.extend([0, 7, 17]);
+        let mut data = vec![0xFF; 64];
+        data.extend(tag);
+
+        let tags = read_v1(&mut fixtures::file(&data), data.len() as u64).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.year, Some(1999));
+        assert_eq!(tags.track_number, Some(7));
+        assert_eq!(tags.genres, ["Rock"]);
+    }
+}
@@ -0,0 +1,358 @@
+mod ape;
+mod id3;
+mod mp4;
+mod vorbis;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use super::probe::read_at;
+
+/// Identifiers MusicBrainz Picard writes, named after its Vorbis comment keys.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MusicBrainzIds {
+    pub recording_id: Option<String>, // MUSICBRAINZ_TRACKID
+    pub track_id: Option<String>,     // MUSICBRAINZ_RELEASETRACKID
+    pub release_id: Option<String>,   // MUSICBRAINZ_ALBUMID
+    pub release_group_id: Option<String>,
+    pub artist_id: Option<String>,
+    pub album_artist_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>, // in dB
+    pub track_peak: Option<f64>, // linear, 1.0 is full scale
+    pub album_gain: Option<f64>, // in dB
+    pub album_peak: Option<f64>,
+}
+
+/// An embedded picture; the image itself is cached on disk under its hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverArt {
+    pub mime_type: String,
+    pub hash: String, // BLAKE3 of the image, shared by every track of an album
+    pub size: usize,  // in bytes
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl CoverArt {
+    fn new(mime_type: &str, data: Vec<u8>) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        }
+        let mime_type = content_type(mime_type, &data);
+        Some(CoverArt {
+            hash: blake3::hash(&data).to_hex().to_string(),
+            size: data.len(),
+            mime_type,
+            data,
+        })
+    }
+
+    /// The type to serve the image as; covers stored before types were
+    /// checked may still carry whatever their tag declared.
+    pub fn content_type(&self) -> String {
+        content_type(&self.mime_type, &self.data)
+    }
+
+    /// The cache file name, e.g. "<hash>.jpg".
+    pub fn file_name(&self) -> String {
+        let extension = match self.mime_type.as_str() {
+            "image/png" => "png",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/bmp" => "bmp",
+            "application/octet-stream" => "bin",
+            _ => "jpg",
+        };
+        format!("{}.{}", self.hash, extension)
+    }
+}
+
+/// Music tags read from whichever tag formats a file carries, with the
+/// preferred format for the container winning field by field.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MusicTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<i32>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub compilation: bool,
+    pub musicbrainz: MusicBrainzIds,
+    pub replay_gain: ReplayGain,
+    pub cover: Option<CoverArt>,
+    #[serde(skip)]
+    front_cover: bool,
+}
+
+impl MusicTags {
+    fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.album_artist.is_none()
+            && self.cover.is_none()
+    }
+
+    /// Sets a field from a Vorbis comment, APE item, ID3 `TXXX` frame or MP4
+    /// freeform atom. Keys are matched ignoring case, spaces and underscores,
+    /// so "Album Artist", "ALBUMARTIST" and "album_artist" are the same field;
+    /// fields that are already set are kept.
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.trim_matches(char::from(0)).trim();
+        if value.is_empty() {
+            return;
+        }
+        let key: String = key
+            .chars()
+            .filter(|c| *c != ' ' && *c != '_')
+            .flat_map(char::to_uppercase)
+            .collect();
+        let text = || Some(value.to_string());
+        match key.as_str() {
+            "TITLE" => fill(&mut self.title, text()),
+            "ARTIST" => fill(&mut self.artist, text()),
+            "ALBUMARTIST" => fill(&mut self.album_artist, text()),
+            "ALBUM" => fill(&mut self.album, text()),
+            "TRACKNUMBER" | "TRACK" => {
+                let (number, total) = number_pair(value);
+                fill(&mut self.track_number, number);
+                fill(&mut self.track_total, total);
+            }
+            "TRACKTOTAL" | "TOTALTRACKS" => fill(&mut self.track_total, value.parse().ok()),
+            "DISCNUMBER" | "DISC" => {
+                let (number, total) = number_pair(value);
+                fill(&mut self.disc_number, number);
+                fill(&mut self.disc_total, total);
+            }
+            "DISCTOTAL" | "TOTALDISCS" => fill(&mut self.disc_total, value.parse().ok()),
+            "DATE" | "YEAR" | "ORIGINALDATE" => fill(&mut self.year, year(value)),
+            "GENRE" => {
+                for genre in value.split(';').map(str::trim) {
+                    if !genre.is_empty() && !self.genres.iter().any(|g| g == genre) {
+                        self.genres.push(genre.to_string());
+                    }
+                }
+            }
+            "COMPOSER" => fill(&mut self.composer, text()),
+            "COMPILATION" => self.compilation |= value == "1",
+            "MUSICBRAINZTRACKID" => fill(&mut self.musicbrainz.recording_id, text()),
+            "MUSICBRAINZRELEASETRACKID" => fill(&mut self.musicbrainz.track_id, text()),
+            "MUSICBRAINZALBUMID" => fill(&mut self.musicbrainz.release_id, text()),
+            "MUSICBRAINZRELEASEGROUPID" => fill(&mut self.musicbrainz.release_group_id, text()),
+            "MUSICBRAINZARTISTID" => fill(&mut self.musicbrainz.artist_id, text()),
+            "MUSICBRAINZALBUMARTISTID" => fill(&mut self.musicbrainz.album_artist_id, text()),
+            "REPLAYGAINTRACKGAIN" => fill(&mut self.replay_gain.track_gain, decibels(value)),
+            "REPLAYGAINTRACKPEAK" => fill(&mut self.replay_gain.track_peak, value.parse().ok()),
+            "REPLAYGAINALBUMGAIN" => fill(&mut self.replay_gain.album_gain, decibels(value)),
+            "REPLAYGAINALBUMPEAK" => fill(&mut self.replay_gain.album_peak, value.parse().ok()),
+            _ => {}
+        }
+    }
+
+    // The first front cover, or else the first picture of any kind
+    fn set_cover(&mut self, cover: Option<CoverArt>, front: bool) {
+        if (self.cover.is_none() || front && !self.front_cover)
+            && let Some(cover) = cover
+        {
+            self.cover = Some(cover);
+            self.front_cover = front;
+        }
+    }
+
+    // Fills whatever `self` lacks from a lower-priority tag
+    fn merge(&mut self, other: MusicTags) {
+        fill(&mut self.title, other.title);
+        fill(&mut self.artist, other.artist);
+        fill(&mut self.album_artist, other.album_artist);
+        fill(&mut self.album, other.album);
+        fill(&mut self.track_number, other.track_number);
+        fill(&mut self.track_total, other.track_total);
+        fill(&mut self.disc_number, other.disc_number);
+        fill(&mut self.disc_total, other.disc_total);
+        fill(&mut self.year, other.year);
+        if self.genres.is_empty() {
+            self.genres = other.genres;
+        }
+        fill(&mut self.composer, other.composer);
+        self.compilation |= other.compilation;
+        let (ids, other_ids) = (&mut self.musicbrainz, other.musicbrainz);
+        fill(&mut ids.recording_id, other_ids.recording_id);
+        fill(&mut ids.track_id, other_ids.track_id);
+        fill(&mut ids.release_id, other_ids.release_id);
+        fill(&mut ids.release_group_id, other_ids.release_group_id);
+        fill(&mut ids.artist_id, other_ids.artist_id);
+        fill(&mut ids.album_artist_id, other_ids.album_artist_id);
+        let (gain, other_gain) = (&mut self.replay_gain, other.replay_gain);
+        fill(&mut gain.track_gain, other_gain.track_gain);
+        fill(&mut gain.track_peak, other_gain.track_peak);
+        fill(&mut gain.album_gain, other_gain.album_gain);
+        fill(&mut gain.album_peak, other_gain.album_peak);
+        self.set_cover(other.cover, other.front_cover);
+    }
+}
+
+pub struct TagReader;
+
+impl TagReader {
+    /// Reads the tags of an audio file, detecting the container by its leading
+    /// bytes. `None` when the file has no tags we understand.
+    pub async fn read(path: PathBuf) -> Option<MusicTags> {
+        tokio::task::spawn_blocking(move || Self::read_blocking(&path))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    fn read_blocking(path: &Path) -> Option<MusicTags> {
+        let mut file = File::open(path).ok()?;
+        let size = file.metadata().ok()?.len();
+
+        // An ID3v2 tag may precede MP3, AAC and (against the spec) FLAC streams
+        let mut tags = MusicTags::default();
+        let (id3v2, start) = id3::read_v2(&mut file, size);
+        let magic = read_at(&mut file, start, 12.min(size.saturating_sub(start)))?;
+
+        if magic.starts_with(b"fLaC") {
+            tags.merge(vorbis::read_flac(&mut file, start + 4).unwrap_or_default());
+            tags.merge(id3v2.unwrap_or_default());
+        } else if magic.starts_with(b"OggS") {
+            tags.merge(vorbis::read_ogg(&mut file, size).unwrap_or_default());
+        } else if magic.get(4..8) == Some(b"ftyp") {
+            tags.merge(mp4::read(&mut file, size).unwrap_or_default());
+        } else {
+            // MP3, AAC, APE, WavPack and friends: ID3v2, then APE, then ID3v1
+            tags.merge(id3v2.unwrap_or_default());
+            tags.merge(ape::read(&mut file, size).unwrap_or_default());
+            tags.merge(id3::read_v1(&mut file, size).unwrap_or_default());
+        }
+
+        (!tags.is_empty()).then_some(tags)
+    }
+}
+
+fn fill<T>(slot: &mut Option<T>, value: Option<T>) {
+    if slot.is_none() {
+        *slot = value;
+    }
+}
+
+// "3", "3/12" or "03 of 12"
+fn number_pair(value: &str) -> (Option<u32>, Option<u32>) {
+    let mut parts = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok().filter(|n| *n > 0));
+    (parts.next().flatten(), parts.next().flatten())
+}
+
+// "2003", "2003-05-01" or "2003-05-01T12:00:00"
+fn year(value: &str) -> Option<i32> {
+    value.get(..4)?.parse().ok().filter(|year| *year > 0)
+}
+
+// "-6.54 dB"
+fn decibels(value: &str) -> Option<f64> {
+    value
+        .trim_end_matches(|c: char| c.is_alphabetic() || c.is_whitespace())
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Declared types are often wrong ("image/jpg", "PNG"), the bytes aren't. An
+// image we can't sniff keeps its declared type only if that's a raster image
+// type, so a tag can't get HTML or SVG served from the server's origin
+fn content_type(declared: &str, data: &[u8]) -> String {
+    if let Some(mime_type) = sniff_image(data) {
+        return mime_type.to_string();
+    }
+    let declared = declared.trim().to_lowercase();
+    let raster = declared.strip_prefix("image/").is_some_and(|subtype| {
+        !subtype.is_empty()
+            && !subtype.contains("svg")
+            && subtype
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+    });
+    if raster {
+        declared
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else if data.starts_with(b"BM") {
+        Some("image/bmp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffed_types_win_over_declared_ones() {
+        assert_eq!(content_type("image/jpg", b"\x89PNG\r\n\x1a\n"), "image/png");
+        assert_eq!(content_type("text/html", b"\xFF\xD8\xFF\xE0"), "image/jpeg");
+    }
+
+    #[test]
+    fn unsniffed_covers_keep_only_raster_image_types() {
+        assert_eq!(
+            content_type(" Image/AVIF ", b"\0\0\0\x1cftypavif"),
+            "image/avif"
+        );
+        for declared in [
+            "text/html",
+            "image/svg+xml",
+            "image/",
+            "image/x;y",
+            "",
+            "-->",
+        ] {
+            assert_eq!(
+                content_type(declared, b"<html></html>"),
+                "application/octet-stream"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_pictures_are_dropped() {
+        assert!(CoverArt::new("image/png", Vec::new()).is_none());
+    }
+
+    #[test]
+    fn numbers_and_totals() {
+        assert_eq!(number_pair("3"), (Some(3), None));
+        assert_eq!(number_pair("03 of 12"), (Some(3), Some(12)));
+        assert_eq!(number_pair("0/0"), (None, None));
+        assert_eq!(decibels("-6.54 dB"), Some(-6.54));
+        assert_eq!(year("2003-05-01"), Some(2003));
+        assert_eq!(year("03"), None);
+    }
+}
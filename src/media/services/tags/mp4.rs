@@ -0,0 +1,189 @@
+use std::fs::File;
+
+use super::{CoverArt, MusicTags, id3};
+use crate::media::services::probe::{
+    Bytes,
+    mp4::{atoms, child, read_moov},
+};
+
+// Well-known types of a `data` atom
+const UTF8: u32 = 1;
+const JPEG: u32 = 13;
+const PNG: u32 = 14;
+const BMP: u32 = 27;
+
+/// Reads the iTunes-style item list at `moov/udta/meta/ilst`.
+pub(super) fn read(file: &mut File, size: u64) -> Option<MusicTags> {
+    let (moov, _) = read_moov(file, size)?;
+    let meta = child(child(&moov, b"udta")?, b"meta")?;
+    // ISO `meta` is a full box with four bytes of version and flags, QuickTime's isn't
+    let meta = match meta.get(4..8) {
+        Some(b"hdlr") => meta,
+        _ => meta.get(4..)?,
+    };
+    let ilst = child(meta, b"ilst")?;
+
+    let mut tags = MusicTags::default();
+    for (name, item) in atoms(ilst) {
+        match &name {
+            b"\xA9nam" => tags.set("TITLE", &text(item)),
+            b"\xA9ART" => tags.set("ARTIST", &text(item)),
+            b"aART" => tags.set("ALBUMARTIST", &text(item)),
+            b"\xA9alb" => tags.set("ALBUM", &text(item)),
+            b"\xA9day" => tags.set("DATE", &text(item)),
+            b"\xA9gen" => tags.set("GENRE", &text(item)),
+            b"\xA9wrt" => tags.set("COMPOSER", &text(item)),
+            b"trkn" | b"disk" => {
+                // Two reserved bytes, then the number and the total as u16s
+                let value = data(item)
+                    .next()
+                    .map(|(_, value)| value)
+                    .unwrap_or_default();
+                let mut bytes = Bytes::new(value);
+                bytes.skip(2);
+                let number = bytes.u16().map(u32::from).filter(|n| *n > 0);
+                let total = bytes.u16().map(u32::from).filter(|n| *n > 0);
+                if &name == b"trkn" {
+                    tags.track_number = tags.track_number.or(number);
+                    tags.track_total = tags.track_total.or(total);
+                } else {
+                    tags.disc_number = tags.disc_number.or(number);
+                    tags.disc_total = tags.disc_total.or(total);
+                }
+            }
+            b"gnre" => {
+                // ID3v1 genre index plus one
+                let genre = data(item)
+                    .next()
+                    .and_then(|(_, value)| Bytes::new(value).u16())
+                    .and_then(|index| (index as usize).checked_sub(1))
+                    .and_then(id3::genre);
+                if let Some(genre) = genre {
+                    tags.set("GENRE", &genre);
+                }
+            }
+            b"cpil" => {
+                tags.compilation |= data(item).next().is_some_and(|(_, value)| value == [1]);
+            }
+            b"covr" => {
+                for (kind, value) in data(item) {
+                    let mime_type = match kind {
+                        JPEG => "image/jpeg",
+                        PNG => "image/png",
+                        BMP => "image/bmp",
+                        _ => "",
+                    };
+                    // iTunes has no picture types; the first one is the cover
+                    tags.set_cover(CoverArt::new(mime_type, value.to_vec()), false);
+                }
+            }
+            b"----" => {
+                // Freeform items: a reverse-DNS namespace, a name and the value
+                let name = child(item, b"name").and_then(|name| name.get(4..));
+                if let Some(name) = name {
+                    tags.set(&String::from_utf8_lossy(name), &text(item));
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(tags)
+}
+
+// The `data` atoms of an item: a type, a locale, then the value
+fn data(item: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    atoms(item)
+        .filter(|(name, _)| name == b"data")
+        .filter_map(|(_, body)| {
+            let mut bytes = Bytes::new(body);
+            let kind = bytes.u32()? & 0x00FF_FFFF;
+            bytes.skip(4)?;
+            Some((kind, bytes.rest()))
+        })
+}
+
+// All UTF-8 values of an item, joined
+fn text(item: &[u8]) -> String {
+    data(item)
+        .filter(|(kind, _)| *kind == UTF8 || *kind == 0)
+        .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn boxed(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn item(name: &[u8; 4], kind: u32, value: &[u8]) -> Vec<u8> {
+        let mut body = kind.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0; 4]); // locale
+        body.extend_from_slice(value);
+        boxed(name, &boxed(b"data", &body))
+    }
+
+    fn file_with(meta: &[u8]) -> Vec<u8> {
+        let mut data = boxed(b"ftyp", b"M4A \0\0\0\0");
+        data.extend(boxed(b"moov", &boxed(b"udta", &boxed(b"meta", meta))));
+        data
+    }
+
+    fn read_bytes(data: &[u8]) -> Option<MusicTags> {
+        read(&mut fixtures::file(data), data.len() as u64)
+    }
+
+    #[test]
+    fn reads_the_item_list() {
+        let ilst = [
+            item(b"\xA9nam", UTF8, b"Song"),
+            item(b"trkn", 0, &[0, 0, 0, 3, 0, 12, 0, 0]),
+            item(b"gnre", 0, &[0, 18]),
+            item(b"covr", JPEG, b"\x89PNG\r\n\x1a\n"),
+        ]
+        .concat();
+        let mut meta = vec![0; 4]; // ISO full box version and flags
+        meta.extend(boxed(b"hdlr", &[0; 25]));
+        meta.extend(boxed(b"ilst", &ilst));
+
+        let tags = read_bytes(&file_with(&meta)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!((tags.track_number, tags.track_total), (Some(3), Some(12)));
+        assert_eq!(tags.genres, ["Rock"]);
+        // The bytes win over the declared type
+        assert_eq!(tags.cover.unwrap().mime_type, "image/png");
+    }
+
+    #[test]
+    fn reads_quicktime_meta_without_version() {
+        let mut meta = boxed(b"hdlr", &[0; 25]);
+        meta.extend(boxed(b"ilst", &item(b"\xA9ART", UTF8, b"Band")));
+        let tags = read_bytes(&file_with(&meta)).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("Band"));
+    }
+
+    #[test]
+    fn malformed_items_are_skipped() {
+        let mut ilst = boxed(b"trkn", &boxed(b"data", &[0; 3])); // shorter than its header
+        ilst.extend(item(b"\xA9alb", UTF8, b"Record"));
+        ilst.extend([0, 0, 0, 0x40]); // runs past the list
+        ilst.extend(b"\xA9day");
+        let mut meta = vec![0; 4];
+        meta.extend(boxed(b"ilst", &ilst));
+
+        let tags = read_bytes(&file_with(&meta)).unwrap();
+        assert_eq!(tags.track_number, None);
+        assert_eq!(tags.album.as_deref(), Some("Record"));
+        assert_eq!(tags.year, None);
+
+        let data = boxed(b"ftyp", b"M4A \0\0\0\0");
+        assert!(read_bytes(&data).is_none());
+    }
+}
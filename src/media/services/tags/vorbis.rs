@@ -0,0 +1,242 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::fs::File;
+
+use super::{CoverArt, MusicTags, read_at};
+use crate::media::services::probe::Bytes;
+
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+const MAX_COMMENT_PACKET: usize = 16 * 1024 * 1024; // embedded pictures make these large
+
+/// Reads the VORBIS_COMMENT and PICTURE blocks of a native FLAC stream;
+/// `offset` is just past the "fLaC" marker.
+pub(super) fn read_flac(file: &mut File, mut offset: u64) -> Option<MusicTags> {
+    let mut tags = MusicTags::default();
+    loop {
+        let header = read_at(file, offset, 4)?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        match block_type {
+            VORBIS_COMMENT => {
+                comments(&read_at(file, offset + 4, len)?, &mut tags);
+            }
+            PICTURE => {
+                let block = read_at(file, offset + 4, len)?;
+                if let Some((cover, front)) = picture(&block) {
+                    tags.set_cover(Some(cover), front);
+                }
+            }
+            _ => {}
+        }
+        if last {
+            break;
+        }
+        offset += 4 + len;
+    }
+    Some(tags)
+}
+
+/// Reads the comment header of the first stream in an Ogg file: the second
+/// packet of Vorbis, Opus and Ogg FLAC streams alike.
+pub(super) fn read_ogg(file: &mut File, size: u64) -> Option<MusicTags> {
+    let mut serial = None;
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+    let mut offset = 0;
+    while offset + 27 <= size && packets.len() < 3 {
+        let header = read_at(file, offset, 27)?;
+        if !header.starts_with(b"OggS") {
+            return None;
+        }
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().ok()?);
+        let segments = header[26] as u64;
+        let lacing = read_at(file, offset + 27, segments)?;
+        let body_len: u64 = lacing.iter().map(|len| *len as u64).sum();
+        let body_start = offset + 27 + segments;
+        offset = body_start + body_len;
+
+        // Other streams' pages are interleaved with the ones we want
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+        let body = read_at(file, body_start, body_len)?;
+        let mut start = 0;
+        for len in lacing {
+            let packet = packets.last_mut()?;
+            packet.extend_from_slice(&body[start..start + len as usize]);
+            start += len as usize;
+            if packet.len() > MAX_COMMENT_PACKET {
+                return None;
+            }
+            // A lacing value below 255 ends the packet
+            if len < 255 {
+                packets.push(Vec::new());
+            }
+        }
+    }
+
+    let packet = packets.get(1)?;
+    let mut tags = MusicTags::default();
+    if let Some(rest) = packet.strip_prefix(b"\x03vorbis") {
+        comments(rest, &mut tags);
+    } else if let Some(rest) = packet.strip_prefix(b"OpusTags") {
+        comments(rest, &mut tags);
+    } else if packet.first().map(|byte| byte & 0x7F) == Some(VORBIS_COMMENT) {
+        // Ogg FLAC carries its metadata blocks as packets, headers included
+        comments(packet.get(4..)?, &mut tags);
+    } else {
+        return None;
+    }
+    Some(tags)
+}
+
+// A vendor string, then length-prefixed "KEY=value" fields, all little-endian;
+// fields before a truncation are kept
+fn comments(data: &[u8], tags: &mut MusicTags) -> Option<()> {
+    let mut bytes = Bytes::new(data);
+    let vendor = bytes.u32_le()? as usize;
+    bytes.skip(vendor)?;
+    let count = bytes.u32_le()?;
+    for _ in 0..count {
+        let len = bytes.u32_le()? as usize;
+        let field = String::from_utf8_lossy(bytes.take(len)?);
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        if key.eq_ignore_ascii_case("METADATA_BLOCK_PICTURE") {
+            let block = STANDARD.decode(value.trim()).ok();
+            if let Some((cover, front)) = block.as_deref().and_then(picture) {
+                tags.set_cover(Some(cover), front);
+            }
+        } else {
+            tags.set(key, value);
+        }
+    }
+    Some(())
+}
+
+// A FLAC PICTURE block, also found base64-encoded in Vorbis comments
+fn picture(block: &[u8]) -> Option<(CoverArt, bool)> {
+    let mut bytes = Bytes::new(block);
+    let picture_type = bytes.u32()?;
+    let mime_len = bytes.u32()? as usize;
+    let mime_type = String::from_utf8_lossy(bytes.take(mime_len)?).into_owned();
+    let description_len = bytes.u32()? as usize;
+    bytes.skip(description_len)?;
+    bytes.skip(16)?; // width, height, colour depth and palette size
+    let data_len = bytes.u32()? as usize;
+    let data = bytes.take(data_len)?;
+    Some((CoverArt::new(&mime_type, data.to_vec())?, picture_type == 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::services::probe::fixtures;
+
+    fn comment_block(vendor: &str, fields: &[&str]) -> Vec<u8> {
+        let mut data = (vendor.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(vendor.as_bytes());
+        data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        for field in fields {
+            data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            data.extend_from_slice(field.as_bytes());
+        }
+        data
+    }
+
+    fn picture_block(picture_type: u32, mime_type: &str, image: &[u8]) -> Vec<u8> {
+        let mut data = picture_type.to_be_bytes().to_vec();
+        data.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+        data.extend_from_slice(mime_type.as_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes()); // description
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&(image.len() as u32).to_be_bytes());
+        data.extend_from_slice(image);
+        data
+    }
+
+    fn page(serial: u32, lacing: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = b"OggS\0\0".to_vec();
+        data.extend_from_slice(&[0; 8]); // granule position
+        data.extend_from_slice(&serial.to_le_bytes());
+        data.extend_from_slice(&[0; 8]); // sequence number and checksum
+        data.push(lacing.len() as u8);
+        data.extend_from_slice(lacing);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn block(block_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut data = (body.len() as u32).to_be_bytes().to_vec();
+        data[0] = block_type;
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn reads_flac_blocks() {
+        let mut data = b"fLaC".to_vec();
+        data.extend(block(0, &[0; 34])); // STREAMINFO
+        let comments = comment_block("ref", &["TITLE=Song", "tracknumber=3/12", "junk"]);
+        data.extend(block(VORBIS_COMMENT, &comments));
+        let picture = picture_block(3, "image/PNG", b"\x89PNG\r\n\x1a\n");
+        data.extend(block(0x80 | PICTURE, &picture));
+
+        let tags = read_flac(&mut fixtures::file(&data), 4).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!((tags.track_number, tags.track_total), (Some(3), Some(12)));
+        assert_eq!(tags.cover.unwrap().mime_type, "image/png");
+    }
+
+    #[test]
+    fn joins_ogg_packets_across_pages_and_skips_other_streams() {
+        // Long enough to need a 255 lacing value and a continuation page
+        let vendor = "v".repeat(300);
+        let comments = [
+            b"\x03vorbis".as_slice(),
+            &comment_block(&vendor, &["ARTIST=Band"]),
+        ]
+        .concat();
+        let mut data = page(1, &[30], &[1; 30]);
+        data.extend(page(2, &[10], &[0; 10]));
+        data.extend(page(1, &[255], &comments[..255]));
+        data.extend(page(1, &[(comments.len() - 255) as u8], &comments[255..]));
+
+        let tags = read_ogg(&mut fixtures::file(&data), data.len() as u64).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("Band"));
+    }
+
+    #[test]
+    fn truncated_ogg_is_none() {
+        let comments = [b"OpusTags".as_slice(), &comment_block("v", &["TITLE=x"])].concat();
+        let mut data = page(1, &[19], &[1; 19]);
+        data.extend(page(1, &[comments.len() as u8], &comments));
+        let data = &data[..data.len() - 4];
+        assert!(read_ogg(&mut fixtures::file(data), data.len() as u64).is_none());
+
+        let data = b"RIFF\0\0\0\0WAVEfmt \0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        assert!(read_ogg(&mut fixtures::file(data), data.len() as u64).is_none());
+    }
+
+    #[test]
+    fn keeps_comments_before_a_truncation() {
+        let mut data = comment_block("v", &["TITLE=Song", "ALBUM=Record"]);
+        data.truncate(data.len() - 3);
+        let mut tags = MusicTags::default();
+        assert!(comments(&data, &mut tags).is_none());
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.album, None);
+    }
+
+    #[test]
+    fn pictures_that_arent_images_are_not_served_as_declared() {
+        let block = picture_block(3, "text/html", b"<script>alert(1)</script>");
+        let (cover, front) = picture(&block).unwrap();
+        assert!(front);
+        assert_eq!(cover.mime_type, "application/octet-stream");
+
+        assert!(picture(&block[..block.len() - 1]).is_none());
+        assert!(picture(&picture_block(3, "image/png", b"")).is_none());
+    }
+}
@@ -0,0 +1,488 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::time::Sleep;
+use uuid::Uuid;
+
+use crate::auth::services::{ROLE_ADMIN, ROLE_GUEST, ROLE_USER};
+
+const ROLES: [&str; 3] = [ROLE_ADMIN, ROLE_USER, ROLE_GUEST];
+// Bursts above the configured rate are allowed for at most this long
+const BURST: Duration = Duration::from_secs(1);
+const MIN_BURST_BYTES: f64 = 64.0 * 1024.0;
+const RATE_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Local,
+    Remote,
+}
+
+impl Network {
+    /// Loopback, private and link-local clients count as local; unknown ones as remote.
+    pub fn of(ip: Option<IpAddr>) -> Self {
+        let local = match ip {
+            Some(IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+            Some(IpAddr::V6(ip)) => {
+                ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip
+                        .to_ipv4_mapped()
+                        .is_some_and(|v4| v4.is_loopback() || v4.is_private())
+            }
+            None => false,
+        };
+        if local {
+            Network::Local
+        } else {
+            Network::Remote
+        }
+    }
+
+    fn env_suffix(&self) -> &'static str {
+        match self {
+            Network::Local => "LOCAL",
+            Network::Remote => "REMOTE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BandwidthLimit {
+    pub session_kbps: u64, // 0 means unlimited
+    pub profile_kbps: u64, // 0 means unlimited, shared by all of a profile's streams
+}
+
+/// Limits per role and network, read from the environment.
+///
+/// `SESSION_BANDWIDTH_KBPS` and `PROFILE_BANDWIDTH_KBPS` may be narrowed with a
+/// `_<NETWORK>` or `_<ROLE>_<NETWORK>` suffix, e.g. `SESSION_BANDWIDTH_KBPS_USER_REMOTE`.
+#[derive(Debug, Clone)]
+pub struct BandwidthPolicy {
+    limits: HashMap<(String, Network), BandwidthLimit>,
+    global_kbps: u64, // 0 means unlimited
+}
+
+impl BandwidthPolicy {
+    pub fn from_env() -> Self {
+        let mut limits = HashMap::new();
+        for network in [Network::Local, Network::Remote] {
+            for role in ROLES {
+                let limit = BandwidthLimit {
+                    session_kbps: Self::env_kbps("SESSION_BANDWIDTH_KBPS", role, network),
+                    profile_kbps: Self::env_kbps("PROFILE_BANDWIDTH_KBPS", role, network),
+                };
+                limits.insert((role.to_string(), network), limit);
+            }
+        }
+        let global_kbps = env::var("GLOBAL_EGRESS_KBPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        BandwidthPolicy {
+            limits,
+            global_kbps,
+        }
+    }
+
+    pub fn limit(&self, role: &str, network: Network) -> BandwidthLimit {
+        self.limits
+            .get(&(role.to_string(), network))
+            .or_else(|| self.limits.get(&(ROLE_USER.to_string(), network)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn env_kbps(name: &str, role: &str, network: Network) -> u64 {
+        [
+            format!("{}_{}_{}", name, role.to_uppercase(), network.env_suffix()),
+            format!("{}_{}", name, network.env_suffix()),
+            name.to_string(),
+        ]
+        .iter()
+        .find_map(|key| env::var(key).ok().and_then(|v| v.parse().ok()))
+        .unwrap_or(0)
+    }
+}
+
+/// Token bucket that lets callers go into debt and then wait it off, so a
+/// chunk is never split and concurrent streams share the rate fairly.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64, // in bytes per second
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(kbps: u64) -> Self {
+        let rate = kbps as f64 * 1000.0 / 8.0;
+        let capacity = (rate * BURST.as_secs_f64()).max(MIN_BURST_BYTES);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes `bytes` tokens and returns how long to wait before sending them.
+    fn take(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+type SharedBucket = Arc<Mutex<TokenBucket>>;
+
+/// Who a stream is served to, used to pick its limits.
+#[derive(Debug, Clone)]
+pub struct StreamContext {
+    pub session_id: Option<Uuid>,
+    pub profile_id: Uuid,
+    pub media_id: Uuid,
+    pub role: String,
+    pub client_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreamThroughput {
+    pub stream_id: Uuid,
+    pub session_id: Option<Uuid>,
+    pub profile_id: Uuid,
+    pub media_id: Uuid,
+    pub role: String,
+    pub network: Network,
+    pub client_ip: Option<IpAddr>,
+    pub session_limit_kbps: u64, // 0 means unlimited
+    pub profile_limit_kbps: u64, // 0 means unlimited
+    pub current_kbps: f64,
+    pub bytes_sent: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ThroughputReport {
+    pub global_limit_kbps: u64, // 0 means unlimited
+    pub total_kbps: f64,
+    pub streams: Vec<StreamThroughput>,
+}
+
+#[derive(Debug)]
+struct RateWindow {
+    started: Instant,
+    bytes: u64,
+    last_kbps: f64,
+}
+
+#[derive(Debug)]
+struct StreamMeter {
+    context: StreamContext,
+    network: Network,
+    limit: BandwidthLimit,
+    started_at: DateTime<Utc>,
+    bytes_sent: AtomicU64,
+    window: Mutex<RateWindow>,
+}
+
+impl StreamMeter {
+    fn record(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        let mut window = self.window.lock().unwrap();
+        window.bytes += bytes as u64;
+        let elapsed = window.started.elapsed();
+        if elapsed >= RATE_WINDOW {
+            window.last_kbps = window.bytes as f64 * 8.0 / 1000.0 / elapsed.as_secs_f64();
+            window.started = Instant::now();
+            window.bytes = 0;
+        }
+    }
+
+    fn current_kbps(&self) -> f64 {
+        let window = self.window.lock().unwrap();
+        let elapsed = window.started.elapsed();
+        // A stalled stream hasn't closed its window yet but is no longer sending
+        if elapsed >= RATE_WINDOW {
+            window.bytes as f64 * 8.0 / 1000.0 / elapsed.as_secs_f64()
+        } else {
+            window.last_kbps
+        }
+    }
+}
+
+struct Inner {
+    policy: BandwidthPolicy,
+    global: Option<SharedBucket>,
+    profiles: Mutex<HashMap<(Uuid, Network), SharedBucket>>,
+    streams: Mutex<HashMap<Uuid, Arc<StreamMeter>>>,
+}
+
+/// Rate limits streaming bodies per session, per profile and server-wide.
+#[derive(Clone)]
+pub struct BandwidthManager {
+    inner: Arc<Inner>,
+}
+
+impl BandwidthManager {
+    pub fn new(policy: BandwidthPolicy) -> Self {
+        let global = (policy.global_kbps > 0)
+            .then(|| Arc::new(Mutex::new(TokenBucket::new(policy.global_kbps))));
+        BandwidthManager {
+            inner: Arc::new(Inner {
+                policy,
+                global,
+                profiles: Mutex::new(HashMap::new()),
+                streams: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Wraps a body stream so it is sent no faster than its limits allow.
+    pub fn throttle<S>(&self, stream: S, context: StreamContext) -> ThrottledStream<S> {
+        let network = Network::of(context.client_ip);
+        let limit = self.inner.policy.limit(&context.role, network);
+
+        let mut buckets = Vec::new();
+        if limit.session_kbps > 0 {
+            buckets.push(Arc::new(Mutex::new(TokenBucket::new(limit.session_kbps))));
+        }
+        if limit.profile_kbps > 0 {
+            let mut profiles = self.inner.profiles.lock().unwrap();
+            let bucket = profiles
+                .entry((context.profile_id, network))
+                .or_insert_with(|| Arc::new(Mutex::new(TokenBucket::new(limit.profile_kbps))));
+            buckets.push(bucket.clone());
+        }
+        if let Some(global) = &self.inner.global {
+            buckets.push(global.clone());
+        }
+
+        let stream_id = Uuid::new_v4();
+        let meter = Arc::new(StreamMeter {
+            context,
+            network,
+            limit,
+            started_at: Utc::now(),
+            bytes_sent: AtomicU64::new(0),
+            window: Mutex::new(RateWindow {
+                started: Instant::now(),
+                bytes: 0,
+                last_kbps: 0.0,
+            }),
+        });
+        self.inner
+            .streams
+            .lock()
+            .unwrap()
+            .insert(stream_id, meter.clone());
+
+        ThrottledStream {
+            inner: stream,
+            buckets,
+            meter,
+            delay: None,
+            pending: None,
+            _registration: Registration {
+                manager: self.clone(),
+                stream_id,
+            },
+        }
+    }
+
+    /// Current throughput of every open stream, busiest first.
+    pub fn report(&self) -> ThroughputReport {
+        let mut streams: Vec<StreamThroughput> = self
+            .inner
+            .streams
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(stream_id, meter)| StreamThroughput {
+                stream_id: *stream_id,
+                session_id: meter.context.session_id,
+                profile_id: meter.context.profile_id,
+                media_id: meter.context.media_id,
+                role: meter.context.role.clone(),
+                network: meter.network,
+                client_ip: meter.context.client_ip,
+                session_limit_kbps: meter.limit.session_kbps,
+                profile_limit_kbps: meter.limit.profile_kbps,
+                current_kbps: meter.current_kbps(),
+                bytes_sent: meter.bytes_sent.load(Ordering::Relaxed),
+                started_at: meter.started_at,
+            })
+            .collect();
+        streams.sort_by(|a, b| b.current_kbps.total_cmp(&a.current_kbps));
+
+        ThroughputReport {
+            global_limit_kbps: self.inner.policy.global_kbps,
+            total_kbps: streams.iter().map(|s| s.current_kbps).sum(),
+            streams,
+        }
+    }
+
+    fn unregister(&self, stream_id: Uuid) {
+        let Some(meter) = self.inner.streams.lock().unwrap().remove(&stream_id) else {
+            return;
+        };
+        // Drop the profile bucket once its last stream is gone
+        let key = (meter.context.profile_id, meter.network);
+        let mut profiles = self.inner.profiles.lock().unwrap();
+        if profiles
+            .get(&key)
+            .is_some_and(|bucket| Arc::strong_count(bucket) == 1)
+        {
+            profiles.remove(&key);
+        }
+    }
+}
+
+struct Registration {
+    manager: BandwidthManager,
+    stream_id: Uuid,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.manager.unregister(self.stream_id);
+    }
+}
+
+/// A body stream that waits between chunks to stay within its limits.
+pub struct ThrottledStream<S> {
+    inner: S,
+    buckets: Vec<SharedBucket>,
+    meter: Arc<StreamMeter>,
+    delay: Option<Pin<Box<Sleep>>>,
+    pending: Option<Bytes>,
+    // Declared last so the buckets are released before the registration checks them
+    _registration: Registration,
+}
+
+impl<S, E> Stream for ThrottledStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(delay) = self.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+            return Poll::Ready(self.pending.take().map(Ok));
+        }
+
+        let chunk = match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            other => return other,
+        };
+
+        self.meter.record(chunk.len());
+        let wait = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.lock().unwrap().take(chunk.len()))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if wait.is_zero() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+
+        let mut delay = Box::pin(tokio::time::sleep(wait));
+        if delay.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+        self.delay = Some(delay);
+        self.pending = Some(chunk);
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Close enough for the few microseconds that pass between calls
+    fn approx(actual: Duration, expected: Duration) -> bool {
+        actual.abs_diff(expected) < Duration::from_millis(10)
+    }
+
+    #[test]
+    fn a_full_bucket_sends_a_burst_without_waiting() {
+        let mut bucket = TokenBucket::new(8_000); // 1 MB/s
+        assert_eq!(bucket.capacity, 1_000_000.0);
+        assert_eq!(bucket.take(600_000), Duration::ZERO);
+        assert_eq!(bucket.take(400_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn going_into_debt_waits_it_off() {
+        let mut bucket = TokenBucket::new(8_000);
+        // A chunk larger than the bucket is never split, only paid for later
+        assert!(approx(bucket.take(1_500_000), Duration::from_millis(500)));
+        // Later takes queue behind the debt
+        assert!(approx(bucket.take(250_000), Duration::from_millis(750)));
+    }
+
+    #[test]
+    fn refills_at_the_rate_up_to_the_capacity() {
+        let mut bucket = TokenBucket::new(8_000);
+        bucket.take(1_500_000);
+        bucket.updated -= Duration::from_secs(1);
+        assert_eq!(bucket.take(500_000), Duration::ZERO);
+
+        // A long idle spell doesn't bank more than one burst
+        bucket.updated -= Duration::from_secs(60);
+        assert_eq!(bucket.take(1_000_000), Duration::ZERO);
+        assert!(approx(bucket.take(100_000), Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn slow_limits_still_allow_a_minimum_burst() {
+        let mut bucket = TokenBucket::new(8); // 1 KB/s
+        assert_eq!(bucket.capacity, MIN_BURST_BYTES);
+        assert_eq!(bucket.take(64 * 1024), Duration::ZERO);
+        assert!(approx(bucket.take(1000), Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn private_and_loopback_clients_are_local() {
+        let network = |ip: &str| Network::of(Some(ip.parse().unwrap()));
+        assert_eq!(network("127.0.0.1"), Network::Local);
+        assert_eq!(network("192.168.1.20"), Network::Local);
+        assert_eq!(network("169.254.0.5"), Network::Local);
+        assert_eq!(network("fd00::1"), Network::Local);
+        assert_eq!(network("::ffff:10.0.0.1"), Network::Local);
+        assert_eq!(network("8.8.8.8"), Network::Remote);
+        assert_eq!(network("2001:db8::1"), Network::Remote);
+        assert_eq!(Network::of(None), Network::Remote);
+    }
+}
@@ -0,0 +1,592 @@
+use bytes::Bytes;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    pin::Pin,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{ChildStderr, Command},
+    sync::{OwnedSemaphorePermit, Semaphore, watch},
+};
+use tokio_util::{
+    io::ReaderStream,
+    sync::{CancellationToken, DropGuard},
+};
+use uuid::Uuid;
+
+use crate::media::errors::MediaError;
+
+const PERMIT_WAIT: Duration = Duration::from_secs(5);
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeContainer {
+    MpegTs,
+    Mp4,
+    WebM,
+}
+
+impl TranscodeContainer {
+    fn ffmpeg_format(&self) -> &'static str {
+        match self {
+            TranscodeContainer::MpegTs => "mpegts",
+            TranscodeContainer::Mp4 => "mp4",
+            TranscodeContainer::WebM => "webm",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TranscodeContainer::MpegTs => "video/mp2t",
+            TranscodeContainer::Mp4 => "video/mp4",
+            TranscodeContainer::WebM => "video/webm",
+        }
+    }
+}
+
+/// Target output for a single transcode session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscodeOptions {
+    pub container: TranscodeContainer,
+    pub video_codec: String,        // e.g., "h264", "hevc", "vp9" or "copy"
+    pub audio_codec: String,        // e.g., "aac", "opus" or "copy"
+    pub video_bitrate: Option<u32>, // in kbps
+    pub audio_bitrate: Option<u32>, // in kbps
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl Default for TranscodeOptions {
+    fn default() -> Self {
+        TranscodeOptions {
+            container: TranscodeContainer::MpegTs,
+            video_codec: "h264".to_string(),
+            audio_codec: "aac".to_string(),
+            video_bitrate: None,
+            audio_bitrate: None,
+            max_width: None,
+            max_height: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscodeState {
+    Starting,
+    Running,
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscodeProgress {
+    pub session_id: Uuid,
+    pub media_id: Uuid,
+    pub state: TranscodeState,
+    pub start_offset: f64,     // in seconds
+    pub position: f64,         // in seconds, absolute within the source
+    pub duration: Option<f64>, // in seconds
+    pub percent: Option<f64>,  // 0.0 - 100.0
+    pub speed: Option<f64>,    // realtime multiplier reported by ffmpeg
+    pub fps: Option<f64>,
+    pub bitrate: Option<f64>, // in kbps
+}
+
+struct TranscodeJob {
+    job_id: Uuid,
+    profile_id: Uuid,
+    cancel: CancellationToken,
+    progress: watch::Receiver<TranscodeProgress>,
+}
+
+struct TranscodeManagerInner {
+    ffmpeg_path: String,
+    limit: Arc<Semaphore>,
+    jobs: Mutex<HashMap<Uuid, TranscodeJob>>,
+}
+
+/// Spawns and supervises ffmpeg processes, one per playback session.
+#[derive(Clone)]
+pub struct TranscodeManager {
+    inner: Arc<TranscodeManagerInner>,
+}
+
+impl TranscodeManager {
+    pub fn new(ffmpeg_path: String, max_concurrent: usize) -> Self {
+        TranscodeManager {
+            inner: Arc::new(TranscodeManagerInner {
+                ffmpeg_path,
+                limit: Arc::new(Semaphore::new(max_concurrent)),
+                jobs: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Starts transcoding `input` from `offset` seconds for the given session.
+    ///
+    /// If the session already has a running transcoder (e.g. the client seeked),
+    /// the old process is cancelled and a new one is started at the new offset.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start(
+        &self,
+        session_id: Uuid,
+        profile_id: Uuid,
+        media_id: Uuid,
+        input: PathBuf,
+        duration: Option<f64>,
+        offset: f64,
+        options: &TranscodeOptions,
+    ) -> Result<TranscodeStream, MediaError> {
+        {
+            let mut jobs = self.inner.jobs.lock().unwrap();
+            if let Some(previous) = jobs.get(&session_id) {
+                if previous.profile_id != profile_id {
+                    return Err(MediaError::NotFound);
+                }
+                previous.cancel.cancel();
+                jobs.remove(&session_id);
+            }
+        }
+
+        // A restarted session needs a moment for its old process to release its slot
+        let permit = tokio::time::timeout(PERMIT_WAIT, self.inner.limit.clone().acquire_owned())
+            .await
+            .map_err(|_| MediaError::TranscoderBusy)?
+            .map_err(|_| MediaError::TranscoderBusy)?;
+
+        let mut child = Command::new(&self.inner.ffmpeg_path)
+            .args(Self::build_args(&input, offset, options))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| MediaError::TranscodeFailed(format!("failed to spawn ffmpeg: {}", e)))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| MediaError::TranscodeFailed("ffmpeg stdout unavailable".into()))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| MediaError::TranscodeFailed("ffmpeg stderr unavailable".into()))?;
+
+        let initial = TranscodeProgress {
+            session_id,
+            media_id,
+            state: TranscodeState::Starting,
+            start_offset: offset,
+            position: offset,
+            duration,
+            percent: None,
+            speed: None,
+            fps: None,
+            bitrate: None,
+        };
+        let (progress_tx, progress_rx) = watch::channel(initial);
+        let cancel = CancellationToken::new();
+
+        let job_id = Uuid::new_v4();
+        self.inner.jobs.lock().unwrap().insert(
+            session_id,
+            TranscodeJob {
+                job_id,
+                profile_id,
+                cancel: cancel.clone(),
+                progress: progress_rx,
+            },
+        );
+
+        tokio::spawn(Self::read_progress(stderr, progress_tx.clone()));
+        tokio::spawn(Self::supervise(
+            self.clone(),
+            session_id,
+            job_id,
+            child,
+            cancel.clone(),
+            progress_tx,
+            permit,
+        ));
+
+        Ok(TranscodeStream {
+            inner: ReaderStream::new(stdout),
+            _guard: cancel.drop_guard(),
+        })
+    }
+
+    /// The profile a transcode session was started for.
+    pub fn owner(&self, session_id: Uuid) -> Option<Uuid> {
+        let jobs = self.inner.jobs.lock().unwrap();
+        jobs.get(&session_id).map(|job| job.profile_id)
+    }
+
+    pub fn progress(&self, session_id: Uuid) -> Option<TranscodeProgress> {
+        let jobs = self.inner.jobs.lock().unwrap();
+        jobs.get(&session_id)
+            .map(|job| job.progress.borrow().clone())
+    }
+
+    pub fn cancel(&self, session_id: Uuid) -> bool {
+        let jobs = self.inner.jobs.lock().unwrap();
+        match jobs.get(&session_id) {
+            Some(job) => {
+                job.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn build_args(input: &std::path::Path, offset: f64, options: &TranscodeOptions) -> Vec<String> {
+        let mut args: Vec<String> = vec![
+            "-hide_banner".into(),
+            "-nostdin".into(),
+            "-loglevel".into(),
+            "error".into(),
+        ];
+
+        // Input seeking is fast and accurate enough for playback restarts
+        if offset > 0.0 {
+            args.extend(["-ss".into(), format!("{:.3}", offset)]);
+        }
+        args.extend(["-i".into(), input.to_string_lossy().into_owned()]);
+        args.extend([
+            "-map".into(),
+            "0:v:0?".into(),
+            "-map".into(),
+            "0:a:0?".into(),
+        ]);
+
+        args.extend([
+            "-c:v".into(),
+            Self::video_encoder(&options.video_codec).into(),
+        ]);
+        if options.video_codec != "copy" {
+            if let Some(bitrate) = options.video_bitrate {
+                args.extend([
+                    "-b:v".into(),
+                    format!("{}k", bitrate),
+                    "-maxrate".into(),
+                    format!("{}k", bitrate),
+                    "-bufsize".into(),
+                    format!("{}k", bitrate * 2),
+                ]);
+            }
+            if options.max_width.is_some() || options.max_height.is_some() {
+                // Only ever scale down, keep aspect ratio and even dimensions
+                let width = options
+                    .max_width
+                    .map_or("iw".to_string(), |w| format!("min(iw,{})", w));
+                let height = options
+                    .max_height
+                    .map_or("ih".to_string(), |h| format!("min(ih,{})", h));
+                args.extend([
+                    "-vf".into(),
+                    format!(
+                        "scale=w='{}':h='{}':force_original_aspect_ratio=decrease:force_divisible_by=2",
+                        width, height
+                    ),
+                ]);
+            }
+        }
+
+        args.extend([
+            "-c:a".into(),
+            Self::audio_encoder(&options.audio_codec).into(),
+        ]);
+        if options.audio_codec != "copy"
+            && let Some(bitrate) = options.audio_bitrate
+        {
+            args.extend(["-b:a".into(), format!("{}k", bitrate)]);
+        }
+
+        if options.container == TranscodeContainer::Mp4 {
+            // Fragmented MP4 so the output can be streamed without seeking back
+            args.extend([
+                "-movflags".into(),
+                "frag_keyframe+empty_moov+default_base_moof".into(),
+            ]);
+        }
+
+        args.extend([
+            "-f".into(),
+            options.container.ffmpeg_format().into(),
+            "-progress".into(),
+            "pipe:2".into(),
+            "-nostats".into(),
+            "pipe:1".into(),
+        ]);
+        args
+    }
+
+    fn video_encoder(codec: &str) -> &'static str {
+        match codec {
+            "copy" => "copy",
+            "hevc" | "h265" => "libx265",
+            "vp9" => "libvpx-vp9",
+            "av1" => "libsvtav1",
+            _ => "libx264",
+        }
+    }
+
+    fn audio_encoder(codec: &str) -> &'static str {
+        match codec {
+            "copy" => "copy",
+            "opus" => "libopus",
+            "mp3" => "libmp3lame",
+            "ac3" => "ac3",
+            "flac" => "flac",
+            _ => "aac",
+        }
+    }
+
+    /// Parses the `key=value` blocks written by `-progress pipe:2`.
+    async fn read_progress(stderr: ChildStderr, progress: watch::Sender<TranscodeProgress>) {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Some((key, value)) = line.split_once('=') else {
+                tracing::warn!("ffmpeg: {}", line);
+                continue;
+            };
+            progress.send_modify(|p| Self::apply_progress(p, key, value.trim()));
+        }
+    }
+
+    fn apply_progress(p: &mut TranscodeProgress, key: &str, value: &str) {
+        match key {
+            "out_time_us" | "out_time_ms" => {
+                // Both keys are reported in microseconds by ffmpeg
+                if let Ok(us) = value.parse::<f64>() {
+                    p.position = p.start_offset + us / 1_000_000.0;
+                    p.percent = p
+                        .duration
+                        .filter(|d| *d > 0.0)
+                        .map(|d| (p.position / d * 100.0).min(100.0));
+                }
+            }
+            "speed" => p.speed = value.trim_end_matches('x').parse().ok(),
+            "fps" => p.fps = value.parse().ok(),
+            "bitrate" => p.bitrate = value.trim_end_matches("kbits/s").parse().ok(),
+            "progress" if p.state == TranscodeState::Starting => {
+                p.state = TranscodeState::Running;
+            }
+            _ => {}
+        }
+    }
+
+    async fn supervise(
+        manager: TranscodeManager,
+        session_id: Uuid,
+        job_id: Uuid,
+        mut child: tokio::process::Child,
+        cancel: CancellationToken,
+        progress: watch::Sender<TranscodeProgress>,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let state = tokio::select! {
+            _ = cancel.cancelled() => {
+                if let Err(e) = child.kill().await {
+                    tracing::warn!("failed to kill ffmpeg for session {}: {}", session_id, e);
+                }
+                TranscodeState::Cancelled
+            }
+            status = child.wait() => match status {
+                Ok(status) if status.success() => TranscodeState::Finished,
+                Ok(status) => {
+                    tracing::warn!("ffmpeg for session {} exited with {}", session_id, status);
+                    TranscodeState::Failed
+                }
+                Err(e) => {
+                    tracing::warn!("failed to wait for ffmpeg for session {}: {}", session_id, e);
+                    TranscodeState::Failed
+                }
+            },
+        };
+        progress.send_modify(|p| p.state = state);
+        drop(permit);
+
+        // Keep the final state around for a while so clients can still query it
+        tokio::time::sleep(FINISHED_JOB_TTL).await;
+        let mut jobs = manager.inner.jobs.lock().unwrap();
+        if jobs
+            .get(&session_id)
+            .is_some_and(|job| job.job_id == job_id)
+        {
+            jobs.remove(&session_id);
+        }
+    }
+}
+
+/// ffmpeg output; dropping it (e.g. when the client disconnects) stops the transcoder.
+pub struct TranscodeStream {
+    inner: ReaderStream<tokio::process::ChildStdout>,
+    _guard: DropGuard,
+}
+
+impl Stream for TranscodeStream {
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn args(offset: f64, options: &TranscodeOptions) -> Vec<String> {
+        TranscodeManager::build_args(Path::new("/media/movie.mkv"), offset, options)
+    }
+
+    // The value following `flag`, if the flag is there
+    fn value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+        let at = args.iter().position(|arg| arg == flag)?;
+        args.get(at + 1).map(String::as_str)
+    }
+
+    fn progress(start_offset: f64, duration: Option<f64>) -> TranscodeProgress {
+        TranscodeProgress {
+            session_id: Uuid::nil(),
+            media_id: Uuid::nil(),
+            state: TranscodeState::Starting,
+            start_offset,
+            position: start_offset,
+            duration,
+            percent: None,
+            speed: None,
+            fps: None,
+            bitrate: None,
+        }
+    }
+
+    #[test]
+    fn default_options_encode_h264_and_aac_to_mpegts() {
+        let args = args(0.0, &TranscodeOptions::default());
+        assert_eq!(value(&args, "-ss"), None);
+        assert_eq!(value(&args, "-i"), Some("/media/movie.mkv"));
+        assert_eq!(value(&args, "-c:v"), Some("libx264"));
+        assert_eq!(value(&args, "-c:a"), Some("aac"));
+        assert_eq!(value(&args, "-f"), Some("mpegts"));
+        assert_eq!(value(&args, "-vf"), None);
+        assert_eq!(value(&args, "-movflags"), None);
+        assert_eq!(args.last().map(String::as_str), Some("pipe:1"));
+    }
+
+    #[test]
+    fn seeks_before_the_input() {
+        let args = args(90.5, &TranscodeOptions::default());
+        assert_eq!(value(&args, "-ss"), Some("90.500"));
+        let seek = args.iter().position(|arg| arg == "-ss");
+        let input = args.iter().position(|arg| arg == "-i");
+        assert!(seek < input);
+    }
+
+    #[test]
+    fn limits_bitrate_and_only_scales_down() {
+        let options = TranscodeOptions {
+            container: TranscodeContainer::Mp4,
+            video_codec: "hevc".to_string(),
+            audio_codec: "opus".to_string(),
+            video_bitrate: Some(4000),
+            audio_bitrate: Some(128),
+            max_width: Some(1280),
+            max_height: None,
+        };
+        let args = args(0.0, &options);
+        assert_eq!(value(&args, "-c:v"), Some("libx265"));
+        assert_eq!(value(&args, "-b:v"), Some("4000k"));
+        assert_eq!(value(&args, "-bufsize"), Some("8000k"));
+        assert_eq!(
+            value(&args, "-vf"),
+            Some(
+                "scale=w='min(iw,1280)':h='ih':force_original_aspect_ratio=decrease:force_divisible_by=2"
+            )
+        );
+        assert_eq!(value(&args, "-c:a"), Some("libopus"));
+        assert_eq!(value(&args, "-b:a"), Some("128k"));
+        assert_eq!(value(&args, "-f"), Some("mp4"));
+        assert!(value(&args, "-movflags").is_some_and(|flags| flags.contains("frag_keyframe")));
+    }
+
+    #[test]
+    fn copied_streams_get_no_encoder_options() {
+        let options = TranscodeOptions {
+            container: TranscodeContainer::WebM,
+            video_codec: "copy".to_string(),
+            audio_codec: "copy".to_string(),
+            video_bitrate: Some(4000),
+            audio_bitrate: Some(128),
+            max_width: Some(1280),
+            max_height: Some(720),
+        };
+        let args = args(0.0, &options);
+        assert_eq!(value(&args, "-c:v"), Some("copy"));
+        assert_eq!(value(&args, "-c:a"), Some("copy"));
+        assert_eq!(value(&args, "-b:v"), None);
+        assert_eq!(value(&args, "-b:a"), None);
+        assert_eq!(value(&args, "-vf"), None);
+        assert_eq!(value(&args, "-f"), Some("webm"));
+    }
+
+    #[test]
+    fn progress_is_absolute_within_the_source() {
+        let mut p = progress(60.0, Some(120.0));
+        TranscodeManager::apply_progress(&mut p, "out_time_us", "30000000");
+        assert_eq!(p.position, 90.0);
+        assert_eq!(p.percent, Some(75.0));
+
+        TranscodeManager::apply_progress(&mut p, "out_time_ms", "90000000");
+        assert_eq!(p.position, 150.0);
+        assert_eq!(p.percent, Some(100.0));
+
+        TranscodeManager::apply_progress(&mut p, "out_time_us", "N/A");
+        assert_eq!(p.position, 150.0);
+    }
+
+    #[test]
+    fn progress_without_duration_has_no_percent() {
+        let mut p = progress(0.0, None);
+        TranscodeManager::apply_progress(&mut p, "out_time_us", "5000000");
+        assert_eq!(p.position, 5.0);
+        assert_eq!(p.percent, None);
+    }
+
+    #[test]
+    fn parses_speed_fps_and_bitrate() {
+        let mut p = progress(0.0, None);
+        TranscodeManager::apply_progress(&mut p, "speed", "1.25x");
+        TranscodeManager::apply_progress(&mut p, "fps", "24.0");
+        TranscodeManager::apply_progress(&mut p, "bitrate", "2048.5kbits/s");
+        TranscodeManager::apply_progress(&mut p, "frame", "100");
+        assert_eq!(p.speed, Some(1.25));
+        assert_eq!(p.fps, Some(24.0));
+        assert_eq!(p.bitrate, Some(2048.5));
+
+        TranscodeManager::apply_progress(&mut p, "speed", "N/A");
+        assert_eq!(p.speed, None);
+    }
+
+    #[test]
+    fn first_progress_block_marks_the_job_running() {
+        let mut p = progress(0.0, None);
+        TranscodeManager::apply_progress(&mut p, "progress", "continue");
+        assert_eq!(p.state, TranscodeState::Running);
+
+        p.state = TranscodeState::Cancelled;
+        TranscodeManager::apply_progress(&mut p, "progress", "end");
+        assert_eq!(p.state, TranscodeState::Cancelled);
+    }
+}
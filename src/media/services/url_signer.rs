@@ -0,0 +1,211 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::{errors::AppError, state::AppState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Query parameters carried by a signed stream URL.
+#[derive(Debug, Deserialize)]
+pub struct SignedQuery {
+    pub profile_id: Uuid,
+    pub expires: i64, // unix timestamp
+    pub signature: String,
+    #[serde(default)]
+    pub bind_ip: bool,
+}
+
+/// Signs stream URLs so players that can't send an `Authorization` header
+/// (`<video>`, Chromecast, VLC) can still fetch a single media item.
+pub struct UrlSigner;
+
+impl UrlSigner {
+    /// Appends `profile_id`, `expires` and `signature` to a media URL.
+    pub fn sign_url(
+        state: &AppState,
+        url: &str,
+        media_id: Uuid,
+        profile_id: Uuid,
+        client_ip: Option<IpAddr>,
+    ) -> String {
+        let expires = Utc::now().timestamp() + state.stream_url_ttl;
+        let bound_ip = client_ip.filter(|_| state.stream_url_bind_ip);
+        let signature = Self::signature(
+            &state.stream_url_secret,
+            media_id,
+            profile_id,
+            expires,
+            bound_ip,
+        );
+
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let mut signed = format!(
+            "{}{}profile_id={}&expires={}&signature={}",
+            url, separator, profile_id, expires, signature
+        );
+        if bound_ip.is_some() {
+            signed.push_str("&bind_ip=true");
+        }
+        signed
+    }
+
+    /// Checks a signed URL for `media_id` and returns the profile it was issued to.
+    pub fn verify(
+        state: &AppState,
+        media_id: Uuid,
+        query: &SignedQuery,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Uuid, AppError> {
+        if query.expires < Utc::now().timestamp() {
+            return Err(AppError::AuthenticationError);
+        }
+
+        let bound_ip = if query.bind_ip {
+            Some(client_ip.ok_or(AppError::AuthenticationError)?)
+        } else {
+            None
+        };
+        let signature = hex::decode(&query.signature).map_err(|_| AppError::AuthenticationError)?;
+
+        Self::mac(
+            &state.stream_url_secret,
+            media_id,
+            query.profile_id,
+            query.expires,
+            bound_ip,
+        )
+        .verify_slice(&signature)
+        .map_err(|_| AppError::AuthenticationError)?;
+
+        Ok(query.profile_id)
+    }
+
+    fn signature(
+        secret: &str,
+        media_id: Uuid,
+        profile_id: Uuid,
+        expires: i64,
+        ip: Option<IpAddr>,
+    ) -> String {
+        hex::encode(
+            Self::mac(secret, media_id, profile_id, expires, ip)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    fn mac(
+        secret: &str,
+        media_id: Uuid,
+        profile_id: Uuid,
+        expires: i64,
+        ip: Option<IpAddr>,
+    ) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+        let ip = ip.map(|ip| ip.to_string()).unwrap_or_default();
+        mac.update(format!("{}:{}:{}:{}", media_id, profile_id, expires, ip).as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Splits a signed URL back into the query its handler would receive
+    fn query(url: &str) -> SignedQuery {
+        let (_, query) = url.split_once('?').unwrap();
+        let param = |key: &str| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+        };
+        SignedQuery {
+            profile_id: param("profile_id").unwrap().parse().unwrap(),
+            expires: param("expires").unwrap().parse().unwrap(),
+            signature: param("signature").unwrap().to_string(),
+            bind_ip: param("bind_ip") == Some("true"),
+        }
+    }
+
+    fn client() -> Option<IpAddr> {
+        Some("192.168.1.20".parse().unwrap())
+    }
+
+    #[test]
+    fn signed_urls_verify_for_their_media_only() {
+        let state = AppState::for_tests();
+        let (media_id, profile_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let url = UrlSigner::sign_url(
+            &state,
+            &format!("/v1/media/{}/file?session_id={}", media_id, Uuid::new_v4()),
+            media_id,
+            profile_id,
+            client(),
+        );
+        assert!(url.contains("?session_id=") && url.contains("&profile_id="));
+        assert!(!url.contains("bind_ip"));
+
+        let query = query(&url);
+        let verified = UrlSigner::verify(&state, media_id, &query, None).unwrap();
+        assert_eq!(verified, profile_id);
+        assert!(UrlSigner::verify(&state, Uuid::new_v4(), &query, None).is_err());
+    }
+
+    #[test]
+    fn tampered_or_foreign_signatures_are_rejected() {
+        let state = AppState::for_tests();
+        let media_id = Uuid::new_v4();
+        let url = UrlSigner::sign_url(&state, "/file", media_id, Uuid::new_v4(), None);
+
+        let mut other_profile = query(&url);
+        other_profile.profile_id = Uuid::new_v4();
+        assert!(UrlSigner::verify(&state, media_id, &other_profile, None).is_err());
+
+        let mut extended = query(&url);
+        extended.expires += 3600;
+        assert!(UrlSigner::verify(&state, media_id, &extended, None).is_err());
+
+        let mut garbled = query(&url);
+        garbled.signature = "not hex".to_string();
+        assert!(UrlSigner::verify(&state, media_id, &garbled, None).is_err());
+
+        let mut other_secret = AppState::for_tests();
+        other_secret.stream_url_secret = "another-secret".to_string();
+        assert!(UrlSigner::verify(&other_secret, media_id, &query(&url), None).is_err());
+    }
+
+    #[test]
+    fn expired_urls_are_rejected() {
+        let mut state = AppState::for_tests();
+        state.stream_url_ttl = -1;
+        let media_id = Uuid::new_v4();
+        let url = UrlSigner::sign_url(&state, "/file", media_id, Uuid::new_v4(), None);
+        assert!(UrlSigner::verify(&state, media_id, &query(&url), None).is_err());
+    }
+
+    #[test]
+    fn bound_urls_only_work_from_the_signing_address() {
+        let mut state = AppState::for_tests();
+        state.stream_url_bind_ip = true;
+        let media_id = Uuid::new_v4();
+        let url = UrlSigner::sign_url(&state, "/file", media_id, Uuid::new_v4(), client());
+        assert!(url.ends_with("&bind_ip=true"));
+
+        let query = query(&url);
+        assert!(UrlSigner::verify(&state, media_id, &query, client()).is_ok());
+        let elsewhere = Some("10.0.0.7".parse().unwrap());
+        assert!(UrlSigner::verify(&state, media_id, &query, elsewhere).is_err());
+        assert!(UrlSigner::verify(&state, media_id, &query, None).is_err());
+
+        // Dropping the flag doesn't unbind the signature
+        let mut unbound = query;
+        unbound.bind_ip = false;
+        assert!(UrlSigner::verify(&state, media_id, &unbound, client()).is_err());
+    }
+}
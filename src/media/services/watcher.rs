@@ -0,0 +1,241 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use sea_orm::EntityTrait;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::Instant};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    errors::AppError,
+    media::services::{
+        scan_jobs::{ScanJobService, ScanScope, ScanTrigger},
+        scanner::ScanReport,
+    },
+    state::AppState,
+};
+
+const LIBRARY_POLL_INTERVAL: Duration = Duration::from_secs(60); // for added or removed libraries
+
+/// How watchers batch events and how often they fall back to a full scan.
+#[derive(Debug, Clone, Copy)]
+pub struct WatchConfig {
+    pub debounce: Duration, // quiet time before a batch of events is scanned
+    pub full_scan_interval: Duration, // catches events the OS dropped
+}
+
+impl WatchConfig {
+    pub fn from_env() -> Self {
+        let debounce = env::var("SCAN_DEBOUNCE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2000);
+        let full_scan_interval = env::var("FULL_SCAN_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(6 * 60 * 60);
+        WatchConfig {
+            debounce: Duration::from_millis(debounce),
+            full_scan_interval: Duration::from_secs(full_scan_interval),
+        }
+    }
+}
+
+/// Keeps every library in sync with its folder: inotify (or the platform
+/// equivalent) for changes as they happen, plus a periodic full scan.
+pub struct LibraryWatcher;
+
+impl LibraryWatcher {
+    /// Starts a watcher per library and keeps the set in line with the `library` table.
+    pub fn spawn_all(state: AppState) {
+        if env::var("WATCH_LIBRARIES").unwrap_or_else(|_| "true".to_string()) != "true" {
+            return;
+        }
+        let config = WatchConfig::from_env();
+
+        tokio::spawn(async move {
+            let mut watchers: HashMap<Uuid, (String, CancellationToken)> = HashMap::new();
+            let mut poll = tokio::time::interval(LIBRARY_POLL_INTERVAL);
+            loop {
+                poll.tick().await;
+                let libraries = match entity::library::Entity::find().all(&state.conn).await {
+                    Ok(libraries) => libraries,
+                    Err(e) => {
+                        tracing::warn!("failed to list libraries to watch: {}", e);
+                        continue;
+                    }
+                };
+
+                // A library whose path changed gets a fresh watcher
+                let current: HashMap<Uuid, String> = libraries
+                    .into_iter()
+                    .map(|library| (library.id, library.path))
+                    .collect();
+                watchers.retain(|id, (path, stop)| {
+                    let keep = current.get(id) == Some(path);
+                    if !keep {
+                        stop.cancel();
+                    }
+                    keep
+                });
+                for (id, path) in current {
+                    if watchers.contains_key(&id) {
+                        continue;
+                    }
+                    let stop = CancellationToken::new();
+                    tokio::spawn(Self::watch(
+                        state.clone(),
+                        id,
+                        path.clone(),
+                        config,
+                        stop.clone(),
+                    ));
+                    watchers.insert(id, (path, stop));
+                }
+            }
+        });
+    }
+
+    /// Watches one library until `stop` is cancelled.
+    pub async fn watch(
+        state: AppState,
+        library_id: Uuid,
+        library_path: String,
+        config: WatchConfig,
+        stop: CancellationToken,
+    ) {
+        let (tx, mut events) = mpsc::unbounded_channel();
+        // Without a watcher, the periodic full scans still keep the library current
+        let watcher = match Self::start_watcher(Path::new(&library_path), tx) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                tracing::warn!("can't watch {}, relying on full scans: {}", library_path, e);
+                None
+            }
+        };
+
+        // The first tick runs a full scan right away, picking up changes made while stopped
+        let mut full_scan = tokio::time::interval(config.full_scan_interval);
+        let mut pending: HashSet<PathBuf> = HashSet::new();
+        let mut rescan_all = false;
+        let mut deadline: Option<Instant> = None;
+        let mut watching = watcher.is_some();
+
+        loop {
+            let flush = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = stop.cancelled() => break,
+                _ = full_scan.tick() => {
+                    let result = ScanJobService::run(
+                        state.clone(),
+                        library_id,
+                        library_path.clone(),
+                        ScanTrigger::Periodic,
+                        ScanScope::Full,
+                    )
+                    .await;
+                    // Skipped while a manual scan holds the library; keep what's pending
+                    if !matches!(result, Err(AppError::Conflict(_))) {
+                        pending.clear();
+                        rescan_all = false;
+                        deadline = None;
+                    }
+                    Self::log(&library_path, result);
+                }
+                event = events.recv(), if watching => {
+                    let Some(event) = event else {
+                        watching = false;
+                        continue;
+                    };
+                    match event {
+                        Ok(event) => {
+                            if event.need_rescan() {
+                                rescan_all = true;
+                            } else if Self::is_relevant(&event) {
+                                pending.extend(event.paths);
+                            } else {
+                                continue;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!("watch error in {}: {}", library_path, e);
+                            rescan_all = true;
+                        }
+                    }
+                    // Every new event pushes the scan back until things settle
+                    deadline = Some(Instant::now() + config.debounce);
+                }
+                _ = flush => {
+                    let scope = if rescan_all {
+                        ScanScope::Full
+                    } else {
+                        ScanScope::Paths(pending.iter().cloned().collect())
+                    };
+                    let result = ScanJobService::run(
+                        state.clone(),
+                        library_id,
+                        library_path.clone(),
+                        ScanTrigger::Watch,
+                        scope,
+                    )
+                    .await;
+                    // Another scan holds the library; try the batch again later
+                    if matches!(result, Err(AppError::Conflict(_))) {
+                        deadline = Some(Instant::now() + config.debounce);
+                        continue;
+                    }
+                    pending.clear();
+                    rescan_all = false;
+                    deadline = None;
+                    Self::log(&library_path, result);
+                }
+            }
+        }
+    }
+
+    fn start_watcher(
+        path: &Path,
+        tx: mpsc::UnboundedSender<notify::Result<Event>>,
+    ) -> notify::Result<RecommendedWatcher> {
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    }
+
+    // Creates, writes, deletes and renames (both sides); reads and metadata-only changes aren't
+    fn is_relevant(event: &Event) -> bool {
+        match event.kind {
+            EventKind::Create(_) | EventKind::Remove(_) => true,
+            EventKind::Modify(kind) => !matches!(kind, notify::event::ModifyKind::Metadata(_)),
+            _ => false,
+        }
+    }
+
+    fn log(library_path: &str, result: Result<ScanReport, AppError>) {
+        match result {
+            Ok(report) if report.added + report.updated + report.moved + report.removed > 0 => {
+                tracing::info!(
+                    "scanned {}: {} added, {} updated, {} moved, {} removed",
+                    library_path,
+                    report.added,
+                    report.updated,
+                    report.moved,
+                    report.removed
+                )
+            }
+            Ok(_) | Err(AppError::Conflict(_)) => {}
+            Err(e) => tracing::warn!("failed to scan {}: {}", library_path, e),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod auth;
 pub mod entity;
 pub mod routes;
+pub mod shares;
 pub mod state;
 pub mod errors;
 pub mod media;
\ No newline at end of file
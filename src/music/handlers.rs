@@ -0,0 +1,73 @@
+use crate::{
+    auth::models::Claims,
+    errors::{AppError, Result},
+    music::{
+        models::{AlbumResponse, AlbumTracksResponse, ArtistQuery, ArtistResponse, TrackResponse},
+        services::{album_tracks, artist_albums, list_artists},
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use uuid::Uuid;
+
+// Handler for listing artists by name
+pub async fn list_artists_handler(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Query(query): Query<ArtistQuery>,
+) -> Result<impl IntoResponse> {
+    let artists: Vec<ArtistResponse> = list_artists(&state, query)
+        .await?
+        .into_iter()
+        .map(ArtistResponse::from)
+        .collect();
+    Ok::<_, AppError>((StatusCode::OK, Json(artists)).into_response())
+}
+
+// Handler for listing an artist's albums and the compilations they appear on
+pub async fn artist_albums_handler(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Path(artist_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let albums: Vec<AlbumResponse> = artist_albums(&state, artist_id)
+        .await?
+        .into_iter()
+        .map(AlbumResponse::from)
+        .collect();
+    Ok::<_, AppError>((StatusCode::OK, Json(albums)).into_response())
+}
+
+// Handler for an album's tracks in disc and track order
+pub async fn album_tracks_handler(
+    State(state): State<AppState>,
+    _claims: Claims,
+    Path(album_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let (album, artist, tracks) = album_tracks(&state, album_id).await?;
+    let tracks = tracks
+        .into_iter()
+        .map(|(track, artist)| TrackResponse {
+            id: track.id,
+            media_id: track.media_id,
+            album_id: track.album_id,
+            artist_id: track.artist_id,
+            artist,
+            title: track.title,
+            disc_number: track.disc_number,
+            track_number: track.track_number,
+            duration: track.duration,
+            musicbrainz_id: track.musicbrainz_id,
+        })
+        .collect();
+    let response = AlbumTracksResponse {
+        album: AlbumResponse::from(album),
+        artist,
+        tracks,
+    };
+    Ok::<_, AppError>((StatusCode::OK, Json(response)).into_response())
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct ArtistQuery {
+    pub library_id: Option<Uuid>, // only artists with albums or tracks in this library
+    pub offset: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub musicbrainz_id: Option<String>,
+}
+
+impl From<entity::artist::Model> for ArtistResponse {
+    fn from(artist: entity::artist::Model) -> Self {
+        ArtistResponse {
+            id: artist.id,
+            name: artist.name,
+            musicbrainz_id: artist.musicbrainz_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumResponse {
+    pub id: Uuid,
+    pub library_id: Uuid,
+    pub artist_id: Uuid, // the album artist; "Various Artists" for compilations
+    pub title: String,
+    pub year: Option<i32>,
+    pub disc_total: Option<i32>,
+    pub is_compilation: bool,
+    pub musicbrainz_id: Option<String>,
+    pub cover_hash: Option<String>, // changes when the cover does
+}
+
+impl From<entity::album::Model> for AlbumResponse {
+    fn from(album: entity::album::Model) -> Self {
+        AlbumResponse {
+            id: album.id,
+            library_id: album.library_id,
+            artist_id: album.artist_id,
+            title: album.title,
+            year: album.year,
+            disc_total: album.disc_total,
+            is_compilation: album.is_compilation,
+            musicbrainz_id: album.musicbrainz_id,
+            cover_hash: album.cover_hash,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackResponse {
+    pub id: Uuid,
+    pub media_id: Uuid, // what to stream
+    pub album_id: Uuid,
+    pub artist_id: Uuid,
+    pub artist: String,
+    pub title: String,
+    pub disc_number: i32,
+    pub track_number: Option<i32>,
+    pub duration: Option<f64>, // in seconds
+    pub musicbrainz_id: Option<String>,
+}
+
+/// An album and its tracks in disc and track order.
+#[derive(Debug, Serialize)]
+pub struct AlbumTracksResponse {
+    pub album: AlbumResponse,
+    pub artist: String,
+    pub tracks: Vec<TrackResponse>,
+}
@@ -0,0 +1,458 @@
+use crate::{
+    errors::{AppError, Result},
+    media::services::tags::MusicTags,
+    music::models::ArtistQuery,
+    state::AppState,
+};
+use chrono::Utc;
+use entity::{album, artist, track};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+    sea_query::{Condition, Expr, OnConflict, Query},
+};
+use uuid::Uuid;
+
+const VARIOUS_ARTISTS: &str = "Various Artists";
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+const UNKNOWN_ALBUM: &str = "Unknown Album";
+const DISC_MARKERS: &[&str] = &["disc", "disk", "cd"];
+
+/// A scanned audio file to file under its artist and album.
+pub struct TrackSource<'a> {
+    pub library_id: Uuid,
+    pub media_id: Uuid,
+    pub file_path: &'a str, // relative to the library root
+    pub title: &'a str,     // the media title, used when the tags have none
+    pub duration: Option<f64>,
+    pub tags: &'a MusicTags,
+}
+
+/// Creates or updates the track for an audio file, creating its artist and
+/// album on first sight. Untagged files land under "Unknown Artist" and an
+/// "Unknown Album" per folder.
+pub async fn index_track(state: &AppState, source: TrackSource<'_>) -> Result<track::Model> {
+    let db = &state.conn;
+    let tags = source.tags;
+    let (folder, folder_disc) = album_folder(source.file_path);
+    let (album_title, title_disc) = match tags.album.as_deref() {
+        Some(album) => split_disc(album),
+        None => (UNKNOWN_ALBUM.to_string(), None),
+    };
+
+    let artist_name = tags
+        .artist
+        .as_deref()
+        .or(tags.album_artist.as_deref())
+        .unwrap_or(UNKNOWN_ARTIST);
+    let artist =
+        find_or_create_artist(db, artist_name, tags.musicbrainz.artist_id.as_deref()).await?;
+    let album = resolve_album(db, &source, &album_title, folder, &artist).await?;
+    let album = update_album(db, album, tags).await?;
+
+    let now = Utc::now().naive_utc();
+    let existing = track::Entity::find()
+        .filter(track::Column::MediaId.eq(source.media_id))
+        .one(db)
+        .await?;
+    let mut model = match &existing {
+        Some(existing) => existing.clone().into_active_model(),
+        None => track::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            media_id: Set(source.media_id),
+            created_at: Set(now),
+            ..Default::default()
+        },
+    };
+    model.album_id = Set(album.id);
+    model.artist_id = Set(artist.id);
+    model.title = Set(tags
+        .title
+        .clone()
+        .unwrap_or_else(|| source.title.to_string()));
+    // Multi-disc albums may only say which disc they are in the album title or folder
+    model.disc_number = Set(tags.disc_number.or(title_disc).or(folder_disc).unwrap_or(1) as i32);
+    model.track_number = Set(tags.track_number.map(|number| number as i32));
+    model.duration = Set(source.duration);
+    model.musicbrainz_id = Set(tags.musicbrainz.recording_id.clone());
+    model.updated_at = Set(now);
+    let track = match existing {
+        Some(_) => model.update(db).await?,
+        None => model.insert(db).await?,
+    };
+    Ok(track)
+}
+
+/// Drops albums without tracks and artists without albums or tracks, e.g.
+/// after files were removed or retagged.
+pub async fn prune(state: &AppState) -> Result<()> {
+    let db = &state.conn;
+    album::Entity::delete_many()
+        .filter(
+            album::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(track::Column::AlbumId)
+                    .from(track::Entity)
+                    .to_owned(),
+            ),
+        )
+        .exec(db)
+        .await?;
+    artist::Entity::delete_many()
+        .filter(
+            artist::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(album::Column::ArtistId)
+                    .from(album::Entity)
+                    .to_owned(),
+            ),
+        )
+        .filter(
+            artist::Column::Id.not_in_subquery(
+                Query::select()
+                    .column(track::Column::ArtistId)
+                    .from(track::Entity)
+                    .to_owned(),
+            ),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_artists(state: &AppState, query: ArtistQuery) -> Result<Vec<artist::Model>> {
+    let mut select = artist::Entity::find().order_by_asc(artist::Column::NameKey);
+    if let Some(library_id) = query.library_id {
+        let album_artists = Query::select()
+            .column(album::Column::ArtistId)
+            .from(album::Entity)
+            .and_where(album::Column::LibraryId.eq(library_id))
+            .to_owned();
+        let track_artists = Query::select()
+            .column((track::Entity, track::Column::ArtistId))
+            .from(track::Entity)
+            .inner_join(
+                album::Entity,
+                Expr::col((album::Entity, album::Column::Id))
+                    .equals((track::Entity, track::Column::AlbumId)),
+            )
+            .and_where(Expr::col((album::Entity, album::Column::LibraryId)).eq(library_id))
+            .to_owned();
+        select = select.filter(
+            Condition::any()
+                .add(artist::Column::Id.in_subquery(album_artists))
+                .add(artist::Column::Id.in_subquery(track_artists)),
+        );
+    }
+    Ok(select
+        .offset(query.offset.unwrap_or(0))
+        .limit(query.limit.unwrap_or(100).min(1000))
+        .all(&state.conn)
+        .await?)
+}
+
+/// The artist's own albums and the compilations they appear on, oldest first.
+pub async fn artist_albums(state: &AppState, artist_id: Uuid) -> Result<Vec<album::Model>> {
+    artist::Entity::find_by_id(artist_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let appears_on = Query::select()
+        .column(track::Column::AlbumId)
+        .from(track::Entity)
+        .and_where(track::Column::ArtistId.eq(artist_id))
+        .to_owned();
+    Ok(album::Entity::find()
+        .filter(
+            Condition::any()
+                .add(album::Column::ArtistId.eq(artist_id))
+                .add(album::Column::Id.in_subquery(appears_on)),
+        )
+        .order_by_asc(album::Column::Year)
+        .order_by_asc(album::Column::TitleKey)
+        .all(&state.conn)
+        .await?)
+}
+
+/// An album, its artist's name and its tracks with theirs, in disc and track order.
+pub async fn album_tracks(
+    state: &AppState,
+    album_id: Uuid,
+) -> Result<(album::Model, String, Vec<(track::Model, String)>)> {
+    let (album, artist) = album::Entity::find_by_id(album_id)
+        .find_also_related(artist::Entity)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let tracks = track::Entity::find()
+        .filter(track::Column::AlbumId.eq(album_id))
+        .find_also_related(artist::Entity)
+        .order_by_asc(track::Column::DiscNumber)
+        .order_by_asc(track::Column::TrackNumber)
+        .order_by_asc(track::Column::Title)
+        .all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|(track, artist)| (track, artist.map(|a| a.name).unwrap_or_default()))
+        .collect();
+    Ok((album, artist.map(|a| a.name).unwrap_or_default(), tracks))
+}
+
+// Tagged albums are found by MusicBrainz release, then by album artist and
+// title. Without an album artist, one title in one folder is one album, and
+// tracks by different artists turn it into a compilation
+async fn resolve_album<C: ConnectionTrait>(
+    db: &C,
+    source: &TrackSource<'_>,
+    title: &str,
+    folder: String,
+    artist: &artist::Model,
+) -> Result<album::Model> {
+    let tags = source.tags;
+    if let Some(release_id) = &tags.musicbrainz.release_id {
+        let album = album::Entity::find()
+            .filter(album::Column::LibraryId.eq(source.library_id))
+            .filter(album::Column::MusicbrainzId.eq(release_id))
+            .one(db)
+            .await?;
+        if let Some(album) = album {
+            return Ok(album);
+        }
+    }
+
+    let album_artist = tags.album_artist.as_deref();
+    if tags.compilation || album_artist.is_some_and(is_various) {
+        let various = find_or_create_artist(db, VARIOUS_ARTISTS, None).await?;
+        return find_or_create_album(db, source.library_id, &various, title, None, true).await;
+    }
+    if let Some(name) = album_artist {
+        let album_artist =
+            find_or_create_artist(db, name, tags.musicbrainz.album_artist_id.as_deref()).await?;
+        return find_or_create_album(db, source.library_id, &album_artist, title, None, false)
+            .await;
+    }
+
+    let existing = album::Entity::find()
+        .filter(album::Column::LibraryId.eq(source.library_id))
+        .filter(album::Column::Folder.eq(folder.as_str()))
+        .filter(album::Column::TitleKey.eq(key(title)))
+        .one(db)
+        .await?;
+    match existing {
+        Some(album) if album.artist_id == artist.id || album.is_compilation => Ok(album),
+        Some(album) => make_compilation(db, album).await,
+        None => {
+            find_or_create_album(db, source.library_id, artist, title, Some(folder), false).await
+        }
+    }
+}
+
+async fn make_compilation<C: ConnectionTrait>(db: &C, album: album::Model) -> Result<album::Model> {
+    let various = find_or_create_artist(db, VARIOUS_ARTISTS, None).await?;
+    // Another folder may already hold a compilation with the same title
+    let existing = album::Entity::find()
+        .filter(album::Column::LibraryId.eq(album.library_id))
+        .filter(album::Column::ArtistId.eq(various.id))
+        .filter(album::Column::TitleKey.eq(album.title_key.as_str()))
+        .one(db)
+        .await?;
+    if let Some(existing) = existing {
+        track::Entity::update_many()
+            .col_expr(track::Column::AlbumId, Expr::value(existing.id))
+            .filter(track::Column::AlbumId.eq(album.id))
+            .exec(db)
+            .await?;
+        album::Entity::delete_by_id(album.id).exec(db).await?;
+        return Ok(existing);
+    }
+
+    let mut album = album.into_active_model();
+    album.artist_id = Set(various.id);
+    album.is_compilation = Set(true);
+    album.updated_at = Set(Utc::now().naive_utc());
+    Ok(album.update(db).await?)
+}
+
+// Fills in what earlier tracks of the album didn't say
+async fn update_album<C: ConnectionTrait>(
+    db: &C,
+    album: album::Model,
+    tags: &MusicTags,
+) -> Result<album::Model> {
+    let year = album.year.or(tags.year);
+    let disc_total = album
+        .disc_total
+        .max(tags.disc_total.map(|total| total as i32))
+        .max(tags.disc_number.map(|number| number as i32));
+    let musicbrainz_id = album
+        .musicbrainz_id
+        .clone()
+        .or_else(|| tags.musicbrainz.release_id.clone());
+    let cover_hash = album
+        .cover_hash
+        .clone()
+        .or_else(|| tags.cover.as_ref().map(|cover| cover.hash.clone()));
+    if year == album.year
+        && disc_total == album.disc_total
+        && musicbrainz_id == album.musicbrainz_id
+        && cover_hash == album.cover_hash
+    {
+        return Ok(album);
+    }
+
+    let mut album = album.into_active_model();
+    album.year = Set(year);
+    album.disc_total = Set(disc_total);
+    album.musicbrainz_id = Set(musicbrainz_id);
+    album.cover_hash = Set(cover_hash);
+    album.updated_at = Set(Utc::now().naive_utc());
+    Ok(album.update(db).await?)
+}
+
+// Inserts are no-ops on conflict, so concurrent scans agree on one row
+async fn find_or_create_artist<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    musicbrainz_id: Option<&str>,
+) -> Result<artist::Model> {
+    let name_key = key(name);
+    let now = Utc::now().naive_utc();
+    artist::Entity::insert(artist::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        name: Set(name.trim().to_string()),
+        name_key: Set(name_key.clone()),
+        musicbrainz_id: Set(musicbrainz_id.map(str::to_string)),
+        created_at: Set(now),
+        updated_at: Set(now),
+    })
+    .on_conflict(
+        OnConflict::column(artist::Column::NameKey)
+            .do_nothing()
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+
+    let artist = artist::Entity::find()
+        .filter(artist::Column::NameKey.eq(name_key))
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    match musicbrainz_id {
+        Some(musicbrainz_id) if artist.musicbrainz_id.is_none() => {
+            let mut artist = artist.into_active_model();
+            artist.musicbrainz_id = Set(Some(musicbrainz_id.to_string()));
+            artist.updated_at = Set(now);
+            Ok(artist.update(db).await?)
+        }
+        _ => Ok(artist),
+    }
+}
+
+async fn find_or_create_album<C: ConnectionTrait>(
+    db: &C,
+    library_id: Uuid,
+    artist: &artist::Model,
+    title: &str,
+    folder: Option<String>,
+    compilation: bool,
+) -> Result<album::Model> {
+    let title_key = key(title);
+    let now = Utc::now().naive_utc();
+    // Tagged albums have no folder; folder-grouped ones are one per folder
+    let in_folder = match &folder {
+        Some(folder) => album::Column::Folder.eq(folder.as_str()),
+        None => album::Column::Folder.is_null(),
+    };
+    album::Entity::insert(album::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        library_id: Set(library_id),
+        artist_id: Set(artist.id),
+        title: Set(title.to_string()),
+        title_key: Set(title_key.clone()),
+        year: Set(None),
+        disc_total: Set(None),
+        is_compilation: Set(compilation),
+        musicbrainz_id: Set(None),
+        folder: Set(folder),
+        cover_hash: Set(None),
+        created_at: Set(now),
+        updated_at: Set(now),
+    })
+    .on_conflict(
+        OnConflict::columns([
+            album::Column::LibraryId,
+            album::Column::ArtistId,
+            album::Column::TitleKey,
+            album::Column::Folder,
+        ])
+        .do_nothing()
+        .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+
+    album::Entity::find()
+        .filter(album::Column::LibraryId.eq(library_id))
+        .filter(album::Column::ArtistId.eq(artist.id))
+        .filter(album::Column::TitleKey.eq(title_key))
+        .filter(in_folder)
+        .one(db)
+        .await?
+        .ok_or(AppError::NotFound)
+}
+
+// Case and runs of whitespace don't make a different artist or album
+fn key(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn is_various(name: &str) -> bool {
+    matches!(
+        key(name).as_str(),
+        "various artists" | "various" | "va" | "v.a." | "v/a"
+    )
+}
+
+// "Album (Disc 2)", "Album [CD2]" or "Album - Disk 2" are disc 2 of "Album"
+fn split_disc(title: &str) -> (String, Option<u32>) {
+    let lower = title.to_ascii_lowercase();
+    for marker in DISC_MARKERS {
+        let Some(at) = lower.rfind(marker) else {
+            continue;
+        };
+        let number = lower[at + marker.len()..]
+            .trim_end_matches([')', ']'])
+            .trim();
+        let album = title[..at].trim_end();
+        let separated = album.ends_with(['(', '[', '-']) || lower[..at].ends_with(' ');
+        let album = album.trim_end_matches(['(', '[', '-']).trim_end();
+        if separated
+            && !album.is_empty()
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+        {
+            return (album.to_string(), number.parse().ok());
+        }
+    }
+    (title.trim().to_string(), None)
+}
+
+// The folder a file's album lives in; "CD1" or "Disc 2" subfolders belong to
+// the album folder above them and say which disc the file is on
+fn album_folder(file_path: &str) -> (String, Option<u32>) {
+    let folder = file_path.rsplit_once('/').map_or("", |(folder, _)| folder);
+    let (parent, name) = folder.rsplit_once('/').unwrap_or(("", folder));
+    let lower = name.to_ascii_lowercase();
+    for marker in DISC_MARKERS {
+        if let Some(number) = lower.strip_prefix(marker)
+            && let Ok(disc) = number.trim().parse()
+        {
+            return (parent.to_string(), Some(disc));
+        }
+    }
+    (folder.to_string(), None)
+}
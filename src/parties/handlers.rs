@@ -0,0 +1,92 @@
+use crate::{
+    auth::{
+        models::Claims,
+        services::{authorize_profile, verify_jwt},
+    },
+    errors::{AppError, Result},
+    parties::{
+        models::{CreatePartyRequest, JoinPartyRequest, LeavePartyQuery, PartySocketQuery},
+        services::{PartyConnection, create_party, get_party, join_party, leave_party},
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json},
+};
+use uuid::Uuid;
+
+// Handler for starting a watch party
+pub async fn create_party_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<CreatePartyRequest>,
+) -> Result<impl IntoResponse> {
+    let party = create_party(State(state), claims, req).await?;
+    Ok::<_, AppError>((StatusCode::CREATED, Json(party)).into_response())
+}
+
+// Handler for joining a watch party through its invite link
+pub async fn join_party_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(token): Path<String>,
+    Json(req): Json<JoinPartyRequest>,
+) -> Result<impl IntoResponse> {
+    let party = join_party(State(state), claims, &token, req.profile_id).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(party)).into_response())
+}
+
+// Handler for the current state of a watch party
+pub async fn get_party_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(party_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let party = get_party(State(state), claims, party_id).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(party)).into_response())
+}
+
+// Handler for leaving a watch party, or ending it as the host
+pub async fn leave_party_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(party_id): Path<Uuid>,
+    Query(query): Query<LeavePartyQuery>,
+) -> Result<impl IntoResponse> {
+    leave_party(State(state), claims, party_id, query.profile_id, query.end).await?;
+    Ok::<_, AppError>(StatusCode::NO_CONTENT.into_response())
+}
+
+// Handler for the synchronized playback and chat WebSocket of a watch party
+pub async fn party_socket_handler(
+    State(state): State<AppState>,
+    Path(party_id): Path<Uuid>,
+    Query(query): Query<PartySocketQuery>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<impl IntoResponse> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or(query.token)
+        .ok_or(AppError::AuthenticationError)?;
+    let claims = verify_jwt(State(state.clone()), token).await?;
+    let profile = authorize_profile(
+        State(state.clone()),
+        &claims,
+        query.profile_id.unwrap_or(claims.sub),
+    )
+    .await?;
+    if !state.parties.is_member(party_id, profile.id) {
+        return Err(AppError::NotFound);
+    }
+
+    Ok::<_, AppError>(
+        ws.on_upgrade(move |socket| PartyConnection::run(state, socket, party_id, profile.id))
+            .into_response(),
+    )
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePartyRequest {
+    pub media_id: Uuid,
+    pub profile_id: Option<Uuid>, // the hosting profile; defaults to the caller
+    pub start_position: Option<f64>, // in seconds; defaults to the host's resume position
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct JoinPartyRequest {
+    pub profile_id: Option<Uuid>, // defaults to the caller
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeavePartyQuery {
+    pub profile_id: Option<Uuid>,
+    #[serde(default)]
+    pub end: bool, // the host ends the party for everyone instead of leaving
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PartySocketQuery {
+    pub token: Option<String>, // browsers can't set headers on WebSocket requests
+    pub profile_id: Option<Uuid>,
+}
+
+/// Shared playback state; the live position is extrapolated from `updated_at`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaybackState {
+    pub position: f64, // in seconds, as of `updated_at`
+    pub paused: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PlaybackState {
+    /// Where playback should be at `now`.
+    pub fn position_at(&self, now: DateTime<Utc>) -> f64 {
+        if self.paused {
+            return self.position;
+        }
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        self.position + elapsed
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PartyMember {
+    pub profile_id: Uuid,
+    pub name: String,
+    pub connected: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PartyResponse {
+    pub id: Uuid,
+    pub media_id: Uuid,
+    pub host_id: Uuid,
+    pub invite_token: String,
+    pub join_url: String,
+    pub socket_url: String,
+    pub members: Vec<PartyMember>,
+    pub playback: PlaybackState,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Sent by party members over the WebSocket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PartyCommand {
+    // Host only
+    Play { position: Option<f64> },
+    Pause { position: Option<f64> },
+    Seek { position: f64 },
+    TransferHost { profile_id: Uuid },
+    // Anyone
+    Chat { text: String },
+    Position { position: f64 }, // periodic report used for drift correction
+    Ping { client_time: i64 },  // unix millis, echoed back for clock offset estimation
+}
+
+/// Sent to party members over the WebSocket.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PartyEvent {
+    Welcome {
+        party: PartyResponse,
+        server_time: i64, // unix millis
+    },
+    Playback {
+        playback: PlaybackState,
+        by: Uuid,
+        server_time: i64,
+    },
+    // Only sent to the member that drifted
+    Correct {
+        position: f64,
+        drift: f64, // in seconds; positive when the member is ahead
+        server_time: i64,
+    },
+    Chat {
+        profile_id: Uuid,
+        name: String,
+        text: String,
+        sent_at: DateTime<Utc>,
+    },
+    Pong {
+        client_time: i64,
+        server_time: i64,
+    },
+    MemberJoined {
+        member: PartyMember,
+    },
+    MemberLeft {
+        profile_id: Uuid,
+    },
+    HostChanged {
+        host_id: Uuid,
+    },
+    Ended,
+    Error {
+        message: String,
+    },
+}
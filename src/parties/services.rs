@@ -0,0 +1,663 @@
+use crate::{
+    auth::{models::Claims, services::authorize_profile},
+    errors::{AppError, Result},
+    media::services::progress::ProgressTracker,
+    parties::models::{
+        CreatePartyRequest, PartyCommand, PartyEvent, PartyMember, PartyResponse, PlaybackState,
+    },
+    state::AppState,
+};
+use axum::extract::{
+    State,
+    ws::{Message, WebSocket},
+};
+use chrono::{DateTime, Utc};
+use entity::{media, profile};
+use futures::{SinkExt, StreamExt};
+use sea_orm::EntityTrait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const DRIFT_TOLERANCE: f64 = 0.5; // in seconds; members further off are corrected
+const HISTORY_INTERVAL: Duration = Duration::from_secs(10); // between history writes per member
+const IDLE_PARTY_TIMEOUT: chrono::Duration = chrono::Duration::hours(1); // for parties nobody is connected to
+const MAX_CHAT_LENGTH: usize = 2000;
+const EVENT_BUFFER: usize = 64;
+
+struct Member {
+    profile_id: Uuid,
+    account_id: Uuid, // the household owner's profile
+    name: String,
+    connections: usize,
+    last_saved: Option<Instant>,
+}
+
+impl Member {
+    fn to_response(&self) -> PartyMember {
+        PartyMember {
+            profile_id: self.profile_id,
+            name: self.name.clone(),
+            connected: self.connections > 0,
+        }
+    }
+}
+
+struct Party {
+    id: Uuid,
+    invite_token: String,
+    media_id: Uuid,
+    host_id: Uuid,
+    members: Vec<Member>, // in join order, which is also the host succession order
+    playback: PlaybackState,
+    created_at: DateTime<Utc>,
+    emptied_at: Option<DateTime<Utc>>, // when the last member disconnected
+    events: broadcast::Sender<PartyEvent>,
+}
+
+impl Party {
+    fn to_response(&self) -> PartyResponse {
+        PartyResponse {
+            id: self.id,
+            media_id: self.media_id,
+            host_id: self.host_id,
+            invite_token: self.invite_token.clone(),
+            join_url: format!("/v1/parties/join/{}", self.invite_token),
+            socket_url: format!("/v1/parties/{}/ws", self.id),
+            members: self.members.iter().map(Member::to_response).collect(),
+            playback: self.playback.clone(),
+            created_at: self.created_at,
+        }
+    }
+
+    fn member_mut(&mut self, profile_id: Uuid) -> Option<&mut Member> {
+        self.members.iter_mut().find(|m| m.profile_id == profile_id)
+    }
+
+    fn is_connected(&self) -> bool {
+        self.members.iter().any(|m| m.connections > 0)
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        !self.is_connected()
+            && now - self.emptied_at.unwrap_or(self.created_at) >= IDLE_PARTY_TIMEOUT
+    }
+
+    // Broadcast errors only mean nobody is listening yet
+    fn broadcast(&self, event: PartyEvent) {
+        let _ = self.events.send(event);
+    }
+
+    // The longest-standing connected member takes over from a departed host
+    fn hand_over_host(&mut self) {
+        if self
+            .members
+            .iter()
+            .any(|m| m.profile_id == self.host_id && m.connections > 0)
+        {
+            return;
+        }
+        if let Some(next) = self.members.iter().find(|m| m.connections > 0) {
+            self.host_id = next.profile_id;
+            self.broadcast(PartyEvent::HostChanged {
+                host_id: self.host_id,
+            });
+        }
+    }
+}
+
+/// Live watch parties. Rooms only exist in memory and end an hour after the
+/// last member disconnects, so a dropped connection doesn't end the party.
+#[derive(Clone, Default)]
+pub struct PartyRegistry {
+    parties: Arc<RwLock<HashMap<Uuid, Party>>>,
+}
+
+impl PartyRegistry {
+    pub fn create(&self, media_id: Uuid, host: &profile::Model, position: f64) -> PartyResponse {
+        let now = Utc::now();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let party = Party {
+            id: Uuid::new_v4(),
+            // 122 random bits, URL safe
+            invite_token: Uuid::new_v4().simple().to_string(),
+            media_id,
+            host_id: host.id,
+            members: vec![Self::member(host)],
+            playback: PlaybackState {
+                position,
+                paused: true,
+                updated_at: now,
+            },
+            created_at: now,
+            emptied_at: None,
+            events,
+        };
+        let response = party.to_response();
+
+        let mut parties = self.parties.write().unwrap();
+        parties.retain(|_, p| !p.is_expired(now));
+        parties.insert(party.id, party);
+        response
+    }
+
+    pub fn find(&self, party_id: Uuid) -> Option<PartyResponse> {
+        let parties = self.parties.read().unwrap();
+        parties.get(&party_id).map(Party::to_response)
+    }
+
+    pub fn find_by_token(&self, token: &str) -> Option<Uuid> {
+        let parties = self.parties.read().unwrap();
+        parties
+            .values()
+            .find(|p| p.invite_token == token)
+            .map(|p| p.id)
+    }
+
+    /// Whether the caller is a member, or owns the household of one.
+    pub fn is_visible_to(&self, party_id: Uuid, account: Uuid) -> bool {
+        let parties = self.parties.read().unwrap();
+        parties.get(&party_id).is_some_and(|p| {
+            p.members
+                .iter()
+                .any(|m| m.profile_id == account || m.account_id == account)
+        })
+    }
+
+    pub fn is_member(&self, party_id: Uuid, profile_id: Uuid) -> bool {
+        let parties = self.parties.read().unwrap();
+        parties
+            .get(&party_id)
+            .is_some_and(|p| p.members.iter().any(|m| m.profile_id == profile_id))
+    }
+
+    pub fn join(&self, party_id: Uuid, profile: &profile::Model) -> Option<PartyResponse> {
+        let mut parties = self.parties.write().unwrap();
+        let party = parties.get_mut(&party_id)?;
+        if party.member_mut(profile.id).is_none() {
+            let member = Self::member(profile);
+            party.broadcast(PartyEvent::MemberJoined {
+                member: member.to_response(),
+            });
+            party.members.push(member);
+        }
+        Some(party.to_response())
+    }
+
+    /// Removes a member for good; the party ends once nobody is left.
+    pub fn leave(&self, party_id: Uuid, profile_id: Uuid) -> Option<PlaybackState> {
+        let mut parties = self.parties.write().unwrap();
+        let party = parties.get_mut(&party_id)?;
+        let index = party
+            .members
+            .iter()
+            .position(|m| m.profile_id == profile_id)?;
+        party.members.remove(index);
+        party.broadcast(PartyEvent::MemberLeft { profile_id });
+        let playback = party.playback.clone();
+
+        if party.members.is_empty() {
+            party.broadcast(PartyEvent::Ended);
+            parties.remove(&party_id);
+        } else if party.host_id == profile_id {
+            // Prefer a connected member, but someone has to hold the remote
+            party.host_id = party
+                .members
+                .iter()
+                .find(|m| m.connections > 0)
+                .unwrap_or(&party.members[0])
+                .profile_id;
+            party.broadcast(PartyEvent::HostChanged {
+                host_id: party.host_id,
+            });
+        }
+        Some(playback)
+    }
+
+    /// Ends the party for everyone.
+    pub fn end(&self, party_id: Uuid) {
+        if let Some(party) = self.parties.write().unwrap().remove(&party_id) {
+            party.broadcast(PartyEvent::Ended);
+        }
+    }
+
+    fn connect(
+        &self,
+        party_id: Uuid,
+        profile_id: Uuid,
+    ) -> Option<(broadcast::Receiver<PartyEvent>, PartyResponse)> {
+        let mut parties = self.parties.write().unwrap();
+        let party = parties.get_mut(&party_id)?;
+        let member = party.member_mut(profile_id)?;
+        member.connections += 1;
+        if member.connections == 1 {
+            let member = member.to_response();
+            party.broadcast(PartyEvent::MemberJoined { member });
+        }
+        // The first member back takes the remote if the host hasn't returned
+        if party.emptied_at.take().is_some() {
+            party.hand_over_host();
+        }
+        Some((party.events.subscribe(), party.to_response()))
+    }
+
+    fn disconnect(&self, party_id: Uuid, profile_id: Uuid) -> Option<PlaybackState> {
+        let mut parties = self.parties.write().unwrap();
+        let party = parties.get_mut(&party_id)?;
+        let member = party.member_mut(profile_id)?;
+        member.connections = member.connections.saturating_sub(1);
+        if member.connections == 0 {
+            party.broadcast(PartyEvent::MemberLeft { profile_id });
+        }
+        let now = Utc::now();
+        let playback = party.playback.clone();
+
+        if party.is_connected() {
+            party.hand_over_host();
+        } else {
+            // Kept for a while so members can reconnect, paused where they left off
+            party.emptied_at = Some(now);
+            party.playback = PlaybackState {
+                position: playback.position_at(now),
+                paused: true,
+                updated_at: now,
+            };
+        }
+        parties.retain(|_, p| !p.is_expired(now));
+        Some(playback)
+    }
+
+    fn control(
+        &self,
+        party_id: Uuid,
+        profile_id: Uuid,
+        command: &PartyCommand,
+    ) -> std::result::Result<(), String> {
+        let mut parties = self.parties.write().unwrap();
+        let party = parties.get_mut(&party_id).ok_or("party has ended")?;
+        if party.host_id != profile_id {
+            return Err("only the host can control playback".to_string());
+        }
+
+        let now = Utc::now();
+        let current = party.playback.position_at(now);
+        party.playback = match *command {
+            PartyCommand::Play { position } => PlaybackState {
+                position: position.unwrap_or(current).max(0.0),
+                paused: false,
+                updated_at: now,
+            },
+            PartyCommand::Pause { position } => PlaybackState {
+                position: position.unwrap_or(current).max(0.0),
+                paused: true,
+                updated_at: now,
+            },
+            PartyCommand::Seek { position } => PlaybackState {
+                position: position.max(0.0),
+                paused: party.playback.paused,
+                updated_at: now,
+            },
+            PartyCommand::TransferHost { profile_id: next } => {
+                let next = party
+                    .members
+                    .iter()
+                    .find(|m| m.profile_id == next && m.connections > 0)
+                    .ok_or("the new host must be a connected member")?;
+                party.host_id = next.profile_id;
+                party.broadcast(PartyEvent::HostChanged {
+                    host_id: party.host_id,
+                });
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        party.broadcast(PartyEvent::Playback {
+            playback: party.playback.clone(),
+            by: profile_id,
+            server_time: now.timestamp_millis(),
+        });
+        Ok(())
+    }
+
+    fn chat(&self, party_id: Uuid, profile_id: Uuid, text: &str) {
+        let parties = self.parties.read().unwrap();
+        let Some(party) = parties.get(&party_id) else {
+            return;
+        };
+        let Some(member) = party.members.iter().find(|m| m.profile_id == profile_id) else {
+            return;
+        };
+        party.broadcast(PartyEvent::Chat {
+            profile_id,
+            name: member.name.clone(),
+            text: text.chars().take(MAX_CHAT_LENGTH).collect(),
+            sent_at: Utc::now(),
+        });
+    }
+
+    /// Compares a member's reported position with the party's; returns a
+    /// correction when it drifted, and whether it's time to save progress.
+    fn report_position(
+        &self,
+        party_id: Uuid,
+        profile_id: Uuid,
+        position: f64,
+    ) -> (Option<PartyEvent>, bool) {
+        let mut parties = self.parties.write().unwrap();
+        let Some(party) = parties.get_mut(&party_id) else {
+            return (None, false);
+        };
+        let now = Utc::now();
+        let expected = party.playback.position_at(now);
+        let Some(member) = party.member_mut(profile_id) else {
+            return (None, false);
+        };
+
+        let save = member
+            .last_saved
+            .is_none_or(|saved| saved.elapsed() >= HISTORY_INTERVAL);
+        if save {
+            member.last_saved = Some(Instant::now());
+        }
+        let drift = position - expected;
+        let correction = (drift.abs() > DRIFT_TOLERANCE).then(|| PartyEvent::Correct {
+            position: expected,
+            drift,
+            server_time: now.timestamp_millis(),
+        });
+        (correction, save)
+    }
+
+    fn member(profile: &profile::Model) -> Member {
+        Member {
+            profile_id: profile.id,
+            account_id: profile.parent_id.unwrap_or(profile.id),
+            name: profile.name.clone(),
+            connections: 0,
+            last_saved: None,
+        }
+    }
+}
+
+pub async fn create_party(
+    State(state): State<AppState>,
+    claims: Claims,
+    req: CreatePartyRequest,
+) -> Result<PartyResponse> {
+    let host = authorize_profile(
+        State(state.clone()),
+        &claims,
+        req.profile_id.unwrap_or(claims.sub),
+    )
+    .await?;
+    media::Entity::find_by_id(req.media_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let position = match req.start_position {
+        Some(position) => position.max(0.0),
+        None => ProgressTracker::resume_position(&state, host.id, req.media_id)
+            .await?
+            .unwrap_or(0.0),
+    };
+    Ok(state.parties.create(req.media_id, &host, position))
+}
+
+pub async fn join_party(
+    State(state): State<AppState>,
+    claims: Claims,
+    token: &str,
+    profile_id: Option<Uuid>,
+) -> Result<PartyResponse> {
+    let party_id = state
+        .parties
+        .find_by_token(token)
+        .ok_or(AppError::NotFound)?;
+    let profile = authorize_profile(
+        State(state.clone()),
+        &claims,
+        profile_id.unwrap_or(claims.sub),
+    )
+    .await?;
+    state
+        .parties
+        .join(party_id, &profile)
+        .ok_or(AppError::Gone("The watch party has ended".into()))
+}
+
+pub async fn get_party(
+    State(state): State<AppState>,
+    claims: Claims,
+    party_id: Uuid,
+) -> Result<PartyResponse> {
+    if !state.parties.is_visible_to(party_id, claims.sub) {
+        return Err(AppError::NotFound);
+    }
+    state.parties.find(party_id).ok_or(AppError::NotFound)
+}
+
+/// Leaves the party, or ends it for everyone when the host leaves with `end`.
+pub async fn leave_party(
+    State(state): State<AppState>,
+    claims: Claims,
+    party_id: Uuid,
+    profile_id: Option<Uuid>,
+    end: bool,
+) -> Result<()> {
+    let profile = authorize_profile(
+        State(state.clone()),
+        &claims,
+        profile_id.unwrap_or(claims.sub),
+    )
+    .await?;
+    let party = state.parties.find(party_id).ok_or(AppError::NotFound)?;
+    if !state.parties.is_member(party_id, profile.id) {
+        return Err(AppError::NotFound);
+    }
+
+    let playback = if end {
+        if party.host_id != profile.id {
+            return Err(AppError::AuthorizationError);
+        }
+        state.parties.end(party_id);
+        party.playback
+    } else {
+        state
+            .parties
+            .leave(party_id, profile.id)
+            .ok_or(AppError::NotFound)?
+    };
+    ProgressTracker::report(
+        &state,
+        profile.id,
+        party.media_id,
+        playback.position_at(Utc::now()),
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Runs one member's party connection until it disconnects or the party ends.
+pub struct PartyConnection;
+
+impl PartyConnection {
+    pub async fn run(state: AppState, socket: WebSocket, party_id: Uuid, profile_id: Uuid) {
+        let Some((mut events, party)) = state.parties.connect(party_id, profile_id) else {
+            return;
+        };
+        let media_id = party.media_id;
+        let (mut sink, mut stream) = socket.split();
+
+        let welcome = PartyEvent::Welcome {
+            party,
+            server_time: Utc::now().timestamp_millis(),
+        };
+        if Self::send(&mut sink, &welcome).await.is_err() {
+            state.parties.disconnect(party_id, profile_id);
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(PartyEvent::Ended) => {
+                        let _ = Self::send(&mut sink, &PartyEvent::Ended).await;
+                        break;
+                    }
+                    Ok(event) => {
+                        if Self::send(&mut sink, &event).await.is_err() {
+                            break;
+                        }
+                    }
+                    // A slow client missed some events; the next playback update catches it up
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                message = stream.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                        Some(Ok(_)) => continue,
+                    };
+                    let reply = match serde_json::from_str::<PartyCommand>(&text) {
+                        Ok(command) => {
+                            Self::handle(&state, party_id, profile_id, media_id, command).await
+                        }
+                        Err(e) => Some(PartyEvent::Error {
+                            message: format!("invalid message: {}", e),
+                        }),
+                    };
+                    if let Some(reply) = reply
+                        && Self::send(&mut sink, &reply).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(playback) = state.parties.disconnect(party_id, profile_id)
+            && let Err(e) = ProgressTracker::report(
+                &state,
+                profile_id,
+                media_id,
+                playback.position_at(Utc::now()),
+                None,
+            )
+            .await
+        {
+            tracing::warn!("failed to save party progress: {}", e);
+        }
+    }
+
+    // Returns a reply meant only for this member
+    async fn handle(
+        state: &AppState,
+        party_id: Uuid,
+        profile_id: Uuid,
+        media_id: Uuid,
+        command: PartyCommand,
+    ) -> Option<PartyEvent> {
+        match command {
+            PartyCommand::Chat { text } => {
+                if !text.trim().is_empty() {
+                    state.parties.chat(party_id, profile_id, &text);
+                }
+                None
+            }
+            PartyCommand::Ping { client_time } => Some(PartyEvent::Pong {
+                client_time,
+                server_time: Utc::now().timestamp_millis(),
+            }),
+            PartyCommand::Position { position } => {
+                let (correction, save) = state
+                    .parties
+                    .report_position(party_id, profile_id, position);
+                if save
+                    && let Err(e) =
+                        ProgressTracker::report(state, profile_id, media_id, position, None).await
+                {
+                    tracing::warn!("failed to save party progress: {}", e);
+                }
+                correction
+            }
+            command => state
+                .parties
+                .control(party_id, profile_id, &command)
+                .err()
+                .map(|message| PartyEvent::Error { message }),
+        }
+    }
+
+    async fn send(
+        sink: &mut futures::stream::SplitSink<WebSocket, Message>,
+        event: &PartyEvent,
+    ) -> std::result::Result<(), ()> {
+        let text = serde_json::to_string(event).map_err(|_| ())?;
+        sink.send(Message::Text(text.into())).await.map_err(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> profile::Model {
+        profile::Model {
+            id: Uuid::new_v4(),
+            parent_id: None,
+            email: format!("{}@example.com", name),
+            password: None,
+            phone: None,
+            name: name.to_string(),
+            avatar: None,
+            pin: None,
+            use_pin: None,
+            role: "user".to_string(),
+            created_at: Default::default(),
+            updated_at: Default::default(),
+        }
+    }
+
+    #[test]
+    fn a_party_outlives_its_last_connection() {
+        let registry = PartyRegistry::default();
+        let (host, guest) = (profile("host"), profile("guest"));
+        let party = registry.create(Uuid::new_v4(), &host, 0.0).id;
+        registry.join(party, &guest);
+
+        registry.connect(party, host.id);
+        registry.disconnect(party, host.id);
+        let idle = registry.find(party).unwrap();
+        assert!(idle.playback.paused);
+
+        // Whoever comes back first takes the remote
+        registry.connect(party, guest.id);
+        assert_eq!(registry.find(party).unwrap().host_id, guest.id);
+    }
+
+    #[test]
+    fn idle_parties_end_after_the_timeout() {
+        let registry = PartyRegistry::default();
+        let host = profile("host");
+        let party = registry.create(Uuid::new_v4(), &host, 0.0).id;
+        registry.connect(party, host.id);
+        registry.disconnect(party, host.id);
+
+        registry
+            .parties
+            .write()
+            .unwrap()
+            .get_mut(&party)
+            .unwrap()
+            .emptied_at = Some(Utc::now() - IDLE_PARTY_TIMEOUT);
+        registry.create(Uuid::new_v4(), &host, 0.0);
+        assert!(registry.find(party).is_none());
+    }
+}
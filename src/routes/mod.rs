@@ -3,9 +3,31 @@ use crate::{
         forgot_password_handler, forgot_pin_handler, login_handler, register_handler,
         reset_password_handler, reset_pin_handler,
     },
+    media::handlers::{
+        cancel_scan_handler, chunk_manifest_handler, continue_watching_handler, cover_art_handler,
+        get_library_rules_handler, get_scan_handler, lan_discovery_handler,
+        list_library_media_handler, list_scans_handler, list_sessions_handler,
+        mark_unwatched_handler, mark_watched_handler, p2p_signal_handler, report_progress_handler,
+        scan_events_handler, session_heartbeat_handler, start_scan_handler, stop_session_handler,
+        stop_transcode_handler, stream_file_handler, stream_handler, stream_swarm_handler,
+        throughput_handler, transcode_handler, transcode_progress_handler,
+        update_library_rules_handler,
+    },
+    music::handlers::{album_tracks_handler, artist_albums_handler, list_artists_handler},
+    parties::handlers::{
+        create_party_handler, get_party_handler, join_party_handler, leave_party_handler,
+        party_socket_handler,
+    },
+    shares::handlers::{
+        create_share_handler, list_shares_handler, public_share_handler,
+        public_share_stream_handler, revoke_share_handler, share_access_log_handler,
+    },
     state::AppState,
 };
-use axum::{Router, routing::post};
+use axum::{
+    Router,
+    routing::{delete, get, post},
+};
 
 pub fn auth_routes(state: AppState) -> Router {
     Router::new()
@@ -17,3 +39,92 @@ pub fn auth_routes(state: AppState) -> Router {
         .route("/reset-pin", post(reset_pin_handler))
         .with_state(state)
 }
+
+pub fn media_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/stream", post(stream_handler))
+        .route("/sessions", get(list_sessions_handler))
+        .route("/sessions/{session_id}", delete(stop_session_handler))
+        .route(
+            "/sessions/{session_id}/heartbeat",
+            post(session_heartbeat_handler),
+        )
+        .route("/continue-watching", get(continue_watching_handler))
+        .route("/p2p/signal", get(p2p_signal_handler))
+        .route("/p2p/lan", get(lan_discovery_handler))
+        .route(
+            "/libraries/{library_id}/media",
+            get(list_library_media_handler),
+        )
+        .route("/{media_id}/file", get(stream_file_handler))
+        .route("/{media_id}/swarm", get(stream_swarm_handler))
+        .route("/{media_id}/chunks", get(chunk_manifest_handler))
+        .route("/{media_id}/cover", get(cover_art_handler))
+        .route("/{media_id}/progress", post(report_progress_handler))
+        .route(
+            "/{media_id}/watched",
+            post(mark_watched_handler).delete(mark_unwatched_handler),
+        )
+        .route("/{media_id}/transcode", get(transcode_handler))
+        .route(
+            "/transcode/{session_id}",
+            get(transcode_progress_handler).delete(stop_transcode_handler),
+        )
+        .with_state(state)
+}
+
+pub fn share_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/", post(create_share_handler).get(list_shares_handler))
+        .route("/{share_id}", delete(revoke_share_handler))
+        .route("/{share_id}/access", get(share_access_log_handler))
+        .with_state(state)
+}
+
+pub fn music_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/artists", get(list_artists_handler))
+        .route("/artists/{artist_id}/albums", get(artist_albums_handler))
+        .route("/albums/{album_id}/tracks", get(album_tracks_handler))
+        .with_state(state)
+}
+
+pub fn party_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/", post(create_party_handler))
+        .route("/join/{token}", post(join_party_handler))
+        .route(
+            "/{party_id}",
+            get(get_party_handler).delete(leave_party_handler),
+        )
+        .route("/{party_id}/ws", get(party_socket_handler))
+        .with_state(state)
+}
+
+// Routes reachable without an account, authorized by the share token alone
+pub fn public_share_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/{token}", get(public_share_handler))
+        .route(
+            "/{token}/media/{media_id}",
+            get(public_share_stream_handler),
+        )
+        .with_state(state)
+}
+
+pub fn admin_routes(state: AppState) -> Router {
+    Router::new()
+        .route("/throughput", get(throughput_handler))
+        .route("/libraries/{library_id}/scan", post(start_scan_handler))
+        .route(
+            "/libraries/{library_id}/rules",
+            get(get_library_rules_handler).put(update_library_rules_handler),
+        )
+        .route("/scans", get(list_scans_handler))
+        .route(
+            "/scans/{job_id}",
+            get(get_scan_handler).delete(cancel_scan_handler),
+        )
+        .route("/scans/{job_id}/events", get(scan_events_handler))
+        .with_state(state)
+}
@@ -0,0 +1,186 @@
+use crate::{
+    auth::{models::Claims, services::ROLE_GUEST},
+    errors::{AppError, Result},
+    media::services::{http_fallback::HttpStreamer, throttle::StreamContext},
+    shares::{
+        models::{
+            CreateShareRequest, PublicShareResponse, ShareAccessEntry, ShareAccessQuery,
+            ShareResponse, SharedItem,
+        },
+        services::{
+            consume_use, create_share, grant_url, list_shares, log_access, open_granted_share,
+            open_share, revoke_share, share_access_log, shared_item, shared_items,
+        },
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Redirect},
+};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+const SHARE_PASSWORD_HEADER: &str = "x-share-password";
+
+// Handler for creating a share link to a media item or library
+pub async fn create_share_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<CreateShareRequest>,
+) -> Result<impl IntoResponse> {
+    let share = create_share(State(state), claims, req).await?;
+    Ok::<_, AppError>((StatusCode::CREATED, Json(ShareResponse::from(share))).into_response())
+}
+
+// Handler for listing the caller's share links
+pub async fn list_shares_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<impl IntoResponse> {
+    let shares: Vec<ShareResponse> = list_shares(State(state), claims)
+        .await?
+        .into_iter()
+        .map(ShareResponse::from)
+        .collect();
+    Ok::<_, AppError>((StatusCode::OK, Json(shares)).into_response())
+}
+
+// Handler for revoking a share link
+pub async fn revoke_share_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(share_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let share = revoke_share(State(state), claims, share_id).await?;
+    Ok::<_, AppError>((StatusCode::OK, Json(ShareResponse::from(share))).into_response())
+}
+
+// Handler for the access log of a share link
+pub async fn share_access_log_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(share_id): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    let entries: Vec<ShareAccessEntry> = share_access_log(State(state), claims, share_id)
+        .await?
+        .into_iter()
+        .map(ShareAccessEntry::from)
+        .collect();
+    Ok::<_, AppError>((StatusCode::OK, Json(entries)).into_response())
+}
+
+// Handler for viewing a public share without logging in
+pub async fn public_share_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let password = share_password(&headers);
+    let share = open_share(State(state.clone()), &token, password.as_deref()).await?;
+    let items = shared_items(State(state.clone()), &share).await?;
+
+    log_access(
+        State(state),
+        share.id,
+        None,
+        "view",
+        Some(addr.ip().to_string()),
+        user_agent(&headers),
+    )
+    .await?;
+
+    let response = PublicShareResponse {
+        expires_at: share.expires_at,
+        remaining_uses: share.max_uses.map(|max| (max - share.use_count).max(0)),
+        items: items
+            .into_iter()
+            .map(|media| SharedItem {
+                url: format!("/v1/share/{}/media/{}", share.token, media.id),
+                id: media.id,
+                title: media.title,
+                media_type: media.media_type,
+            })
+            .collect(),
+    };
+    Ok::<_, AppError>((StatusCode::OK, Json(response)).into_response())
+}
+
+// Handler for playing or downloading a shared media item. Requests without a
+// grant start a play: they count a use and are redirected to a granted URL
+// that the rest of the play's range requests go through
+pub async fn public_share_stream_handler(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((token, media_id)): Path<(String, Uuid)>,
+    Query(query): Query<ShareAccessQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let (Some(expires), Some(grant)) = (query.expires, query.grant.as_deref()) else {
+        let password = share_password(&headers);
+        let share = open_share(State(state.clone()), &token, password.as_deref()).await?;
+        let media = shared_item(State(state.clone()), &share, media_id).await?;
+        consume_use(State(state.clone()), &share).await?;
+        let action = if query.download { "download" } else { "play" };
+        log_access(
+            State(state.clone()),
+            share.id,
+            Some(media.id),
+            action,
+            Some(addr.ip().to_string()),
+            user_agent(&headers),
+        )
+        .await?;
+        let url = grant_url(&state, &share, media.id, query.download);
+        return Ok::<_, AppError>(Redirect::temporary(&url).into_response());
+    };
+    let share = open_granted_share(State(state.clone()), &token, media_id, expires, grant).await?;
+    let media = shared_item(State(state.clone()), &share, media_id).await?;
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    // Guests stream under the guest limits and count against the owner's profile
+    let context = StreamContext {
+        session_id: None,
+        profile_id: share.owner_id,
+        media_id: media.id,
+        role: ROLE_GUEST.to_string(),
+        client_ip: Some(addr.ip()),
+    };
+    let mut response = HttpStreamer::stream_file(state, media.id, range, context)
+        .await?
+        .into_response();
+    if query.download {
+        let file_name = media.file_path.rsplit('/').next().unwrap_or(&media.title);
+        let disposition = format!(
+            "attachment; filename=\"{}\"",
+            file_name.replace(['"', '\\'], "_")
+        );
+        if let Ok(value) = disposition.parse() {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+    Ok::<_, AppError>(response)
+}
+
+// Only ever sent as a header, so passwords stay out of URLs, logs and history
+fn share_password(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(SHARE_PASSWORD_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
@@ -0,0 +1,91 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    pub media_id: Option<Uuid>,   // share a single media item...
+    pub library_id: Option<Uuid>, // ...or a whole library/album
+    pub expires_at: Option<DateTime<Utc>>,
+    pub password: Option<String>,
+    pub max_uses: Option<i32>, // plays + downloads
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    pub id: Uuid,
+    pub token: String,
+    pub url: String,
+    pub media_id: Option<Uuid>,
+    pub library_id: Option<Uuid>,
+    pub has_password: bool,
+    pub expires_at: Option<NaiveDateTime>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub revoked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<entity::share_link::Model> for ShareResponse {
+    fn from(share: entity::share_link::Model) -> Self {
+        ShareResponse {
+            id: share.id,
+            url: format!("/v1/share/{}", share.token),
+            token: share.token,
+            media_id: share.media_id,
+            library_id: share.library_id,
+            has_password: share.password_hash.is_some(),
+            expires_at: share.expires_at,
+            max_uses: share.max_uses,
+            use_count: share.use_count,
+            revoked: share.revoked_at.is_some(),
+            created_at: share.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareAccessEntry {
+    pub id: Uuid,
+    pub media_id: Option<Uuid>,
+    pub action: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub accessed_at: NaiveDateTime,
+}
+
+impl From<entity::share_link_access::Model> for ShareAccessEntry {
+    fn from(entry: entity::share_link_access::Model) -> Self {
+        ShareAccessEntry {
+            id: entry.id,
+            media_id: entry.media_id,
+            action: entry.action,
+            ip_address: entry.ip_address,
+            user_agent: entry.user_agent,
+            accessed_at: entry.accessed_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharedItem {
+    pub id: Uuid,
+    pub title: String,
+    pub media_type: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicShareResponse {
+    pub expires_at: Option<NaiveDateTime>,
+    pub remaining_uses: Option<i32>,
+    pub items: Vec<SharedItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareAccessQuery {
+    #[serde(default)]
+    pub download: bool,
+    pub expires: Option<i64>,  // with `grant`, unix timestamp
+    pub grant: Option<String>, // issued when a play or download starts
+}
@@ -0,0 +1,310 @@
+use crate::{
+    auth::{models::Claims, services::authorize_profile},
+    errors::{AppError, Result},
+    shares::models::CreateShareRequest,
+    state::AppState,
+};
+use axum::extract::State;
+use bcrypt::{hash, verify};
+use chrono::Utc;
+use entity::{
+    library, media,
+    share_link::{self, ActiveModel, Column as ShareColumn, Entity as ShareEntity},
+    share_link_access,
+};
+use hmac::{Hmac, Mac};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder, Set,
+    sea_query::{Condition, Expr},
+};
+use sha2::Sha256;
+use uuid::Uuid;
+
+pub async fn create_share(
+    State(state): State<AppState>,
+    claims: Claims,
+    req: CreateShareRequest,
+) -> Result<share_link::Model> {
+    // The same check a stream of the item goes through
+    let owner = authorize_profile(State(state.clone()), &claims, claims.sub).await?;
+    let db = &state.conn;
+
+    match (req.media_id, req.library_id) {
+        (Some(media_id), None) => {
+            media::Entity::find_by_id(media_id)
+                .one(db)
+                .await?
+                .ok_or(AppError::NotFound)?;
+        }
+        (None, Some(library_id)) => {
+            library::Entity::find_by_id(library_id)
+                .one(db)
+                .await?
+                .ok_or(AppError::NotFound)?;
+        }
+        _ => {
+            return Err(AppError::ValidationError(
+                "Share exactly one of media_id or library_id".into(),
+            ));
+        }
+    }
+
+    if req.max_uses.is_some_and(|max| max < 1) {
+        return Err(AppError::ValidationError(
+            "max_uses must be at least 1".into(),
+        ));
+    }
+
+    let password_hash = match req.password.filter(|p| !p.is_empty()) {
+        Some(password) => Some(
+            hash(&password, bcrypt::DEFAULT_COST)
+                .map_err(|_e| AppError::InternalServerError("Password hashing failed".into()))?,
+        ),
+        None => None,
+    };
+
+    let share = ActiveModel {
+        id: Set(Uuid::new_v4()),
+        // 122 random bits, URL safe
+        token: Set(Uuid::new_v4().simple().to_string()),
+        owner_id: Set(owner.id),
+        media_id: Set(req.media_id),
+        library_id: Set(req.library_id),
+        password_hash: Set(password_hash),
+        expires_at: Set(req.expires_at.map(|at| at.naive_utc())),
+        max_uses: Set(req.max_uses),
+        use_count: Set(0),
+        revoked_at: Set(None),
+        created_at: Set(Utc::now().naive_utc()),
+    };
+
+    Ok(share.insert(db).await?)
+}
+
+pub async fn list_shares(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Vec<share_link::Model>> {
+    Ok(ShareEntity::find()
+        .filter(ShareColumn::OwnerId.eq(claims.sub))
+        .order_by_desc(ShareColumn::CreatedAt)
+        .all(&state.conn)
+        .await?)
+}
+
+// Shares can be managed by their owner and the owner's household account
+pub async fn find_owned_share(
+    State(state): State<AppState>,
+    claims: &Claims,
+    share_id: Uuid,
+) -> Result<share_link::Model> {
+    let share = ShareEntity::find_by_id(share_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    authorize_profile(State(state), claims, share.owner_id).await?;
+    Ok(share)
+}
+
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    claims: Claims,
+    share_id: Uuid,
+) -> Result<share_link::Model> {
+    let share = find_owned_share(State(state.clone()), &claims, share_id).await?;
+    if share.revoked_at.is_some() {
+        return Ok(share);
+    }
+
+    let mut share = share.into_active_model();
+    share.revoked_at = Set(Some(Utc::now().naive_utc()));
+    Ok(share.update(&state.conn).await?)
+}
+
+pub async fn share_access_log(
+    State(state): State<AppState>,
+    claims: Claims,
+    share_id: Uuid,
+) -> Result<Vec<share_link_access::Model>> {
+    let share = find_owned_share(State(state.clone()), &claims, share_id).await?;
+    Ok(share_link_access::Entity::find()
+        .filter(share_link_access::Column::ShareLinkId.eq(share.id))
+        .order_by_desc(share_link_access::Column::AccessedAt)
+        .all(&state.conn)
+        .await?)
+}
+
+/// Resolves a public token into a share that may currently be used.
+pub async fn open_share(
+    State(state): State<AppState>,
+    token: &str,
+    password: Option<&str>,
+) -> Result<share_link::Model> {
+    let share = find_live_share(&state, token).await?;
+    if share.max_uses.is_some_and(|max| share.use_count >= max) {
+        return Err(AppError::Gone("This share link has been used up".into()));
+    }
+
+    if let Some(password_hash) = &share.password_hash {
+        let password = password.ok_or(AppError::AuthenticationError)?;
+        if !verify(password, password_hash)
+            .map_err(|_e| AppError::InternalServerError("Password verification failed".into()))?
+        {
+            return Err(AppError::AuthenticationError);
+        }
+    }
+
+    Ok(share)
+}
+
+/// Resolves a token for a stream that already holds a playback grant. The use
+/// was counted when the grant was issued, so a used-up share still streams;
+/// revoking or expiring it doesn't.
+pub async fn open_granted_share(
+    State(state): State<AppState>,
+    token: &str,
+    media_id: Uuid,
+    expires: i64,
+    grant: &str,
+) -> Result<share_link::Model> {
+    let share = find_live_share(&state, token).await?;
+    if expires < Utc::now().timestamp() {
+        return Err(AppError::AuthenticationError);
+    }
+    let grant = hex::decode(grant).map_err(|_| AppError::AuthenticationError)?;
+    grant_mac(&state, &share, media_id, expires)
+        .verify_slice(&grant)
+        .map_err(|_| AppError::AuthenticationError)?;
+    Ok(share)
+}
+
+/// The stream URL for one play or download of a shared item. Its range
+/// requests carry the grant, so seeking doesn't count as another use.
+pub fn grant_url(
+    state: &AppState,
+    share: &share_link::Model,
+    media_id: Uuid,
+    download: bool,
+) -> String {
+    let expires = Utc::now().timestamp() + state.stream_url_ttl;
+    let grant = hex::encode(
+        grant_mac(state, share, media_id, expires)
+            .finalize()
+            .into_bytes(),
+    );
+    let mut url = format!(
+        "/v1/share/{}/media/{}?expires={}&grant={}",
+        share.token, media_id, expires, grant
+    );
+    if download {
+        url.push_str("&download=true");
+    }
+    url
+}
+
+async fn find_live_share(state: &AppState, token: &str) -> Result<share_link::Model> {
+    let share = ShareEntity::find()
+        .filter(ShareColumn::Token.eq(token))
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if share.revoked_at.is_some() {
+        return Err(AppError::Gone("This share link has been revoked".into()));
+    }
+    if share
+        .expires_at
+        .is_some_and(|at| at <= Utc::now().naive_utc())
+    {
+        return Err(AppError::Gone("This share link has expired".into()));
+    }
+    Ok(share)
+}
+
+// Grants are scoped to one share and item
+fn grant_mac(
+    state: &AppState,
+    share: &share_link::Model,
+    media_id: Uuid,
+    expires: i64,
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(state.stream_url_secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("share:{}:{}:{}", share.id, media_id, expires).as_bytes());
+    mac
+}
+
+/// Counts a play or download against the share's limit.
+pub async fn consume_use(State(state): State<AppState>, share: &share_link::Model) -> Result<()> {
+    // A single conditional update so concurrent requests can't overshoot the limit
+    let result = ShareEntity::update_many()
+        .col_expr(
+            ShareColumn::UseCount,
+            Expr::col(ShareColumn::UseCount).add(1),
+        )
+        .filter(ShareColumn::Id.eq(share.id))
+        .filter(
+            Condition::any()
+                .add(ShareColumn::MaxUses.is_null())
+                .add(Expr::col(ShareColumn::UseCount).lt(Expr::col(ShareColumn::MaxUses))),
+        )
+        .exec(&state.conn)
+        .await?;
+
+    if result.rows_affected == 0 {
+        return Err(AppError::Gone("This share link has been used up".into()));
+    }
+    Ok(())
+}
+
+pub async fn shared_items(
+    State(state): State<AppState>,
+    share: &share_link::Model,
+) -> Result<Vec<media::Model>> {
+    let mut query = media::Entity::find().order_by_asc(media::Column::Title);
+    query = match (share.media_id, share.library_id) {
+        (Some(media_id), _) => query.filter(media::Column::Id.eq(media_id)),
+        (None, Some(library_id)) => query.filter(media::Column::LibraryId.eq(library_id)),
+        (None, None) => return Ok(vec![]),
+    };
+    Ok(query.all(&state.conn).await?)
+}
+
+pub async fn shared_item(
+    State(state): State<AppState>,
+    share: &share_link::Model,
+    media_id: Uuid,
+) -> Result<media::Model> {
+    let media = media::Entity::find_by_id(media_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let included = share.media_id == Some(media.id) || share.library_id == Some(media.library_id);
+    if !included {
+        return Err(AppError::NotFound);
+    }
+    Ok(media)
+}
+
+pub async fn log_access(
+    State(state): State<AppState>,
+    share_id: Uuid,
+    media_id: Option<Uuid>,
+    action: &str,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+) -> Result<()> {
+    share_link_access::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        share_link_id: Set(share_id),
+        media_id: Set(media_id),
+        action: Set(action.to_string()),
+        ip_address: Set(ip_address),
+        user_agent: Set(user_agent),
+        accessed_at: Set(Utc::now().naive_utc()),
+    }
+    .insert(&state.conn)
+    .await?;
+    Ok(())
+}
@@ -1,5 +1,21 @@
+use chrono::Duration;
 use sea_orm::DatabaseConnection;
-use std::env;
+use std::{env, path::PathBuf};
+
+use crate::{
+    media::services::{
+        discovery::LanDirectory,
+        ice::IceConfig,
+        metadata::MediaTypes,
+        p2p::SignalingHub,
+        scan_jobs::ScanJobs,
+        sessions::SessionRegistry,
+        swarm::SwarmChannels,
+        throttle::{BandwidthManager, BandwidthPolicy},
+        transcoder::TranscodeManager,
+    },
+    parties::services::PartyRegistry,
+};
 
 #[derive(Clone)]
 pub struct AppState {
@@ -9,6 +25,24 @@ pub struct AppState {
     pub allow_register: bool,
     pub allow_anonymous: bool,
     pub allow_peer_to_peer: bool,
+    pub transcoder: TranscodeManager,
+    pub sessions: SessionRegistry,
+    pub watched_threshold: f64, // percent of the duration after which an item counts as watched
+    pub resume_threshold: f64,  // percent of the duration below which playback isn't resumed
+    pub stream_url_secret: String,
+    pub stream_url_ttl: i64, // in seconds
+    pub stream_url_bind_ip: bool,
+    pub bandwidth: BandwidthManager,
+    pub p2p: SignalingHub,
+    pub swarm: SwarmChannels,   // data channels of relayed sessions
+    pub peer_timeout: Duration, // peers without a heartbeat for this long are reaped
+    pub lan: LanDirectory,
+    pub parties: PartyRegistry,
+    pub scans: ScanJobs,
+    pub scan_workers: usize,    // files read and hashed at once during a scan
+    pub scan_batch_size: usize, // rows written per transaction during a scan
+    pub media_types: MediaTypes,
+    pub cover_art_dir: PathBuf, // embedded covers, cached by hash; rebuilt on demand
 }
 
 impl AppState {
@@ -22,6 +56,60 @@ impl AppState {
             env::var("ALLOW_ANONYMOUS").unwrap_or_else(|_| "false".to_string()) == "true";
         let allow_peer_to_peer =
             env::var("ALLOW_PEER_TO_PEER").unwrap_or_else(|_| "false".to_string()) == "true";
+        let ffmpeg_path = env::var("FFMPEG_PATH").unwrap_or_else(|_| "ffmpeg".to_string());
+        let max_transcodes = env::var("MAX_CONCURRENT_TRANSCODES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        let max_streams = env::var("MAX_STREAMS_PER_ACCOUNT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let session_timeout = env::var("STREAM_SESSION_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(90);
+        let watched_threshold = env::var("WATCHED_THRESHOLD_PERCENT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(90.0);
+        let resume_threshold = env::var("RESUME_THRESHOLD_PERCENT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2.0);
+        let stream_url_secret =
+            env::var("STREAM_URL_SECRET").unwrap_or_else(|_| jwt_secret.clone());
+        let stream_url_ttl = env::var("STREAM_URL_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4 * 60 * 60);
+        let peer_timeout = env::var("PEER_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(120);
+        let peer_ban_threshold = env::var("PEER_BAN_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        let peer_ban_secs = env::var("PEER_BAN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(24 * 60 * 60);
+        let scan_workers = env::var("SCAN_WORKERS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4)
+            .max(1);
+        let scan_batch_size = env::var("SCAN_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100)
+            .max(1);
+        let stream_url_bind_ip =
+            env::var("STREAM_URL_BIND_IP").unwrap_or_else(|_| "false".to_string()) == "true";
+        let cover_art_dir = env::var("COVER_ART_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| env::temp_dir().join("smartinis-covers"));
 
         AppState {
             conn,
@@ -30,6 +118,42 @@ impl AppState {
             allow_register,
             allow_anonymous,
             allow_peer_to_peer,
+            transcoder: TranscodeManager::new(ffmpeg_path, max_transcodes),
+            sessions: SessionRegistry::new(max_streams, Duration::seconds(session_timeout)),
+            watched_threshold,
+            resume_threshold,
+            stream_url_secret,
+            stream_url_ttl,
+            stream_url_bind_ip,
+            bandwidth: BandwidthManager::new(BandwidthPolicy::from_env()),
+            p2p: SignalingHub::new(
+                peer_ban_threshold,
+                std::time::Duration::from_secs(peer_ban_secs),
+                IceConfig::from_env(),
+            ),
+            swarm: SwarmChannels::default(),
+            peer_timeout: Duration::seconds(peer_timeout),
+            lan: LanDirectory::default(),
+            parties: PartyRegistry::default(),
+            scans: ScanJobs::default(),
+            scan_workers,
+            scan_batch_size,
+            media_types: MediaTypes::from_env(),
+            cover_art_dir,
         }
     }
 }
+
+#[cfg(test)]
+impl AppState {
+    /// State without a database, with fixed secrets and defaults, for tests of
+    /// services that only read configuration.
+    pub fn for_tests() -> Self {
+        let mut state = AppState::new(sea_orm::DatabaseConnection::Disconnected);
+        state.jwt_secret = "test-jwt-secret".to_string();
+        state.stream_url_secret = "test-stream-secret".to_string();
+        state.stream_url_ttl = 60;
+        state.stream_url_bind_ip = false;
+        state
+    }
+}